@@ -0,0 +1,357 @@
+//! Keyframe animation: tracks of keyframed values that change the properties of a scene over time.
+
+use crate::ray_tracer::*;
+use crate::rgb::*;
+use crate::vector_arithmetic::*;
+
+/// How a value changes from one keyframe to the next.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// An easing curve like CSS's cubic-bezier(): a cubic Bézier curve from (0, 0) to (1, 1)
+    /// with the control points (x1, y1) and (x2, y2), where x is the time and y the progress.
+    /// (x1 and x2 have to lie within [0.0, 1.0].)
+    CubicBezier {
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+    },
+}
+
+/// A value at a given time (in seconds), and how it changes towards the next keyframe.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+    pub interpolation: Interpolation,
+}
+
+/// Values that can be interpolated between keyframes.
+pub trait Interpolate: Copy {
+    /// Interpolates between a and b, t is in [0.0, 1.0] (but may overshoot for some easing curves).
+    fn interpolate(a: &Self, b: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for Vector3 {
+    fn interpolate(a: &Vector3, b: &Vector3, t: f64) -> Vector3 {
+        return sum(&a, &scale_vector(t, &difference(&b, &a)));
+    }
+}
+
+impl Interpolate for Color {
+    fn interpolate(a: &Color, b: &Color, t: f64) -> Color {
+        return lerp_rgb(&Rgb::from_color(&a), &Rgb::from_color(&b), t).to_color();
+    }
+}
+
+/// The keyframes of a value, sorted by their time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<T> {
+    pub keyframes: Vec<Keyframe<T>>,
+}
+
+impl<T: Interpolate> Track<T> {
+    /// Computes the value at a given time, which is the first (last) keyframe's value before (after) them.
+    /// None if the track has no keyframes.
+    pub fn value_at(&self, time: f64) -> Option<T> {
+        let first = self.keyframes.first()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        for pair in self.keyframes.windows(2) {
+            let (from, to) = (&pair[0], &pair[1]);
+            if time < to.time {
+                let t = (time - from.time) / (to.time - from.time);
+                let eased = from.interpolation.ease(t);
+                return Some(T::interpolate(&from.value, &to.value, eased));
+            }
+        }
+        return self.keyframes.last().map(|keyframe| keyframe.value);
+    }
+
+    /// The time of the last keyframe (0.0 if there are none).
+    pub fn duration(&self) -> f64 {
+        return self.keyframes.last().map_or(0.0, |keyframe| keyframe.time);
+    }
+}
+
+impl Interpolation {
+    /// Maps the share of the time between two keyframes that has passed to the progress of the value.
+    pub fn ease(&self, t: f64) -> f64 {
+        return match *self {
+            Interpolation::Linear => t,
+            Interpolation::CubicBezier { x1, y1, x2, y2 } => {
+                let bezier = |a: f64, b: f64, s: f64| {
+                    return 3.0 * (1.0 - s) * (1.0 - s) * s * a
+                        + 3.0 * (1.0 - s) * s * s * b
+                        + s * s * s;
+                };
+                // x grows monotonically along the curve, find the curve parameter of our time by bisection:
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..50 {
+                    let s = 0.5 * (low + high);
+                    if bezier(x1, x2, s) < t {
+                        low = s;
+                    } else {
+                        high = s;
+                    }
+                }
+                bezier(y1, y2, 0.5 * (low + high))
+            }
+        };
+    }
+}
+
+/// A keyframed property of a scene, the indices refer to the scene's objects.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnimatedProperty {
+    SpherePosition(usize, Track<Vector3>),
+    /// The center of a cuboid.
+    CuboidPosition(usize, Track<Vector3>),
+    SphereColor(usize, Track<Color>),
+    CuboidColor(usize, Track<Color>),
+    PlaneColor(usize, Track<Color>),
+    CameraPosition(Track<Vector3>),
+    /// The camera's viewing direction, its up vector is turned along with it.
+    CameraDirection(Track<Vector3>),
+    LightPosition(Track<Vector3>),
+}
+
+/// The animated properties of a scene, rendered at a given frame rate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    pub properties: Vec<AnimatedProperty>,
+    pub frames_per_second: f64,
+}
+
+impl Animation {
+    /// The time of the last keyframe.
+    pub fn duration(&self) -> f64 {
+        return self
+            .properties
+            .iter()
+            .map(|property| match property {
+                AnimatedProperty::SpherePosition(_, track)
+                | AnimatedProperty::CuboidPosition(_, track)
+                | AnimatedProperty::CameraPosition(track)
+                | AnimatedProperty::CameraDirection(track)
+                | AnimatedProperty::LightPosition(track) => track.duration(),
+                AnimatedProperty::SphereColor(_, track)
+                | AnimatedProperty::CuboidColor(_, track)
+                | AnimatedProperty::PlaneColor(_, track) => track.duration(),
+            })
+            .fold(0.0, f64::max);
+    }
+
+    /// The number of frames from time 0.0 up to (and including) the last keyframe.
+    pub fn frame_count(&self) -> u32 {
+        return ((self.duration() * self.frames_per_second).floor() as u32).saturating_add(1);
+    }
+
+    /// The time at which a given frame starts.
+    pub fn frame_time(&self, frame: u32) -> f64 {
+        return frame as f64 / self.frames_per_second;
+    }
+
+    /// Sets the animated properties of a scene to their values at a given time. Moving objects and
+    /// the camera also get the velocity of their motion during the frame, which the shutter blurs.
+    pub fn apply(&self, scene: &mut Scene, time: f64) {
+        let frame_duration = 1.0 / self.frames_per_second;
+        let velocity =
+            |track: &Track<Vector3>, from: &Vector3| match track.value_at(time + frame_duration) {
+                Some(to) => scale_vector(1.0 / frame_duration, &difference(&to, &from)),
+                None => ZERO_VECTOR3,
+            };
+
+        for property in &self.properties {
+            match property {
+                AnimatedProperty::SpherePosition(i, track) => {
+                    if let (Some(sphere), Some(pos)) =
+                        (scene.spheres.get_mut(*i), track.value_at(time))
+                    {
+                        sphere.pos = pos;
+                        sphere.velocity = velocity(&track, &pos);
+                    }
+                }
+                AnimatedProperty::CuboidPosition(i, track) => {
+                    if let (Some(cuboid), Some(center)) =
+                        (scene.cuboids.get_mut(*i), track.value_at(time))
+                    {
+                        let half_extents = scale_vector(0.5, &difference(&cuboid.max, &cuboid.min));
+                        cuboid.min = difference(&center, &half_extents);
+                        cuboid.max = sum(&center, &half_extents);
+                        cuboid.velocity = velocity(&track, &center);
+                    }
+                }
+                AnimatedProperty::SphereColor(i, track) => {
+                    if let (Some(sphere), Some(color)) =
+                        (scene.spheres.get_mut(*i), track.value_at(time))
+                    {
+                        sphere.material.color = color;
+                    }
+                }
+                AnimatedProperty::CuboidColor(i, track) => {
+                    if let (Some(cuboid), Some(color)) =
+                        (scene.cuboids.get_mut(*i), track.value_at(time))
+                    {
+                        cuboid.material.color = color;
+                    }
+                }
+                AnimatedProperty::PlaneColor(i, track) => {
+                    if let (Some(plane), Some(color)) =
+                        (scene.planes.get_mut(*i), track.value_at(time))
+                    {
+                        plane.material.color = color;
+                    }
+                }
+                AnimatedProperty::CameraPosition(track) => {
+                    if let Some(pos) = track.value_at(time) {
+                        scene.camera.pos = pos;
+                        scene.camera.velocity = velocity(&track, &pos);
+                    }
+                }
+                AnimatedProperty::CameraDirection(track) => {
+                    if let Some(direction) = track.value_at(time).and_then(|d| try_normalize(&d)) {
+                        // keep the up vector perpendicular to the new direction:
+                        let camera = &mut scene.camera;
+                        let up = difference(
+                            &camera.up,
+                            &scale_vector(dot_product(&camera.up, &direction), &direction),
+                        );
+                        if let Some(up) = try_normalize(&up) {
+                            camera.direction = direction;
+                            camera.up = up;
+                        }
+                    }
+                }
+                AnimatedProperty::LightPosition(track) => {
+                    if let Some(pos) = track.value_at(time) {
+                        scene.light.pos = pos;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(x: f64) -> Vector3 {
+        return Vector3 { x, y: 0.0, z: 0.0 };
+    }
+
+    fn track(interpolation: Interpolation) -> Track<Vector3> {
+        return Track {
+            keyframes: vec![
+                Keyframe {
+                    time: 1.0,
+                    value: vector(10.0),
+                    interpolation,
+                },
+                Keyframe {
+                    time: 3.0,
+                    value: vector(20.0),
+                    interpolation,
+                },
+            ],
+        };
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let linear = track(Interpolation::Linear);
+        assert_eq!(linear.value_at(0.0), Some(vector(10.0)));
+        assert_eq!(linear.value_at(2.5), Some(vector(17.5)));
+        assert_eq!(linear.value_at(5.0), Some(vector(20.0)));
+        assert_eq!(linear.duration(), 3.0);
+        let empty: Track<Vector3> = Track { keyframes: vec![] };
+        assert_eq!(empty.value_at(1.0), None);
+
+        // CSS's "ease-in-out" is symmetric, slow at the ends and fast in the middle:
+        let ease_in_out = Interpolation::CubicBezier {
+            x1: 0.42,
+            y1: 0.0,
+            x2: 0.58,
+            y2: 1.0,
+        };
+        assert!((ease_in_out.ease(0.5) - 0.5).abs() < 0.000001);
+        assert!((ease_in_out.ease(0.25) + ease_in_out.ease(0.75) - 1.0).abs() < 0.000001);
+        assert!(ease_in_out.ease(0.1) < 0.05);
+        assert!(
+            ease_in_out.ease(0.0).abs() < 0.000001
+                && (ease_in_out.ease(1.0) - 1.0).abs() < 0.000001
+        );
+        let eased = track(ease_in_out).value_at(1.4).unwrap();
+        assert!(eased.x > 10.0 && eased.x < 11.0);
+
+        // ...and with control points on the diagonal, it is linear:
+        let diagonal = Interpolation::CubicBezier {
+            x1: 0.25,
+            y1: 0.25,
+            x2: 0.75,
+            y2: 0.75,
+        };
+        assert!((diagonal.ease(0.3) - 0.3).abs() < 0.000001);
+
+        let grey = Color::interpolate(
+            &Color { r: 0, g: 0, b: 0 },
+            &Color {
+                r: 200,
+                g: 100,
+                b: 0,
+            },
+            0.5,
+        );
+        assert_eq!(
+            grey,
+            Color {
+                r: 100,
+                g: 50,
+                b: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut scene = crate::create_test_scene();
+        let animation = Animation {
+            properties: vec![
+                AnimatedProperty::SpherePosition(0, track(Interpolation::Linear)),
+                AnimatedProperty::CameraDirection(Track {
+                    keyframes: vec![Keyframe {
+                        time: 0.0,
+                        value: Vector3 {
+                            x: 0.0,
+                            y: 1.0,
+                            z: 1.0,
+                        },
+                        interpolation: Interpolation::Linear,
+                    }],
+                }),
+                // (a sphere that does not exist is ignored:)
+                AnimatedProperty::SphereColor(99, Track { keyframes: vec![] }),
+            ],
+            frames_per_second: 10.0,
+        };
+        assert_eq!(animation.duration(), 3.0);
+        assert_eq!(animation.frame_count(), 31);
+        assert_eq!(animation.frame_time(15), 1.5);
+
+        animation.apply(&mut scene, 2.0);
+        assert_eq!(scene.spheres[0].pos, vector(15.0));
+        // the sphere moves 5 units per second:
+        assert!((scene.spheres[0].velocity.x - 5.0).abs() < 0.000001);
+        let camera = scene.camera;
+        assert!((length(&camera.direction) - 1.0).abs() < 0.000001);
+        assert!(dot_product(&camera.direction, &camera.up).abs() < 0.000001);
+        assert!(camera.up.y < 0.0);
+
+        // ...and stands still after the last keyframe:
+        animation.apply(&mut scene, 3.0);
+        assert_eq!(scene.spheres[0].velocity, ZERO_VECTOR3);
+    }
+}
@@ -0,0 +1,240 @@
+//! Renders the frames of lazor's test animation into numbered PNG files, or into an animated GIF or PNG.
+
+use lazor::gif::*;
+use lazor::image_io::*;
+use lazor::ray_tracer::*;
+use lazor::{create_test_animation, create_test_scene};
+use std::env;
+use std::process;
+
+const USAGE: &str = "Usage: lazor [--size WIDTHxHEIGHT] [--frames FIRST-LAST] [--fps FPS] \
+                     [--format png|gif|apng] [--dithering on|off] [--output PREFIX_OR_FILE]";
+
+/// How the frames are written.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    /// Every frame into its own PNG file.
+    Png,
+    Gif,
+    /// An animated PNG.
+    Apng,
+}
+
+/// The command line options.
+struct Options {
+    width: u32,
+    height: u32,
+    /// The first and last frame to render (None: all of them).
+    frames: Option<(u32, u32)>,
+    frames_per_second: Option<f64>,
+    format: Format,
+    /// Whether the colors of a GIF are dithered.
+    dithering: bool,
+    /// PNG frames are written to "<prefix>0000.png", "<prefix>0001.png", etc., animations into one file.
+    output: Option<String>,
+}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+
+    let mut animation = create_test_animation();
+    if let Some(fps) = options.frames_per_second {
+        animation.frames_per_second = fps;
+    }
+    let (first, last) = options
+        .frames
+        .unwrap_or((0, animation.frame_count().saturating_sub(1)));
+
+    let mut animation_frames = vec![];
+    for frame in first..=last {
+        let mut scene = create_test_scene();
+        animation.apply(&mut scene, animation.frame_time(frame));
+        let (rgba, stats) = render_rgba(&scene, options.width, options.height);
+        if stats.nan_samples > 0 || stats.inf_samples > 0 {
+            eprintln!(
+                "Encountered {} NaN and {} Inf samples while rendering frame {}!",
+                stats.nan_samples, stats.inf_samples, frame
+            );
+        }
+
+        if options.format == Format::Png {
+            let prefix = options.output.as_deref().unwrap_or("frame_");
+            let file_name = format!("{}{:04}.png", prefix, frame);
+            write_file(&file_name, encode_png(options.width, options.height, &rgba));
+        } else {
+            println!("Rendered frame {}", frame);
+            animation_frames.push(rgba);
+        }
+    }
+
+    let (width, height, fps) = (options.width, options.height, animation.frames_per_second);
+    match options.format {
+        Format::Png => {}
+        Format::Gif => write_file(
+            options.output.as_deref().unwrap_or("animation.gif"),
+            encode_gif(width, height, &animation_frames, fps, options.dithering),
+        ),
+        Format::Apng => write_file(
+            options.output.as_deref().unwrap_or("animation.png"),
+            encode_apng(width, height, &animation_frames, fps),
+        ),
+    }
+}
+
+/// Writes an encoded image into a given file, exits if that fails.
+fn write_file(file_name: &str, encoded: Result<Vec<u8>, String>) {
+    let written =
+        encoded.and_then(|bytes| std::fs::write(file_name, bytes).map_err(|e| e.to_string()));
+    match written {
+        Ok(_) => println!("Wrote {}", file_name),
+        Err(e) => {
+            eprintln!("Could not write {}: {}", file_name, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Parses the given command line arguments (without the program name).
+fn parse_options(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        width: 500,
+        height: 500,
+        frames: None,
+        frames_per_second: None,
+        format: Format::Png,
+        dithering: true,
+        output: None,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing a value for {}!", arg))?;
+        match arg.as_str() {
+            "--size" => {
+                let (width, height) = parse_pair(&value, 'x')?;
+                options.width = width;
+                options.height = height;
+            }
+            "--frames" => {
+                let (first, last) = parse_pair(&value, '-')?;
+                if first > last {
+                    return Err(format!(
+                        "The first frame comes after the last one: {}",
+                        value
+                    ));
+                }
+                options.frames = Some((first, last));
+            }
+            "--fps" => {
+                let fps: f64 = value
+                    .parse()
+                    .map_err(|_| format!("Not a frame rate: {}", value))?;
+                if !fps.is_finite() || fps <= 0.0 {
+                    return Err(format!(
+                        "The frame rate must be positive and finite: {}",
+                        value
+                    ));
+                }
+                options.frames_per_second = Some(fps);
+            }
+            "--format" => {
+                options.format = match value.as_str() {
+                    "png" => Format::Png,
+                    "gif" => Format::Gif,
+                    "apng" => Format::Apng,
+                    _ => return Err(format!("Unknown format: {}", value)),
+                }
+            }
+            "--dithering" => {
+                options.dithering = match value.as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("Expected on or off for --dithering: {}", value)),
+                }
+            }
+            "--output" => options.output = Some(value),
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+
+    if options.width == 0 || options.height == 0 {
+        return Err("The image must not be empty!".to_string());
+    }
+    return Ok(options);
+}
+
+/// Parses two numbers separated by a given character, e.g. "640x480".
+fn parse_pair(value: &str, separator: char) -> Result<(u32, u32), String> {
+    let mut parts = value.splitn(2, separator);
+    let mut next_number = || -> Result<u32, String> {
+        return parts
+            .next()
+            .and_then(|part| part.trim().parse().ok())
+            .ok_or_else(|| {
+                format!(
+                    "Expected two numbers separated by '{}': {}",
+                    separator, value
+                )
+            });
+    };
+    return Ok((next_number()?, next_number()?));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        return args.iter().map(|arg| arg.to_string()).collect();
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse_options(args(&[
+            "--size",
+            "64x48",
+            "--frames",
+            "3-7",
+            "--fps",
+            "12",
+            "--format",
+            "gif",
+            "--dithering",
+            "off",
+            "--output",
+            "out/f",
+        ]))
+        .unwrap();
+        assert_eq!((options.width, options.height), (64, 48));
+        assert_eq!(options.frames, Some((3, 7)));
+        assert_eq!(options.frames_per_second, Some(12.0));
+        assert_eq!(options.format, Format::Gif);
+        assert!(!options.dithering);
+        assert_eq!(options.output, Some("out/f".to_string()));
+
+        let defaults = parse_options(vec![]).unwrap();
+        assert_eq!(defaults.frames, None);
+        assert_eq!(defaults.format, Format::Png);
+        assert!(defaults.dithering);
+        assert_eq!(defaults.output, None);
+
+        assert!(parse_options(args(&["--size", "64"])).is_err());
+        assert!(parse_options(args(&["--size", "0x48"])).is_err());
+        assert!(parse_options(args(&["--frames", "7-3"])).is_err());
+        assert!(parse_options(args(&["--fps", "-1"])).is_err());
+        assert!(parse_options(args(&["--fps", "inf"])).is_err());
+        assert!(parse_options(args(&["--fps", "NaN"])).is_err());
+        assert!(parse_options(args(&["--fps"])).is_err());
+        assert!(parse_options(args(&["--format", "bmp"])).is_err());
+        assert!(parse_options(args(&["--dithering", "yes"])).is_err());
+        assert!(parse_options(args(&["--quality", "high"])).is_err());
+    }
+}
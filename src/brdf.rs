@@ -0,0 +1,286 @@
+//! The glTF metallic-roughness material model: a GGX (Trowbridge-Reitz) microfacet specular lobe
+//! with height-correlated Smith shadowing and Fresnel-weighted Lambertian diffuse.
+//! All directions point away from the surface.
+
+use crate::rgb::*;
+use crate::sampling::*;
+use crate::vector_arithmetic::*;
+use std::f64::consts::PI;
+
+/// The reflectance of dielectrics at normal incidence.
+const DIELECTRIC_F0: f64 = 0.04;
+
+/// The smallest roughness, perfect mirrors have a singular distribution.
+const MIN_ROUGHNESS: f64 = 0.02;
+
+/// The parameters of the metallic-roughness model, its base color is the material's (textured) diffuse color.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MetallicRoughness {
+    /// 0.0 for dielectrics, 1.0 for metals.
+    pub metallic: f64,
+    /// The perceptual roughness, the distribution's alpha is its square.
+    pub roughness: f64,
+}
+
+impl MetallicRoughness {
+    fn alpha(&self) -> f64 {
+        let roughness = self.roughness.max(MIN_ROUGHNESS).min(1.0);
+        return roughness * roughness;
+    }
+
+    /// The reflectance at normal incidence: 4% for dielectrics, the base color for metals.
+    pub fn f0(&self, base_color: &Rgb) -> Rgb {
+        let dielectric = Rgb {
+            r: DIELECTRIC_F0,
+            g: DIELECTRIC_F0,
+            b: DIELECTRIC_F0,
+        };
+        return lerp_rgb(&dielectric, &base_color, self.metallic);
+    }
+
+    /// Evaluates the BRDF for light arriving from l that is reflected towards v.
+    pub fn evaluate(&self, base_color: &Rgb, n: &Vector3, v: &Vector3, l: &Vector3) -> Rgb {
+        let n_dot_l = dot_product(&n, &l);
+        let n_dot_v = dot_product(&n, &v);
+        if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+            return BLACK_RGB;
+        }
+        let h = normalize(&sum(&v, &l));
+        let v_dot_h = dot_product(&v, &h);
+
+        let alpha = self.alpha();
+        let f = fresnel_schlick(&self.f0(&base_color), v_dot_h);
+        let specular = scale_rgb(
+            ggx_distribution(dot_product(&n, &h), alpha)
+                * smith_visibility(n_dot_l, n_dot_v, alpha),
+            &f,
+        );
+
+        // light that isn't reflected at the surface enters it, metals absorb it:
+        let diffuse_color = scale_rgb(1.0 - self.metallic, &base_color);
+        let transmitted = Rgb {
+            r: 1.0 - f.r,
+            g: 1.0 - f.g,
+            b: 1.0 - f.b,
+        };
+        let diffuse = scale_rgb(1.0 / PI, &multiply_rgb(&transmitted, &diffuse_color));
+        return add_rgb(&diffuse, &specular);
+    }
+
+    /// The probability of choosing the specular lobe when sampling.
+    fn specular_probability(&self) -> f64 {
+        return 0.5 + 0.5 * self.metallic;
+    }
+
+    /// Samples a direction l of incoming light for a given v, with probability density pdf(),
+    /// given two uniformly distributed numbers in [0.0, 1.0). None if the sample points below the surface.
+    pub fn sample(&self, n: &Vector3, v: &Vector3, u1: f64, u2: f64) -> Option<Vector3> {
+        let (tangent, bitangent) = orthonormal_basis(&n);
+        let to_world = |x: f64, y: f64, z: f64| {
+            return sum(
+                &sum(&scale_vector(x, &tangent), &scale_vector(y, &bitangent)),
+                &scale_vector(z, &n),
+            );
+        };
+
+        // reuse u1 to choose the lobe:
+        let p = self.specular_probability();
+        let l = if u1 < p {
+            // sample a microfacet normal with density D(h) (n . h) and reflect v on it:
+            let u = u1 / p;
+            let alpha = self.alpha();
+            let cos_theta = ((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u)).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * u2;
+            let h = to_world(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            difference(&scale_vector(2.0 * dot_product(&v, &h), &h), &v)
+        } else {
+            sample_cosine_hemisphere(&n, (u1 - p) / (1.0 - p), u2)
+        };
+
+        return if dot_product(&n, &l) > 0.0 {
+            Some(l)
+        } else {
+            None
+        };
+    }
+
+    /// The probability density (per solid angle) of sample() choosing l for a given v.
+    pub fn pdf(&self, n: &Vector3, v: &Vector3, l: &Vector3) -> f64 {
+        let n_dot_l = dot_product(&n, &l);
+        if n_dot_l <= 0.0 || dot_product(&n, &v) <= 0.0 {
+            return 0.0;
+        }
+        let h = normalize(&sum(&v, &l));
+        let n_dot_h = dot_product(&n, &h);
+        let specular =
+            ggx_distribution(n_dot_h, self.alpha()) * n_dot_h / (4.0 * dot_product(&v, &h));
+        let diffuse = n_dot_l / PI;
+
+        let p = self.specular_probability();
+        return p * specular + (1.0 - p) * diffuse;
+    }
+}
+
+/// The GGX normal distribution function.
+fn ggx_distribution(n_dot_h: f64, alpha: f64) -> f64 {
+    if n_dot_h <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+/// The height-correlated Smith shadowing-masking term, divided by 4 (n . l) (n . v).
+fn smith_visibility(n_dot_l: f64, n_dot_v: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let gv = n_dot_l * (n_dot_v * n_dot_v * (1.0 - a2) + a2).sqrt();
+    let gl = n_dot_v * (n_dot_l * n_dot_l * (1.0 - a2) + a2).sqrt();
+    return 0.5 / (gv + gl);
+}
+
+/// Schlick's approximation of the Fresnel reflectance.
+pub fn fresnel_schlick(f0: &Rgb, cos_theta: f64) -> Rgb {
+    let w = (1.0 - cos_theta.max(0.0).min(1.0)).powi(5);
+    return Rgb {
+        r: f0.r + (1.0 - f0.r) * w,
+        g: f0.g + (1.0 - f0.g) * w,
+        b: f0.b + (1.0 - f0.b) * w,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+    const WHITE_RGB: Rgb = Rgb {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+    };
+
+    fn direction(theta: f64, phi: f64) -> Vector3 {
+        return Vector3 {
+            x: theta.sin() * phi.cos(),
+            y: theta.sin() * phi.sin(),
+            z: theta.cos(),
+        };
+    }
+
+    /// Integrates a function over the hemisphere around N (midpoint rule).
+    fn integrate_hemisphere(f: impl Fn(&Vector3) -> f64) -> f64 {
+        let (steps_theta, steps_phi) = (300, 300);
+        let d_theta = 0.5 * PI / steps_theta as f64;
+        let d_phi = 2.0 * PI / steps_phi as f64;
+        let mut total = 0.0;
+        for i in 0..steps_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..steps_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                total += f(&direction(theta, phi)) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        return total;
+    }
+
+    #[test]
+    fn test_energy_conservation() {
+        let v = direction(0.6, 0.0);
+        for (metallic, roughness) in [(0.0, 1.0), (0.0, 0.5), (1.0, 0.5), (1.0, 0.2), (0.0, 0.2)] {
+            let material = MetallicRoughness {
+                metallic,
+                roughness,
+            };
+            // a white surface reflects at most all of the arriving light (single scattering loses some,
+            // up to half of it for very rough metals):
+            let albedo = integrate_hemisphere(|l| {
+                material.evaluate(&WHITE_RGB, &N, &v, &l).average() * dot_product(&N, &l)
+            });
+            assert!(
+                albedo > 0.7 && albedo <= 1.0,
+                "{} {} {}",
+                metallic,
+                roughness,
+                albedo
+            );
+        }
+    }
+
+    #[test]
+    fn test_specular_highlight() {
+        let gold = Rgb {
+            r: 1.0,
+            g: 0.77,
+            b: 0.34,
+        };
+        let material = MetallicRoughness {
+            metallic: 1.0,
+            roughness: 0.2,
+        };
+        let v = direction(0.5, 0.0);
+        let mirrored = direction(0.5, PI);
+        let off = direction(0.9, PI);
+        let peak = material.evaluate(&gold, &N, &v, &mirrored);
+        assert!(peak.average() > 10.0 * material.evaluate(&gold, &N, &v, &off).average());
+
+        // metals tint their highlights, dielectrics don't:
+        assert!(peak.r > peak.b);
+        let plastic = MetallicRoughness {
+            metallic: 0.0,
+            ..material
+        };
+        let f = fresnel_schlick(&plastic.f0(&gold), 1.0);
+        assert_eq!(f.r, f.b);
+    }
+
+    #[test]
+    fn test_sampling() {
+        let v = direction(0.4, 1.0);
+        let material = MetallicRoughness {
+            metallic: 0.5,
+            roughness: 0.5,
+        };
+
+        // the pdf is normalized (apart from the samples that are lost below the surface):
+        let total = integrate_hemisphere(|l| material.pdf(&N, &v, &l));
+        assert!(total > 0.9 && total <= 1.001, "{}", total);
+
+        // ...and it matches the distribution of sample(), which we check with a histogram of n . l:
+        let bins = 4;
+        let mut histogram = vec![0.0; bins];
+        let steps = 400;
+        for i in 0..steps {
+            for j in 0..steps {
+                let u1 = (i as f64 + 0.5) / steps as f64;
+                let u2 = (j as f64 + 0.5) / steps as f64;
+                if let Some(l) = material.sample(&N, &v, u1, u2) {
+                    let bin = (dot_product(&N, &l) * bins as f64) as usize;
+                    histogram[bin.min(bins - 1)] += 1.0 / (steps * steps) as f64;
+                }
+            }
+        }
+        for (bin, sampled) in histogram.iter().enumerate() {
+            let expected = integrate_hemisphere(|l| {
+                let b = (dot_product(&N, &l) * bins as f64) as usize;
+                return if b.min(bins - 1) == bin {
+                    material.pdf(&N, &v, &l)
+                } else {
+                    0.0
+                };
+            });
+            assert!(
+                (sampled - expected).abs() < 0.01,
+                "{} {} {}",
+                bin,
+                sampled,
+                expected
+            );
+        }
+    }
+}
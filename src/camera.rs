@@ -0,0 +1,579 @@
+//! The camera that the primary rays start from: a pinhole that renders everything in focus,
+//! or a thin lens whose aperture blurs everything outside of its focal plane (depth of field).
+//!
+//! Coordinate conventions: the scene's coordinate system is right-handed, a camera's right vector is
+//! its viewing direction × its up vector. Points on the image are relative to the image's center,
+//! their x axis points right, their y axis points up (unlike the canvas's rows) and their unit is
+//! the image's width, see normalize_x_y().
+
+use crate::ray_tracer::*;
+use crate::sampling::*;
+use crate::vector_arithmetic::*;
+use std::cmp;
+use std::f64::consts::PI;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub pos: Vector3,
+    /// The viewing direction, a unit vector.
+    pub direction: Vector3,
+    /// A unit vector that is perpendicular to the viewing direction.
+    pub up: Vector3,
+    pub projection: Projection,
+    /// None for a pinhole camera.
+    pub lens: Option<ThinLens>,
+    pub layout: Layout,
+    /// The distance the camera moves per unit of time (its position is the one at time 0.0).
+    pub velocity: Vector3,
+    /// None for an instantaneous exposure at time 0.0.
+    pub shutter: Option<Shutter>,
+}
+
+/// The time interval in which the camera exposes the image, objects that move within it are blurred.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+    /// The number of rays (at different times) that are averaged for each pixel.
+    pub samples: u32,
+}
+
+/// How the points of the image are mapped to the rays that start from the camera.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Rays through an image plane, the image's width spans a given horizontal field of view (in radians).
+    Perspective { fov: f64 },
+    /// Parallel rays along the viewing direction, from an image plane of a given width (in scene units).
+    Orthographic { width: f64 },
+    /// An equidistant fisheye whose image circle spans the image's width and a given angle (in radians, up to 2 PI):
+    /// the angle between a ray and the viewing direction grows linearly with the distance from the center.
+    Fisheye { fov: f64 },
+    /// A 360° panorama, its x axis is the longitude and its y axis the latitude around the up vector
+    /// (an image with an aspect ratio of 2:1 shows everything, from pole to pole).
+    Equirectangular,
+}
+
+/// The horizontal field of view of a camera with a given focal length and sensor width (e.g. 36 mm for full frame).
+pub fn fov_from_focal_length(focal_length: f64, sensor_width: f64) -> f64 {
+    return 2.0 * (0.5 * sensor_width / focal_length).atan();
+}
+
+/// How the image is divided into the views of several cameras.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Layout {
+    /// The image shows the camera's view.
+    Mono,
+    /// The views of the left and the right eye, two parallel cameras a given distance apart.
+    Stereo {
+        arrangement: StereoArrangement,
+        interocular_distance: f64,
+    },
+    /// The six 90° perspective views along the camera's axes, in a 3×2 atlas:
+    /// right, left and up in the top row, down, forward and backward in the bottom row.
+    /// (The faces are pinhole views, the lens is ignored.)
+    CubeMap,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoArrangement {
+    /// The left eye's view on the left half of the image.
+    SideBySide,
+    /// The left eye's view on the top half of the image.
+    OverUnder,
+}
+
+/// A thin lens, i.e. a disk (or polygon) that rays start from which all meet again on the focal plane.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ThinLens {
+    /// The larger the aperture, the blurrier the parts of the scene that are out of focus.
+    pub aperture_radius: f64,
+    /// The distance of the plane in focus, along the viewing direction.
+    pub focal_distance: f64,
+    pub aperture: Aperture,
+    /// The number of rays that are averaged for each pixel.
+    pub samples: u32,
+}
+
+/// The shape of the aperture, which out-of-focus highlights (bokeh) take on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aperture {
+    Circle,
+    /// A regular polygon that is formed by a given number of diaphragm blades,
+    /// rotated by an angle (in radians).
+    Blades {
+        count: u32,
+        rotation: f64,
+    },
+}
+
+impl Default for Camera {
+    /// A pinhole camera at the origin that looks along the z axis.
+    fn default() -> Camera {
+        return Camera {
+            pos: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            up: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            // an image plane at a distance of 1.0 that is 1.0 wide:
+            projection: Projection::Perspective {
+                fov: 2.0 * 0.5f64.atan(),
+            },
+            lens: None,
+            layout: Layout::Mono,
+            velocity: ZERO_VECTOR3,
+            shutter: None,
+        };
+    }
+}
+
+impl Camera {
+    /// The unit vector that points to the right of the image.
+    pub fn right(&self) -> Vector3 {
+        return cross_product(&self.direction, &self.up);
+    }
+
+    /// Computes the ray (with a unit direction, at time 0.0) through a point of the image,
+    /// or None if the projection doesn't cover the point.
+    pub fn primary_ray(&self, p: &Point) -> Option<Ray> {
+        let right = self.right();
+        let on_image_plane = |x: f64, y: f64| {
+            return sum(&scale_vector(x, &right), &scale_vector(y, &self.up));
+        };
+        return match self.projection {
+            Projection::Perspective { fov } => {
+                // the width of the image plane at a distance of 1.0:
+                let scale = 2.0 * (0.5 * fov).tan();
+                Some(Ray {
+                    origin: self.pos,
+                    direction: normalize(&sum(
+                        &on_image_plane(scale * p.x, scale * p.y),
+                        &self.direction,
+                    )),
+                    time: 0.0,
+                })
+            }
+            Projection::Orthographic { width } => Some(Ray {
+                origin: sum(&self.pos, &on_image_plane(width * p.x, width * p.y)),
+                direction: self.direction,
+                time: 0.0,
+            }),
+            Projection::Fisheye { fov } => {
+                // the image circle touches the image's edges:
+                let r = (p.x * p.x + p.y * p.y).sqrt();
+                let theta = r / 0.5 * 0.5 * fov;
+                if r > 0.5 || theta > PI {
+                    return None;
+                }
+                let towards = if r > 0.0 {
+                    on_image_plane(p.x / r, p.y / r)
+                } else {
+                    on_image_plane(0.0, 0.0)
+                };
+                Some(Ray {
+                    origin: self.pos,
+                    direction: sum(
+                        &scale_vector(theta.cos(), &self.direction),
+                        &scale_vector(theta.sin(), &towards),
+                    ),
+                    time: 0.0,
+                })
+            }
+            Projection::Equirectangular => {
+                let longitude = 2.0 * PI * p.x;
+                let latitude = 2.0 * PI * p.y;
+                if latitude.abs() > 0.5 * PI {
+                    return None;
+                }
+                let horizontal = sum(
+                    &scale_vector(longitude.cos(), &self.direction),
+                    &scale_vector(longitude.sin(), &right),
+                );
+                Some(Ray {
+                    origin: self.pos,
+                    direction: sum(
+                        &scale_vector(latitude.cos(), &horizontal),
+                        &scale_vector(latitude.sin(), &self.up),
+                    ),
+                    time: 0.0,
+                })
+            }
+        };
+    }
+
+    /// Maps a pixel to the view of the layout that it belongs to. Returns the view's camera, the pixel's position
+    /// on that view's image (see primary_ray()) and the offset to the next pixel to the right and below there.
+    pub fn view_of_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> (Camera, Point, Point) {
+        // (an image that is smaller than its layout's grid of views gives each view at least one pixel:)
+        let view_size = |columns: u32, rows: u32| {
+            return (cmp::max(width / columns, 1), cmp::max(height / rows, 1));
+        };
+        let view = |camera: Camera, column: u32, row: u32, columns: u32, rows: u32| {
+            let (view_width, view_height) = view_size(columns, rows);
+            // the last views get the pixels that are left over:
+            let column = cmp::min(column, columns - 1);
+            let row = cmp::min(row, rows - 1);
+            let p = normalize_x_y(
+                x - column * view_width,
+                y - row * view_height,
+                view_width,
+                view_height,
+            );
+            let pixel_size = Point {
+                x: 1.0 / view_width as f64,
+                y: -1.0 / view_width as f64,
+            };
+            return (camera, p, pixel_size);
+        };
+
+        return match self.layout {
+            Layout::Mono => view(*self, 0, 0, 1, 1),
+            Layout::Stereo {
+                arrangement,
+                interocular_distance,
+            } => {
+                let (columns, rows) = match arrangement {
+                    StereoArrangement::SideBySide => (2, 1),
+                    StereoArrangement::OverUnder => (1, 2),
+                };
+                let (view_width, view_height) = view_size(columns, rows);
+                let (column, row) = (x / view_width, y / view_height);
+                let eye = if column + row == 0 { -0.5 } else { 0.5 };
+                let camera = Camera {
+                    pos: sum(
+                        &self.pos,
+                        &scale_vector(eye * interocular_distance, &self.right()),
+                    ),
+                    layout: Layout::Mono,
+                    ..*self
+                };
+                view(camera, column, row, columns, rows)
+            }
+            Layout::CubeMap => {
+                let (view_width, view_height) = view_size(3, 2);
+                let (column, row) = (x / view_width, y / view_height);
+                let face = cmp::min(row, 1) * 3 + cmp::min(column, 2);
+                let right = self.right();
+                let back = scale_vector(-1.0, &self.direction);
+                let (direction, up) = match face {
+                    0 => (right, self.up),
+                    1 => (scale_vector(-1.0, &right), self.up),
+                    2 => (self.up, back),
+                    3 => (scale_vector(-1.0, &self.up), self.direction),
+                    4 => (self.direction, self.up),
+                    _ => (back, self.up),
+                };
+                let camera = Camera {
+                    direction,
+                    up,
+                    projection: Projection::Perspective { fov: 0.5 * PI },
+                    lens: None,
+                    layout: Layout::Mono,
+                    ..*self
+                };
+                view(camera, column, row, 3, 2)
+            }
+        };
+    }
+
+    /// The number of rays that are averaged for each pixel.
+    pub fn samples(&self) -> u32 {
+        let lens_samples = match self.active_lens() {
+            Some(lens) => lens.samples,
+            None => 1,
+        };
+        let shutter_samples = match &self.shutter {
+            Some(shutter) => shutter.samples,
+            None => 1,
+        };
+        return cmp::max(cmp::max(lens_samples, shutter_samples), 1);
+    }
+
+    /// Samples the time of a ray within the shutter interval, given a uniformly distributed number.
+    pub fn sample_time(&self, u: f64) -> f64 {
+        return match &self.shutter {
+            Some(shutter) => shutter.open + u * (shutter.close - shutter.open),
+            None => 0.0,
+        };
+    }
+
+    /// Moves a ray (that starts at the camera's position at time 0.0) along with the camera to a given time.
+    pub fn move_ray(&self, r: &Ray, time: f64) -> Ray {
+        return Ray {
+            origin: sum(&r.origin, &scale_vector(time, &self.velocity)),
+            time,
+            ..*r
+        };
+    }
+
+    /// The lens, unless the camera's views are pinhole views.
+    fn active_lens(&self) -> Option<&ThinLens> {
+        return match self.layout {
+            Layout::CubeMap => None,
+            _ => self.lens.as_ref(),
+        };
+    }
+
+    /// Turns a pinhole ray (that starts at the camera's position) into a ray that starts at a point
+    /// on the lens and passes through the same point on the focal plane, given two uniformly
+    /// distributed numbers that choose the point on the lens.
+    pub fn focus_ray(&self, r: &Ray, u1: f64, u2: f64) -> Ray {
+        let lens = match self.active_lens() {
+            Some(lens) if lens.aperture_radius > 0.0 => lens,
+            _ => return *r,
+        };
+        let forward = dot_product(&r.direction, &self.direction);
+        if forward <= 0.0 {
+            // the ray never reaches the focal plane:
+            return *r;
+        }
+        let focal_point = sum(
+            &r.origin,
+            &scale_vector(lens.focal_distance / forward, &r.direction),
+        );
+
+        let (x, y) = lens.sample_aperture(u1, u2);
+        let origin = sum(
+            &r.origin,
+            &sum(&scale_vector(x, &self.right()), &scale_vector(y, &self.up)),
+        );
+        return Ray {
+            origin,
+            direction: normalize(&difference(&focal_point, &origin)),
+            time: r.time,
+        };
+    }
+}
+
+impl ThinLens {
+    /// Samples a point on the aperture, relative to its center.
+    fn sample_aperture(&self, u1: f64, u2: f64) -> (f64, f64) {
+        let (x, y) = match self.aperture {
+            Aperture::Circle => sample_disk(u1, u2),
+            Aperture::Blades { count, rotation } => sample_regular_polygon(count, rotation, u1, u2),
+        };
+        return (self.aperture_radius * x, self.aperture_radius * y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(u: &Vector3, v: &Vector3) {
+        assert!(distance(&u, &v) < 0.000001, "{:?} {:?}", u, v);
+    }
+
+    #[test]
+    fn test_projections() {
+        let camera = Camera {
+            pos: Vector3 {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            ..Camera::default()
+        };
+        let center = Point { x: 0.0, y: 0.0 };
+        let corner = Point { x: -0.5, y: -0.5 };
+        let edge = Point { x: -0.5, y: 0.0 };
+        let ray = |projection: Projection, p: &Point| {
+            return Camera {
+                projection,
+                ..camera
+            }
+            .primary_ray(&p);
+        };
+
+        // every projection looks along the viewing direction in the center of the image:
+        for projection in [
+            Projection::Perspective { fov: 0.5 * PI },
+            Projection::Orthographic { width: 10.0 },
+            Projection::Fisheye { fov: PI },
+            Projection::Equirectangular,
+        ] {
+            let r = ray(projection, &center).unwrap();
+            assert_close(&r.origin, &camera.pos);
+            assert_close(&r.direction, &camera.direction);
+        }
+
+        // orthographic rays are parallel, their origins span the image plane:
+        let r = ray(Projection::Orthographic { width: 10.0 }, &corner).unwrap();
+        assert_close(&r.direction, &camera.direction);
+        assert_close(
+            &r.origin,
+            &Vector3 {
+                x: 6.0,
+                y: -3.0,
+                z: 3.0,
+            },
+        );
+
+        // a fisheye with a 180° field of view sees sideways at the edges, but nothing beyond its image circle:
+        let r = ray(Projection::Fisheye { fov: PI }, &edge).unwrap();
+        assert_close(&r.direction, &scale_vector(-1.0, &camera.right()));
+        assert_eq!(ray(Projection::Fisheye { fov: PI }, &corner), None);
+
+        // a panorama sees behind the camera at its left edge, and straight down at the bottom of a 2:1 image:
+        let r = ray(Projection::Equirectangular, &edge).unwrap();
+        assert_close(&r.direction, &scale_vector(-1.0, &camera.direction));
+        let r = ray(Projection::Equirectangular, &Point { x: 0.2, y: -0.25 }).unwrap();
+        assert_close(&r.direction, &scale_vector(-1.0, &camera.up));
+        assert_eq!(ray(Projection::Equirectangular, &corner), None);
+
+        // the perspective projection's field of view spans the image's width:
+        let fov = fov_from_focal_length(18.0, 36.0);
+        assert!((fov - 0.5 * PI).abs() < 0.000001);
+        let r = ray(Projection::Perspective { fov }, &edge).unwrap();
+        assert!((length(&r.direction) - 1.0).abs() < 0.000001);
+        assert!((dot_product(&r.direction, &camera.direction) - 0.5f64.sqrt()).abs() < 0.000001);
+    }
+
+    #[test]
+    fn test_layouts() {
+        let camera = Camera {
+            layout: Layout::Stereo {
+                arrangement: StereoArrangement::SideBySide,
+                interocular_distance: 6.0,
+            },
+            ..Camera::default()
+        };
+        // each eye sees the full image, from a slightly different position:
+        let (left, p, pixel_size) = camera.view_of_pixel(5, 5, 40, 20);
+        assert_eq!(p, normalize_x_y(5, 5, 20, 20));
+        assert_eq!(pixel_size, Point { x: 0.05, y: -0.05 });
+        let (right, q, _) = camera.view_of_pixel(25, 5, 40, 20);
+        assert_eq!(p, q);
+        assert_close(
+            &difference(&right.pos, &left.pos),
+            &scale_vector(6.0, &camera.right()),
+        );
+        assert_eq!(left.direction, camera.direction);
+
+        let over_under = Camera {
+            layout: Layout::Stereo {
+                arrangement: StereoArrangement::OverUnder,
+                interocular_distance: 6.0,
+            },
+            ..camera
+        };
+        let (bottom, q, _) = over_under.view_of_pixel(5, 25, 20, 40);
+        assert_eq!(p, q);
+        assert_eq!(bottom.pos, right.pos);
+
+        // the centers of the cube map's faces look along the camera's axes:
+        let cube_map = Camera {
+            layout: Layout::CubeMap,
+            lens: Some(ThinLens {
+                aperture_radius: 2.0,
+                focal_distance: 100.0,
+                aperture: Aperture::Circle,
+                samples: 4,
+            }),
+            ..Camera::default()
+        };
+        let right = camera.right();
+        let axes = [
+            right,
+            scale_vector(-1.0, &right),
+            camera.up,
+            scale_vector(-1.0, &camera.up),
+            camera.direction,
+            scale_vector(-1.0, &camera.direction),
+        ];
+        for (face, axis) in axes.iter().enumerate() {
+            let (x, y) = (10 * (face as u32 % 3) + 5, 10 * (face as u32 / 3) + 5);
+            let (view, p, pixel_size) = cube_map.view_of_pixel(x, y, 30, 20);
+            assert_eq!(p, normalize_x_y(5, 5, 10, 10));
+            assert_eq!(pixel_size, Point { x: 0.1, y: -0.1 });
+            let center = view.primary_ray(&Point { x: 0.0, y: 0.0 }).unwrap();
+            assert_close(&center.direction, &axis);
+
+            // ...and their edges are 45° off:
+            let edge = view.primary_ray(&Point { x: 0.5, y: 0.0 }).unwrap();
+            assert!((dot_product(&edge.direction, &axis) - 0.5f64.sqrt()).abs() < 0.000001);
+        }
+        assert_eq!(cube_map.samples(), 1);
+
+        // images that are smaller than the grid of views still map every pixel to a view:
+        let over_under = Camera {
+            layout: Layout::Stereo {
+                arrangement: StereoArrangement::OverUnder,
+                interocular_distance: 6.0,
+            },
+            ..Camera::default()
+        };
+        for layout_camera in [camera, over_under, cube_map] {
+            for (width, height) in [(1, 1), (2, 1), (1, 2), (2, 3)] {
+                for (x, y) in [(0, 0), (width - 1, height - 1)] {
+                    let (_, p, pixel_size) = layout_camera.view_of_pixel(x, y, width, height);
+                    assert!(p.x.is_finite() && p.y.is_finite());
+                    assert!(pixel_size.x.is_finite() && pixel_size.y.is_finite());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_focus_ray() {
+        let pinhole = Ray {
+            origin: Vector3 {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            direction: Vector3 {
+                x: 0.3,
+                y: -0.2,
+                z: 1.0,
+            },
+            time: 0.0,
+        };
+        let mut camera = Camera {
+            pos: pinhole.origin,
+            ..Camera::default()
+        };
+        assert_eq!(camera.focus_ray(&pinhole, 0.3, 0.7), pinhole);
+        assert_eq!(camera.samples(), 1);
+
+        for aperture in [
+            Aperture::Circle,
+            Aperture::Blades {
+                count: 5,
+                rotation: 0.2,
+            },
+        ] {
+            camera.lens = Some(ThinLens {
+                aperture_radius: 2.0,
+                focal_distance: 100.0,
+                aperture,
+                samples: 4,
+            });
+            let mut rng = Rng::new(3);
+            for _ in 0..100 {
+                let r = camera.focus_ray(&pinhole, rng.next_f64(), rng.next_f64());
+                // the ray starts on the lens...
+                let offset = difference(&r.origin, &pinhole.origin);
+                assert!(dot_product(&offset, &camera.direction).abs() < 0.000001);
+                assert!(length(&offset) <= 2.000001);
+
+                // ...and meets the pinhole ray on the focal plane:
+                let k = (100.0 - offset.z) / r.direction.z;
+                let p = sum(&r.origin, &scale_vector(k, &r.direction));
+                let expected = sum(&pinhole.origin, &scale_vector(100.0, &pinhole.direction));
+                assert!(distance(&p, &expected) < 0.000001);
+            }
+        }
+        assert_eq!(camera.samples(), 4);
+    }
+}
@@ -0,0 +1,392 @@
+//! Constructive solid geometry (CSG): solids that are combined by union, intersection and difference.
+
+use crate::material::*;
+use crate::ray_tracer::*;
+use crate::vector_arithmetic::*;
+use std::cmp::Ordering;
+
+/// A boolean operation that combines two CSG objects.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+/// A CSG object: either a primitive solid or two CSG objects combined by an operation.
+/// A plane is treated as the half-space that lies behind it (opposite to its normal).
+#[derive(PartialEq)]
+pub enum Csg {
+    Sphere(Sphere),
+    Cuboid(Cuboid),
+    Plane(Plane),
+    Node {
+        operation: CsgOperation,
+        left: Box<Csg>,
+        right: Box<Csg>,
+    },
+}
+
+/// A point where a ray crosses the surface of a CSG object.
+#[derive(Copy, Clone)]
+pub struct CsgHit<'a> {
+    pub k: f64,
+    pub geom_object: GeomPrimitive<'a>,
+    pub flip_normal: bool,
+}
+
+/// An interval along a ray that lies inside a CSG object.
+#[derive(Copy, Clone)]
+struct Span<'a> {
+    enter: CsgHit<'a>,
+    exit: CsgHit<'a>,
+}
+
+impl CsgOperation {
+    /// Decides whether a point lies inside the combined object, given whether it lies inside its operands.
+    fn contains(&self, inside_left: bool, inside_right: bool) -> bool {
+        return match self {
+            CsgOperation::Union => inside_left || inside_right,
+            CsgOperation::Intersection => inside_left && inside_right,
+            CsgOperation::Difference => inside_left && !inside_right,
+        };
+    }
+}
+
+impl Csg {
+    /// Computes the closest point (in front of the ray's origin) where a given ray enters or leaves the object.
+    pub fn closest_hit(&self, r: &Ray) -> Option<CsgHit<'_>> {
+        for span in self.spans(&r).iter() {
+            for hit in [span.enter, span.exit].iter() {
+                if hit.k >= TINY_VALUE && hit.k < HUGE_VALUE {
+                    return Some(*hit);
+                }
+            }
+        }
+        return None;
+    }
+
+    /// The solids that the object combines, from left to right.
+    pub fn primitives(&self) -> Vec<GeomPrimitive<'_>> {
+        return match self {
+            Csg::Sphere(sphere) => vec![GeomPrimitive::Sphere(sphere)],
+            Csg::Cuboid(cuboid) => vec![GeomPrimitive::Cuboid(cuboid)],
+            Csg::Plane(plane) => vec![GeomPrimitive::Plane(plane)],
+            Csg::Node { left, right, .. } => [left.primitives(), right.primitives()].concat(),
+        };
+    }
+
+    /// The materials of the solids that the object combines, from left to right.
+    pub fn materials_mut(&mut self) -> Vec<&mut Material> {
+        return match self {
+            Csg::Sphere(sphere) => vec![&mut sphere.material],
+            Csg::Cuboid(cuboid) => vec![&mut cuboid.material],
+            Csg::Plane(plane) => vec![&mut plane.material],
+            Csg::Node { left, right, .. } => {
+                let mut materials = left.materials_mut();
+                materials.extend(right.materials_mut());
+                materials
+            }
+        };
+    }
+
+    /// Finds the index of a given primitive among the solids that the object combines.
+    pub fn primitive_index(&self, primitive: &GeomPrimitive) -> Option<usize> {
+        return self.primitives().iter().position(|p| match (p, primitive) {
+            (GeomPrimitive::Sphere(a), GeomPrimitive::Sphere(b)) => std::ptr::eq(*a, *b),
+            (GeomPrimitive::Cuboid(a), GeomPrimitive::Cuboid(b)) => std::ptr::eq(*a, *b),
+            (GeomPrimitive::Plane(a), GeomPrimitive::Plane(b)) => std::ptr::eq(*a, *b),
+            _ => false,
+        });
+    }
+
+    /// Gives all solids that the object combines a given material.
+    pub fn set_material(&mut self, material: &Material) {
+        match self {
+            Csg::Sphere(sphere) => sphere.material = material.clone(),
+            Csg::Cuboid(cuboid) => cuboid.material = material.clone(),
+            Csg::Plane(plane) => plane.material = material.clone(),
+            Csg::Node { left, right, .. } => {
+                left.set_material(&material);
+                right.set_material(&material);
+            }
+        }
+    }
+
+    /// Computes the ordered, disjoint intervals in which a given ray lies inside the object.
+    fn spans(&self, r: &Ray) -> Vec<Span<'_>> {
+        return match self {
+            Csg::Sphere(sphere) => match ray_sphere_span(&r, &sphere) {
+                Some((min, max)) => vec![primitive_span(min, max, GeomPrimitive::Sphere(sphere))],
+                None => vec![],
+            },
+            Csg::Cuboid(cuboid) => match ray_cuboid_span(&r, &cuboid) {
+                Some((min, max)) => vec![primitive_span(min, max, GeomPrimitive::Cuboid(cuboid))],
+                None => vec![],
+            },
+            Csg::Plane(plane) => ray_half_space_spans(&r, &plane),
+            Csg::Node {
+                operation,
+                left,
+                right,
+            } => combine_spans(*operation, &left.spans(&r), &right.spans(&r)),
+        };
+    }
+}
+
+fn primitive_span(min: f64, max: f64, geom_object: GeomPrimitive) -> Span {
+    return Span {
+        enter: CsgHit {
+            k: min,
+            geom_object,
+            flip_normal: false,
+        },
+        exit: CsgHit {
+            k: max,
+            geom_object,
+            flip_normal: false,
+        },
+    };
+}
+
+/// Computes the interval in which a given ray lies behind a given plane.
+fn ray_half_space_spans<'a>(r: &Ray, plane: &'a Plane) -> Vec<Span<'a>> {
+    let d = dot_product(&r.direction, &plane.n);
+    let origin_side = dot_product(&r.origin, &plane.n) + plane.d;
+
+    if d == 0.0 {
+        // the ray runs parallel to the plane, it lies either completely inside or outside:
+        return if origin_side < 0.0 {
+            vec![primitive_span(
+                f64::NEG_INFINITY,
+                f64::INFINITY,
+                UNKNOWN_PRIMITIVE,
+            )]
+        } else {
+            vec![]
+        };
+    }
+
+    let k = -origin_side / d;
+    let surface = CsgHit {
+        k,
+        geom_object: GeomPrimitive::Plane(plane),
+        flip_normal: false,
+    };
+    let span = if d < 0.0 {
+        Span {
+            enter: surface,
+            exit: CsgHit {
+                k: f64::INFINITY,
+                ..surface
+            },
+        }
+    } else {
+        Span {
+            enter: CsgHit {
+                k: f64::NEG_INFINITY,
+                ..surface
+            },
+            exit: surface,
+        }
+    };
+    return vec![span];
+}
+
+const UNKNOWN_PRIMITIVE: GeomPrimitive<'static> = GeomPrimitive::UNKNOWN;
+
+/// Combines the intervals of two CSG objects by sweeping along the ray over all their surfaces.
+fn combine_spans<'a>(
+    operation: CsgOperation,
+    left: &[Span<'a>],
+    right: &[Span<'a>],
+) -> Vec<Span<'a>> {
+    // all points where the ray enters or leaves one of the operands, ordered along the ray:
+    let mut crossings: Vec<(CsgHit<'a>, bool, bool)> = Vec::new();
+    for span in left.iter() {
+        crossings.push((span.enter, true, true));
+        crossings.push((span.exit, true, false));
+    }
+    for span in right.iter() {
+        crossings.push((span.enter, false, true));
+        crossings.push((span.exit, false, false));
+    }
+    crossings.sort_by(|a, b| a.0.k.partial_cmp(&b.0.k).unwrap_or(Ordering::Equal));
+
+    let mut spans = Vec::new();
+    let mut inside_left = false;
+    let mut inside_right = false;
+    let mut enter: Option<CsgHit<'a>> = None;
+
+    for (hit, is_left, entering) in crossings.into_iter() {
+        let was_inside = operation.contains(inside_left, inside_right);
+        if is_left {
+            inside_left = entering;
+        } else {
+            inside_right = entering;
+        }
+        let is_inside = operation.contains(inside_left, inside_right);
+
+        // the surfaces of a subtracted object face the other way:
+        let hit = if !is_left && operation == CsgOperation::Difference {
+            CsgHit {
+                flip_normal: !hit.flip_normal,
+                ..hit
+            }
+        } else {
+            hit
+        };
+
+        if !was_inside && is_inside {
+            enter = Some(hit);
+        } else if was_inside && !is_inside {
+            // coinciding surfaces of the operands may leave empty intervals behind:
+            match enter.take() {
+                Some(enter_hit) if enter_hit.k < hit.k => spans.push(Span {
+                    enter: enter_hit,
+                    exit: hit,
+                }),
+                _ => (),
+            }
+        }
+    }
+
+    return spans;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_ray() -> Ray {
+        return Ray {
+            origin: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            time: 0.0,
+        };
+    }
+
+    fn test_sphere(z: f64) -> Csg {
+        return Csg::Sphere(Sphere {
+            pos: Vector3 { x: 0.0, y: 0.0, z },
+            r: 10.0,
+            velocity: ZERO_VECTOR3,
+            material: Material::new(Color { r: 255, g: 0, b: 0 }, false),
+        });
+    }
+
+    fn test_cuboid() -> Csg {
+        return Csg::Cuboid(Cuboid {
+            min: Vector3 {
+                x: -5.0,
+                y: -5.0,
+                z: 40.0,
+            },
+            max: Vector3 {
+                x: 5.0,
+                y: 5.0,
+                z: 55.0,
+            },
+            velocity: ZERO_VECTOR3,
+            material: Material::new(Color { r: 0, g: 255, b: 0 }, false),
+        });
+    }
+
+    fn combine(operation: CsgOperation, left: Csg, right: Csg) -> Csg {
+        return Csg::Node {
+            operation,
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+    }
+
+    #[test]
+    fn test_union() {
+        let csg = combine(CsgOperation::Union, test_sphere(50.0), test_sphere(65.0));
+        let spans = csg.spans(&test_ray());
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].enter.k, 40.0);
+        assert_eq!(spans[0].exit.k, 75.0);
+    }
+
+    #[test]
+    fn test_intersection() {
+        let csg = combine(
+            CsgOperation::Intersection,
+            test_sphere(50.0),
+            test_sphere(65.0),
+        );
+        let hit = csg.closest_hit(&test_ray()).unwrap();
+        assert_eq!(hit.k, 55.0);
+        assert!(!hit.flip_normal);
+    }
+
+    #[test]
+    fn test_difference() {
+        // a sphere with a box cut out of its front:
+        let csg = combine(CsgOperation::Difference, test_sphere(50.0), test_cuboid());
+        let hit = csg.closest_hit(&test_ray()).unwrap();
+        assert_eq!(hit.k, 55.0);
+        assert!(hit.flip_normal);
+        match hit.geom_object {
+            GeomPrimitive::Cuboid(_) => (),
+            _ => panic!("Expected to hit the cuboid!"),
+        }
+
+        // a ray that passes beside the box hits the sphere itself:
+        let r = Ray {
+            origin: Vector3 {
+                x: 7.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            ..test_ray()
+        };
+        let hit = csg.closest_hit(&r).unwrap();
+        assert!(hit.k > 40.0 && hit.k < 50.0);
+        assert!(!hit.flip_normal);
+    }
+
+    #[test]
+    fn test_half_space() {
+        // a sphere that is cut in half by a plane:
+        let plane = Csg::Plane(Plane {
+            n: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            d: 50.0,
+            material: Material::new(Color { r: 0, g: 0, b: 255 }, false),
+        });
+        let csg = combine(CsgOperation::Intersection, test_sphere(50.0), plane);
+        let spans = csg.spans(&test_ray());
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].enter.k, 50.0);
+        assert_eq!(spans[0].exit.k, 60.0);
+
+        let r = Ray {
+            origin: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 100.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            time: 0.0,
+        };
+        let hit = csg.closest_hit(&r).unwrap();
+        assert_eq!(hit.k, 40.0);
+    }
+}
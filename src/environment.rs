@@ -0,0 +1,304 @@
+//! Backgrounds: what rays that miss every object see, they also light the scene.
+
+use crate::image_io::*;
+use crate::rgb::*;
+use crate::sampling::*;
+use crate::texture::*;
+use crate::vector_arithmetic::*;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+/// The scene's surroundings, infinitely far away.
+#[derive(Clone)]
+pub enum Background {
+    /// The same radiance in every direction.
+    Solid(Rgb),
+    /// Blends from the radiance straight down (-y) to the radiance straight up (+y).
+    Gradient { bottom: Rgb, top: Rgb },
+    /// An equirectangular environment map.
+    Environment(Rc<EnvironmentMap>),
+}
+
+/// An equirectangular (latitude/longitude) map of the radiance arriving from every direction,
+/// laid out like the texture coordinates of spherical_uv(), i.e. its top row lies at +y.
+pub struct EnvironmentMap {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<Rgb>,
+    /// The cumulative distribution of the texels' weights (luminance times solid angle) in each row.
+    row_cdfs: Vec<f64>,
+    /// The cumulative distribution of the rows' weights.
+    marginal_cdf: Vec<f64>,
+    total_weight: f64,
+}
+
+impl Background {
+    /// Looks up the radiance arriving from a given direction.
+    pub fn radiance(&self, direction: &Vector3) -> Rgb {
+        return match self {
+            Background::Solid(c) => *c,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 * (normalize(&direction).y + 1.0);
+                lerp_rgb(&bottom, &top, t)
+            }
+            Background::Environment(map) => map.radiance(&direction),
+        };
+    }
+
+    /// Checks whether the background is black, i.e. neither shows nor lights anything.
+    pub fn is_black(&self) -> bool {
+        return match self {
+            Background::Solid(c) => *c == BLACK_RGB,
+            Background::Gradient { bottom, top } => *bottom == BLACK_RGB && *top == BLACK_RGB,
+            Background::Environment(map) => map.total_weight <= 0.0,
+        };
+    }
+
+    /// Samples a direction from which light arrives at a surface with a given normal, given two uniformly
+    /// distributed numbers. Environment maps are sampled by their luminance, the other backgrounds by the
+    /// cosine. Returns the direction and its probability density, None if there is nothing to sample.
+    pub fn sample(&self, normal: &Vector3, u1: f64, u2: f64) -> Option<(Vector3, f64)> {
+        return match self {
+            Background::Environment(map) => map.sample(u1, u2),
+            _ => {
+                let l = sample_cosine_hemisphere(&normal, u1, u2);
+                let pdf = dot_product(&normal, &l) / PI;
+                if pdf > 0.0 {
+                    Some((l, pdf))
+                } else {
+                    None
+                }
+            }
+        };
+    }
+}
+
+impl EnvironmentMap {
+    /// Creates an environment map from given texels (row by row, top to bottom).
+    pub fn new(width: usize, height: usize, texels: Vec<Rgb>) -> EnvironmentMap {
+        // the rows close to the poles cover smaller solid angles:
+        let mut row_cdfs = Vec::with_capacity(width * height);
+        let mut marginal_cdf = Vec::with_capacity(height);
+        let mut total_weight = 0.0;
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            let mut row_weight = 0.0;
+            for x in 0..width {
+                row_weight += luminance(&texels[y * width + x]) * sin_theta;
+                row_cdfs.push(row_weight);
+            }
+            // normalize the row's distribution:
+            for c in row_cdfs[y * width..].iter_mut() {
+                *c = if row_weight > 0.0 {
+                    *c / row_weight
+                } else {
+                    1.0
+                };
+            }
+            total_weight += row_weight;
+            marginal_cdf.push(total_weight);
+        }
+        for c in marginal_cdf.iter_mut() {
+            *c /= total_weight.max(f64::MIN_POSITIVE);
+        }
+
+        return EnvironmentMap {
+            width,
+            height,
+            texels,
+            row_cdfs,
+            marginal_cdf,
+            total_weight,
+        };
+    }
+
+    /// Decodes a given HDR (or PNG, JPEG) file into an environment map.
+    pub fn decode(bytes: &[u8]) -> Result<EnvironmentMap, String> {
+        let image = decode_image(&bytes)?;
+        // (texel lookups need at least one texel:)
+        if image.width == 0 || image.height == 0 {
+            return Err("The environment map is empty!".to_string());
+        }
+        let texels = image
+            .samples
+            .chunks_exact(image.channels)
+            .map(|pixel| {
+                if image.channels < 3 {
+                    Rgb {
+                        r: pixel[0],
+                        g: pixel[0],
+                        b: pixel[0],
+                    }
+                } else {
+                    Rgb {
+                        r: pixel[0],
+                        g: pixel[1],
+                        b: pixel[2],
+                    }
+                }
+            })
+            .collect();
+        return Ok(EnvironmentMap::new(image.width, image.height, texels));
+    }
+
+    /// Looks up the radiance arriving from a given direction (the closest texel).
+    pub fn radiance(&self, direction: &Vector3) -> Rgb {
+        let (x, y) = self.texel_of(&direction);
+        return self.texels[y * self.width + x];
+    }
+
+    fn texel_of(&self, direction: &Vector3) -> (usize, usize) {
+        let uv = spherical_uv(&direction);
+        let x = ((uv.u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((uv.v * self.height as f64) as usize).min(self.height - 1);
+        return (x, y);
+    }
+
+    /// Samples a direction with a probability proportional to the radiance arriving from it,
+    /// returns the direction and its probability density (per solid angle).
+    pub fn sample(&self, u1: f64, u2: f64) -> Option<(Vector3, f64)> {
+        if self.total_weight <= 0.0 {
+            return None;
+        }
+
+        // choose a row and then a texel in it, the remainders place the sample within the texel:
+        let (y, fy) = sample_cdf(&self.marginal_cdf, u1);
+        let row = &self.row_cdfs[y * self.width..(y + 1) * self.width];
+        let (x, fx) = sample_cdf(&row, u2);
+
+        let phi = 2.0 * PI * ((x as f64 + fx) / self.width as f64 - 0.5);
+        let theta = PI * (y as f64 + fy) / self.height as f64;
+        let direction = Vector3 {
+            x: theta.sin() * phi.cos(),
+            y: theta.cos(),
+            z: theta.sin() * phi.sin(),
+        };
+        return Some((direction, self.pdf_of_texel(x, y)));
+    }
+
+    /// The probability density (per solid angle) of sample() choosing a given direction.
+    pub fn pdf(&self, direction: &Vector3) -> f64 {
+        if self.total_weight <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.texel_of(&direction);
+        return self.pdf_of_texel(x, y);
+    }
+
+    fn pdf_of_texel(&self, x: usize, y: usize) -> f64 {
+        let sin_theta = (PI * (y as f64 + 0.5) / self.height as f64).sin();
+        let weight = luminance(&self.texels[y * self.width + x]) * sin_theta;
+        // a texel covers (2 PI / width) (PI / height) sin(theta) steradians:
+        let solid_angle = 2.0 * PI * PI * sin_theta / (self.width * self.height) as f64;
+        return weight / self.total_weight / solid_angle;
+    }
+}
+
+fn luminance(c: &Rgb) -> f64 {
+    return (0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b).max(0.0);
+}
+
+/// Chooses the bin of a (normalized) cumulative distribution that contains u,
+/// returns its index and where u lies within it.
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    let i = cdf.partition_point(|c| *c <= u).min(cdf.len() - 1);
+    let low = if i > 0 { cdf[i - 1] } else { 0.0 };
+    let width = cdf[i] - low;
+    let f = if width > 0.0 { (u - low) / width } else { 0.5 };
+    return (i, f.max(0.0).min(1.0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP: Vector3 = Vector3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+
+    /// A dim environment with a bright spot above the horizon.
+    fn create_test_map() -> EnvironmentMap {
+        let (width, height) = (32, 16);
+        let mut texels = vec![
+            Rgb {
+                r: 0.1,
+                g: 0.1,
+                b: 0.1,
+            };
+            width * height
+        ];
+        texels[4 * width + 10] = Rgb {
+            r: 200.0,
+            g: 150.0,
+            b: 100.0,
+        };
+        return EnvironmentMap::new(width, height, texels);
+    }
+
+    #[test]
+    fn test_background_radiance() {
+        let white = Rgb {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+        };
+        let gradient = Background::Gradient {
+            bottom: BLACK_RGB,
+            top: white,
+        };
+        assert_eq!(gradient.radiance(&UP), white);
+        assert_eq!(gradient.radiance(&scale_vector(-2.0, &UP)), BLACK_RGB);
+        assert!(Background::Solid(BLACK_RGB).is_black());
+        assert!(!gradient.is_black());
+
+        let map = create_test_map();
+        assert_eq!(map.radiance(&UP).r, 0.1);
+        let (d, _) = map.sample(0.5, 0.5).unwrap();
+        assert_eq!(map.radiance(&d).r, 200.0);
+
+        assert!(EnvironmentMap::decode(b"#?RADIANCE\n\n-Y 0 +X 0\n").is_err());
+    }
+
+    #[test]
+    fn test_importance_sampling() {
+        let map = create_test_map();
+
+        // the irradiance of a surface facing up, summed over all texels:
+        let mut expected = 0.0;
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let phi = 2.0 * PI * ((x as f64 + 0.5) / map.width as f64 - 0.5);
+                let theta = PI * (y as f64 + 0.5) / map.height as f64;
+                let solid_angle = 2.0 * PI * PI * theta.sin() / (map.width * map.height) as f64;
+                let cos_theta = theta.cos().max(0.0);
+                expected += map.texels[y * map.width + x].r * cos_theta * solid_angle;
+                // the pdf matches the sampled texel:
+                let d = Vector3 {
+                    x: theta.sin() * phi.cos(),
+                    y: theta.cos(),
+                    z: theta.sin() * phi.sin(),
+                };
+                assert_eq!(map.texel_of(&d), (x, y));
+            }
+        }
+
+        // ...and estimated with samples that mostly hit the bright spot:
+        let mut rng = Rng::new(3);
+        let samples = 20000;
+        let mut estimate = 0.0;
+        for _ in 0..samples {
+            let (l, pdf) = map.sample(rng.next_f64(), rng.next_f64()).unwrap();
+            assert!((pdf - map.pdf(&l)).abs() < 0.000001 * pdf);
+            let cos_theta = dot_product(&UP, &l).max(0.0);
+            estimate += map.radiance(&l).r * cos_theta / pdf / samples as f64;
+        }
+        assert!(
+            (estimate / expected - 1.0).abs() < 0.03,
+            "{} {}",
+            estimate,
+            expected
+        );
+    }
+}
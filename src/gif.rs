@@ -0,0 +1,430 @@
+//! Encoding of animated GIFs: the frames share one palette of (at most) 256 colors, which is found by
+//! median cut and onto which the frames are dithered.
+
+use crate::image_io::*;
+use std::collections::HashMap;
+
+/// The most colors a GIF palette can have.
+const MAX_PALETTE_SIZE: usize = 256;
+
+/// At most this many pixels are sampled to find the palette.
+const MAX_PALETTE_SAMPLES: usize = 250000;
+
+/// LZW's dictionary is full when it reaches this code (codes have at most 12 bits, but some decoders
+/// reject the largest one).
+const MAX_LZW_CODE: u16 = 4095;
+
+/// Encodes given 8 bit RGBA frames (row by row, top to bottom) as an animated GIF that loops forever.
+/// The alpha channel is ignored. Without dithering, every pixel gets its closest palette color, with
+/// dithering, the error is spread to its neighbours (Floyd-Steinberg), which avoids color bands.
+pub fn encode_gif(
+    width: u32,
+    height: u32,
+    frames: &[Vec<u8>],
+    frames_per_second: f64,
+    dithering: bool,
+) -> Result<Vec<u8>, String> {
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(format!(
+            "A GIF can be at most 65535x65535 pixels, not {}x{}!",
+            width, height
+        ));
+    }
+    if width == 0 || height == 0 {
+        return Err(format!("A GIF can't be {}x{} pixels!", width, height));
+    }
+    if frames.is_empty() {
+        return Err("An animation needs at least one frame!".to_string());
+    }
+    for frame in frames {
+        check_rgba_size(width, height, &frame)?;
+    }
+
+    let palette = find_palette(&frames, MAX_PALETTE_SIZE);
+    let mut closest_colors = ClosestColors::new(&palette);
+    // (the delay is given in hundredths of a second:)
+    let delay = (100.0 / frames_per_second)
+        .round()
+        .clamp(0.0, u16::MAX as f64) as u16;
+
+    let mut bytes = b"GIF89a".to_vec();
+    // the logical screen descriptor, followed by a global color table with 256 entries:
+    bytes.extend_from_slice(&(width as u16).to_le_bytes());
+    bytes.extend_from_slice(&(height as u16).to_le_bytes());
+    bytes.extend_from_slice(&[0xF7, 0, 0]);
+    for i in 0..MAX_PALETTE_SIZE {
+        bytes.extend_from_slice(&palette.get(i).unwrap_or(&[0, 0, 0])[..]);
+    }
+    // the Netscape extension that makes the animation loop forever:
+    bytes.extend_from_slice(&[0x21, 0xFF, 11]);
+    bytes.extend_from_slice(b"NETSCAPE2.0");
+    bytes.extend_from_slice(&[3, 1, 0, 0, 0]);
+
+    for frame in frames {
+        let indices = if dithering {
+            dither(&frame, width as usize, &palette, &mut closest_colors)
+        } else {
+            frame
+                .chunks_exact(4)
+                .map(|p| closest_colors.find(&[p[0], p[1], p[2]]))
+                .collect()
+        };
+
+        // a graphic control extension with the frame's delay:
+        bytes.extend_from_slice(&[0x21, 0xF9, 4, 0]);
+        bytes.extend_from_slice(&delay.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        // the image descriptor, covering the whole screen:
+        bytes.extend_from_slice(&[0x2C, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&(width as u16).to_le_bytes());
+        bytes.extend_from_slice(&(height as u16).to_le_bytes());
+        bytes.push(0);
+        // the compressed pixels, split into sub-blocks of at most 255 bytes:
+        bytes.push(8);
+        for block in lzw_compress(&indices).chunks(255) {
+            bytes.push(block.len() as u8);
+            bytes.extend_from_slice(&block);
+        }
+        bytes.push(0);
+    }
+
+    bytes.push(0x3B);
+    return Ok(bytes);
+}
+
+/// Finds a palette for given RGBA frames by median cut: starting with a box around all colors, the box
+/// with the longest side is split at the median until there are enough boxes, each box's average color
+/// becomes a palette entry.
+pub fn find_palette(frames: &[Vec<u8>], size: usize) -> Vec<[u8; 3]> {
+    let pixel_count: usize = frames.iter().map(|frame| frame.len() / 4).sum();
+    let step = (pixel_count / MAX_PALETTE_SAMPLES).max(1);
+    let colors: Vec<[u8; 3]> = frames
+        .iter()
+        .flat_map(|frame| frame.chunks_exact(4))
+        .step_by(step)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    if colors.is_empty() {
+        return vec![];
+    }
+
+    let mut boxes = vec![colors];
+    while boxes.len() < size {
+        // the box (with more than one color) whose colors spread the most along one channel:
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, colors)| (i, widest_channel(&colors)))
+            .filter(|(_, (_, extent))| *extent > 0)
+            .max_by_key(|(_, (_, extent))| *extent);
+        let (i, channel) = match widest {
+            Some((i, (channel, _))) => (i, channel),
+            None => break,
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|color| color[channel]);
+        // (split at the median, but never between equal values, so that equal colors share a box:)
+        let median = colors[colors.len() / 2][channel];
+        let mut split = colors
+            .iter()
+            .position(|color| color[channel] == median)
+            .unwrap();
+        if split == 0 {
+            split = colors
+                .iter()
+                .position(|color| color[channel] > median)
+                .unwrap();
+        }
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    return boxes
+        .iter()
+        .map(|colors| {
+            let mut total = [0usize; 3];
+            for color in colors {
+                for (total, c) in total.iter_mut().zip(color) {
+                    *total += *c as usize;
+                }
+            }
+            let average = |c: usize| ((total[c] + colors.len() / 2) / colors.len()) as u8;
+            [average(0), average(1), average(2)]
+        })
+        .collect();
+}
+
+/// Finds the channel along which given colors spread the most, and how far they spread.
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    return (0..3)
+        .map(|c| {
+            let min = colors.iter().map(|color| color[c]).min().unwrap_or(0);
+            let max = colors.iter().map(|color| color[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|(_, extent)| *extent)
+        .unwrap();
+}
+
+/// Looks up the closest palette colors, remembering the colors it has seen.
+struct ClosestColors<'a> {
+    palette: &'a [[u8; 3]],
+    cache: HashMap<[u8; 3], u8>,
+}
+
+impl<'a> ClosestColors<'a> {
+    fn new(palette: &'a [[u8; 3]]) -> ClosestColors<'a> {
+        return ClosestColors {
+            palette,
+            cache: HashMap::new(),
+        };
+    }
+
+    /// Finds the index of the palette color with the smallest (squared Euclidean) distance to a given color.
+    fn find(&mut self, color: &[u8; 3]) -> u8 {
+        let palette = self.palette;
+        return *self.cache.entry(*color).or_insert_with(|| {
+            let distance = |entry: &[u8; 3]| {
+                return (0..3)
+                    .map(|c| (entry[c] as i32 - color[c] as i32).pow(2))
+                    .sum::<i32>();
+            };
+            (0..palette.len())
+                .min_by_key(|i| distance(&palette[*i]))
+                .unwrap_or(0) as u8
+        });
+    }
+}
+
+/// Maps an RGBA frame to palette indices with Floyd-Steinberg dithering: each pixel's error (the difference
+/// between its color and its palette color) is spread to the pixels right of and below it.
+fn dither(
+    frame: &[u8],
+    width: usize,
+    palette: &[[u8; 3]],
+    closest_colors: &mut ClosestColors,
+) -> Vec<u8> {
+    let mut colors: Vec<[f64; 3]> = frame
+        .chunks_exact(4)
+        .map(|p| [p[0] as f64, p[1] as f64, p[2] as f64])
+        .collect();
+    let height = colors.len() / width;
+    let mut indices = Vec::with_capacity(colors.len());
+
+    for y in 0..height {
+        for x in 0..width {
+            let color = colors[y * width + x];
+            let rounded = [0, 1, 2].map(|c| color[c].round().clamp(0.0, 255.0) as u8);
+            let index = closest_colors.find(&rounded);
+            indices.push(index);
+
+            let entry = palette[index as usize];
+            let error = [0, 1, 2].map(|c| color[c] - entry[c] as f64);
+            let mut spread = |dx: isize, dy: usize, weight: f64| {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height {
+                    let neighbour = &mut colors[(y + dy) * width + nx as usize];
+                    for (c, e) in neighbour.iter_mut().zip(&error) {
+                        *c += e * weight;
+                    }
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+
+    return indices;
+}
+
+/// Compresses given 8 bit palette indices with GIF's variant of LZW, the codes are packed into bytes
+/// starting with their least significant bits.
+fn lzw_compress(indices: &[u8]) -> Vec<u8> {
+    let clear_code: u16 = 256;
+    let end_code: u16 = 257;
+
+    let mut bytes = Vec::new();
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    let mut code_size = 9;
+    let mut write_code = |code: u16, code_size: u32, bytes: &mut Vec<u8>| {
+        bits |= (code as u32) << bit_count;
+        bit_count += code_size;
+        while bit_count >= 8 {
+            bytes.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    // maps a known sequence's code and the next index to the code of the longer sequence:
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end_code + 1;
+    write_code(clear_code, code_size, &mut bytes);
+
+    let mut sequence: Option<u16> = None;
+    for index in indices {
+        let prefix = match sequence {
+            None => {
+                sequence = Some(*index as u16);
+                continue;
+            }
+            Some(prefix) => prefix,
+        };
+        if let Some(code) = dictionary.get(&(prefix, *index)) {
+            sequence = Some(*code);
+            continue;
+        }
+
+        write_code(prefix, code_size, &mut bytes);
+        if next_code < MAX_LZW_CODE {
+            dictionary.insert((prefix, *index), next_code);
+            // (the decoder learns each code one step later, so it widens its codes only now:)
+            if next_code == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+            next_code += 1;
+        } else {
+            // the dictionary is full, start over:
+            write_code(clear_code, code_size, &mut bytes);
+            dictionary.clear();
+            next_code = end_code + 1;
+            code_size = 9;
+        }
+        sequence = Some(*index as u16);
+    }
+
+    if let Some(code) = sequence {
+        write_code(code, code_size, &mut bytes);
+    }
+    write_code(end_code, code_size, &mut bytes);
+    // (flush the remaining bits:)
+    write_code(0, 7, &mut bytes);
+    return bytes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decompresses GIF LZW data, the way a decoder would.
+    fn lzw_decompress(bytes: &[u8]) -> Vec<u8> {
+        let mut codes = Vec::new();
+        let (mut pos, mut code_size) = (0, 9);
+        let mut dictionary: Vec<Vec<u8>> = vec![];
+        let mut previous: Option<Vec<u8>> = None;
+        let mut indices = Vec::new();
+        loop {
+            let mut code = 0;
+            for i in 0..code_size {
+                let bit = (bytes[(pos + i) / 8] >> ((pos + i) % 8)) & 1;
+                code |= (bit as usize) << i;
+            }
+            pos += code_size;
+            codes.push(code);
+
+            if code == 256 {
+                dictionary = (0..256).map(|i| vec![i as u8]).collect();
+                dictionary.push(vec![]);
+                dictionary.push(vec![]);
+                code_size = 9;
+                previous = None;
+                continue;
+            }
+            if code == 257 {
+                return indices;
+            }
+            let entry = match (dictionary.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [&previous[..], &previous[..1]].concat(),
+                (None, None) => panic!("Unknown code {}", code),
+            };
+            indices.extend_from_slice(&entry);
+            if let Some(previous) = previous {
+                if dictionary.len() <= MAX_LZW_CODE as usize {
+                    dictionary.push([&previous[..], &entry[..1]].concat());
+                }
+            }
+            if dictionary.len() == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_compress() {
+        let repetitive: Vec<u8> = (0..10000).map(|i| (i / 7 % 3) as u8).collect();
+        let compressed = lzw_compress(&repetitive);
+        assert!(compressed.len() < 1000);
+        assert_eq!(lzw_decompress(&compressed), repetitive);
+
+        // (noise fills the dictionary, which has to start over:)
+        let mut state: u32 = 1;
+        let noise: Vec<u8> = (0..20000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        assert_eq!(lzw_decompress(&lzw_compress(&noise)), noise);
+        assert_eq!(lzw_decompress(&lzw_compress(&[])), vec![]);
+    }
+
+    #[test]
+    fn test_find_palette() {
+        let frame = vec![
+            255, 0, 0, 255, 0, 0, 255, 255, 255, 0, 0, 255, 250, 250, 250, 255,
+        ];
+        let mut palette = find_palette(std::slice::from_ref(&frame), 256);
+        palette.sort();
+        assert_eq!(palette, vec![[0, 0, 255], [250, 250, 250], [255, 0, 0]]);
+
+        // two colors, one for the red pixels and one for the blue and the grey pixel:
+        let mut palette = find_palette(&[frame], 2);
+        palette.sort();
+        assert_eq!(palette, vec![[125, 125, 253], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn test_dither() {
+        // a mid grey is dithered to an even mix of black and white pixels:
+        let palette = [[0, 0, 0], [255, 255, 255]];
+        let frame: Vec<u8> = (0..64).flat_map(|_| vec![128, 128, 128, 255]).collect();
+        let indices = dither(&frame, 8, &palette, &mut ClosestColors::new(&palette));
+        let white = indices.iter().filter(|i| **i == 1).count();
+        assert!((28..=36).contains(&white));
+
+        let mut closest_colors = ClosestColors::new(&palette);
+        assert_eq!(closest_colors.find(&[100, 100, 100]), 0);
+        assert_eq!(closest_colors.find(&[200, 100, 200]), 1);
+    }
+
+    #[test]
+    fn test_encode_gif() {
+        let frames = vec![
+            vec![255, 0, 0, 255, 0, 0, 255, 255],
+            vec![0, 0, 255, 255, 255, 0, 0, 255],
+        ];
+        let gif = encode_gif(2, 1, &frames, 10.0, true).unwrap();
+        assert!(gif.starts_with(b"GIF89a\x02\x00\x01\x00\xF7"));
+        assert_eq!(gif.last(), Some(&0x3B));
+        // each frame waits 10/100 seconds:
+        assert_eq!(
+            gif.windows(6)
+                .filter(|w| *w == [0x21, 0xF9, 4, 0, 10, 0])
+                .count(),
+            2
+        );
+
+        assert!(encode_gif(2, 1, &[], 10.0, true).is_err());
+        assert!(encode_gif(2, 2, &frames, 10.0, true).is_err());
+        assert!(encode_gif(70000, 1, &frames, 10.0, true).is_err());
+        assert!(encode_gif(0, 0, &[vec![]], 10.0, true).is_err());
+        assert!(encode_gif(0, 1, &[vec![]], 10.0, false).is_err());
+    }
+}
@@ -0,0 +1,487 @@
+//! Heightfield terrain, intersected by traversing its grid cells (DDA) instead of as a triangle soup.
+
+use crate::image_io::*;
+use crate::material::*;
+use crate::ray_tracer::*;
+use crate::texture::*;
+use crate::vector_arithmetic::*;
+
+/// A regular grid of height samples in [0.0, 1.0], stored row by row (along x, then along z).
+#[derive(PartialEq)]
+pub struct HeightGrid {
+    /// The number of samples along the x-axis.
+    pub width: usize,
+    /// The number of samples along the z-axis.
+    pub depth: usize,
+    pub heights: Vec<f64>,
+}
+
+/// A terrain whose grid of heights is stretched over an axis-aligned box.
+#[derive(PartialEq)]
+pub struct Heightfield {
+    pub grid: HeightGrid,
+    /// The corner of the terrain's bounding box with the smallest coordinates.
+    pub min: Vector3,
+    /// The extent of the bounding box, size.y is the height of a sample of 1.0.
+    pub size: Vector3,
+    pub material: Material,
+}
+
+impl HeightGrid {
+    /// Loads the heights from a grayscale PNG image, colored images are converted to their luminance.
+    pub fn from_png(bytes: &[u8]) -> Result<HeightGrid, String> {
+        let image = decode_png(&bytes)?;
+        let mut heights = Vec::with_capacity(image.width * image.height);
+        for z in 0..image.height {
+            for x in 0..image.width {
+                heights.push(image.luminance(x, z));
+            }
+        }
+        return HeightGrid::new(image.width, image.height, heights);
+    }
+
+    /// Loads the heights from a raw grid of little-endian 32 bit floats (in any unit), which are mapped to
+    /// [0.0, 1.0]. Also returns the lowest and the highest raw height, which become 0.0 and 1.0.
+    pub fn from_raw_f32(
+        bytes: &[u8],
+        width: usize,
+        depth: usize,
+    ) -> Result<(HeightGrid, f64, f64), String> {
+        let expected = width
+            .checked_mul(depth)
+            .and_then(|n| n.checked_mul(4))
+            .ok_or_else(|| format!("A {}x{} grid is too large!", width, depth))?;
+        if bytes.len() != expected {
+            return Err(format!(
+                "Expected {} bytes for a {}x{} grid, got {}!",
+                expected,
+                width,
+                depth,
+                bytes.len()
+            ));
+        }
+        let raw_heights: Vec<f64> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        if let Some(i) = raw_heights.iter().position(|h| !h.is_finite()) {
+            return Err(format!(
+                "The height at ({}, {}) is not finite: {}",
+                i % width.max(1),
+                i / width.max(1),
+                raw_heights[i]
+            ));
+        }
+
+        let lowest = raw_heights.iter().copied().fold(f64::INFINITY, f64::min);
+        let highest = raw_heights
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        // (a flat grid lies at the bottom of its box:)
+        let range = if highest > lowest {
+            highest - lowest
+        } else {
+            1.0
+        };
+        let heights = raw_heights.iter().map(|h| (h - lowest) / range).collect();
+        let grid = HeightGrid::new(width, depth, heights)?;
+        return Ok((grid, lowest, highest));
+    }
+
+    fn new(width: usize, depth: usize, heights: Vec<f64>) -> Result<HeightGrid, String> {
+        if width < 2 || depth < 2 {
+            return Err(format!(
+                "A heightfield needs at least 2x2 samples, got {}x{}!",
+                width, depth
+            ));
+        }
+        return Ok(HeightGrid {
+            width,
+            depth,
+            heights,
+        });
+    }
+
+    fn height(&self, x: usize, z: usize) -> f64 {
+        return self.heights[z * self.width + x];
+    }
+}
+
+impl Heightfield {
+    /// Computes the ray parameter k of the closest hit in front of the ray's origin (HUGE_VALUE if there is none).
+    pub fn intersect(&self, r: &Ray) -> f64 {
+        let max = sum(&self.min, &self.size);
+        let (t_enter, t_exit) = match ray_box_span(&r, &self.min, &max) {
+            Some(span) => span,
+            None => return HUGE_VALUE,
+        };
+        if t_exit < TINY_VALUE {
+            return HUGE_VALUE;
+        }
+
+        let cells_x = (self.grid.width - 1) as i64;
+        let cells_z = (self.grid.depth - 1) as i64;
+        let cell_size_x = self.size.x / cells_x as f64;
+        let cell_size_z = self.size.z / cells_z as f64;
+
+        // the cell in which the ray enters the bounding box:
+        let t_start = f64::max(t_enter, 0.0);
+        let start = sum(&r.origin, &scale_vector(t_start, &r.direction));
+        let mut cell_x = clamp_cell(((start.x - self.min.x) / cell_size_x).floor(), cells_x);
+        let mut cell_z = clamp_cell(((start.z - self.min.z) / cell_size_z).floor(), cells_z);
+
+        // walk along the cells that the ray passes through:
+        let (step_x, delta_x, mut next_x) = dda_setup(
+            start.x,
+            r.direction.x,
+            self.min.x,
+            cell_size_x,
+            cell_x,
+            t_start,
+        );
+        let (step_z, delta_z, mut next_z) = dda_setup(
+            start.z,
+            r.direction.z,
+            self.min.z,
+            cell_size_z,
+            cell_z,
+            t_start,
+        );
+
+        loop {
+            let k = self.intersect_cell(&r, cell_x as usize, cell_z as usize);
+            let t_leave = f64::min(f64::min(next_x, next_z), t_exit);
+            if k < HUGE_VALUE && k <= t_leave + TINY_VALUE {
+                return k;
+            }
+            if t_leave >= t_exit {
+                return HUGE_VALUE;
+            }
+
+            if next_x < next_z {
+                cell_x += step_x;
+                next_x += delta_x;
+            } else {
+                cell_z += step_z;
+                next_z += delta_z;
+            }
+            if cell_x < 0 || cell_x >= cells_x || cell_z < 0 || cell_z >= cells_z {
+                return HUGE_VALUE;
+            }
+        }
+    }
+
+    /// Intersects a given ray with the two triangles of cell (x, z).
+    fn intersect_cell(&self, r: &Ray, x: usize, z: usize) -> f64 {
+        let p00 = self.vertex(x, z);
+        let p10 = self.vertex(x + 1, z);
+        let p01 = self.vertex(x, z + 1);
+        let p11 = self.vertex(x + 1, z + 1);
+
+        let k1 = ray_triangle_intersection_point(&r, &p00, &p10, &p11);
+        let k2 = ray_triangle_intersection_point(&r, &p00, &p11, &p01);
+        return f64::min(k1, k2);
+    }
+
+    fn vertex(&self, x: usize, z: usize) -> Vector3 {
+        return Vector3 {
+            x: self.min.x + self.size.x * x as f64 / (self.grid.width - 1) as f64,
+            y: self.min.y + self.size.y * self.grid.height(x, z),
+            z: self.min.z + self.size.z * z as f64 / (self.grid.depth - 1) as f64,
+        };
+    }
+
+    /// Computes the texture coordinates at a given point, the texture is stretched over the whole terrain.
+    pub fn compute_uv(&self, point: &Vector3) -> Uv {
+        return Uv {
+            u: (point.x - self.min.x) / self.size.x,
+            v: (point.z - self.min.z) / self.size.z,
+        };
+    }
+
+    /// Computes the derivatives of a point on the terrain with respect to its texture coordinates
+    /// (ignoring the terrain's slope).
+    pub fn compute_uv_derivatives(&self) -> (Vector3, Vector3) {
+        let dpdu = Vector3 {
+            x: self.size.x,
+            y: 0.0,
+            z: 0.0,
+        };
+        let dpdv = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: self.size.z,
+        };
+        return (dpdu, dpdv);
+    }
+
+    /// Computes the normal at a given point by interpolating the normals of its cell's corners.
+    pub fn compute_normal(&self, point: &Vector3) -> Vector3 {
+        let cells_x = (self.grid.width - 1) as i64;
+        let cells_z = (self.grid.depth - 1) as i64;
+        let gx = (point.x - self.min.x) / self.size.x * cells_x as f64;
+        let gz = (point.z - self.min.z) / self.size.z * cells_z as f64;
+        let x = clamp_cell(gx.floor(), cells_x) as usize;
+        let z = clamp_cell(gz.floor(), cells_z) as usize;
+        let fx = f64::min(f64::max(gx - x as f64, 0.0), 1.0);
+        let fz = f64::min(f64::max(gz - z as f64, 0.0), 1.0);
+
+        let n0 = sum(
+            &scale_vector(1.0 - fx, &self.vertex_normal(x, z)),
+            &scale_vector(fx, &self.vertex_normal(x + 1, z)),
+        );
+        let n1 = sum(
+            &scale_vector(1.0 - fx, &self.vertex_normal(x, z + 1)),
+            &scale_vector(fx, &self.vertex_normal(x + 1, z + 1)),
+        );
+        return normalize(&sum(&scale_vector(1.0 - fz, &n0), &scale_vector(fz, &n1)));
+    }
+
+    /// Computes the normal at a grid vertex from the central differences of the heights around it.
+    fn vertex_normal(&self, x: usize, z: usize) -> Vector3 {
+        let x0 = x.saturating_sub(1);
+        let x1 = usize::min(x + 1, self.grid.width - 1);
+        let z0 = z.saturating_sub(1);
+        let z1 = usize::min(z + 1, self.grid.depth - 1);
+
+        let cell_size_x = self.size.x / (self.grid.width - 1) as f64;
+        let cell_size_z = self.size.z / (self.grid.depth - 1) as f64;
+        let dx = (self.grid.height(x1, z) - self.grid.height(x0, z)) * self.size.y
+            / ((x1 - x0) as f64 * cell_size_x);
+        let dz = (self.grid.height(x, z1) - self.grid.height(x, z0)) * self.size.y
+            / ((z1 - z0) as f64 * cell_size_z);
+
+        return normalize(&Vector3 {
+            x: -dx,
+            y: 1.0,
+            z: -dz,
+        });
+    }
+}
+
+/// Computes the direction in which the DDA steps along one axis, the distance (in units of k) between
+/// two cell borders, and the k at which the ray crosses the next border.
+fn dda_setup(
+    start: f64,
+    direction: f64,
+    min: f64,
+    cell_size: f64,
+    cell: i64,
+    t_start: f64,
+) -> (i64, f64, f64) {
+    return if direction > 0.0 {
+        let border = min + (cell + 1) as f64 * cell_size;
+        (
+            1,
+            cell_size / direction,
+            t_start + (border - start) / direction,
+        )
+    } else if direction < 0.0 {
+        let border = min + cell as f64 * cell_size;
+        (
+            -1,
+            -cell_size / direction,
+            t_start + (border - start) / direction,
+        )
+    } else {
+        (0, f64::INFINITY, f64::INFINITY)
+    };
+}
+
+fn clamp_cell(cell: f64, cells: i64) -> i64 {
+    return i64::min(i64::max(cell as i64, 0), cells - 1);
+}
+
+/// Computes the ray parameter k at which a given ray hits the triangle (a, b, c), HUGE_VALUE if it
+/// misses it or hits it closer than TINY_VALUE (Möller-Trumbore).
+fn ray_triangle_intersection_point(r: &Ray, a: &Vector3, b: &Vector3, c: &Vector3) -> f64 {
+    let edge1 = difference(&b, &a);
+    let edge2 = difference(&c, &a);
+    let p = cross_product(&r.direction, &edge2);
+    let det = dot_product(&edge1, &p);
+    if det.abs() < 1e-12 {
+        return HUGE_VALUE;
+    }
+
+    let s = difference(&r.origin, &a);
+    let u = dot_product(&s, &p) / det;
+    if !(0.0..=1.0).contains(&u) {
+        return HUGE_VALUE;
+    }
+
+    let q = cross_product(&s, &edge1);
+    let v = dot_product(&r.direction, &q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return HUGE_VALUE;
+    }
+
+    let k = dot_product(&edge2, &q) / det;
+    return if k < TINY_VALUE { HUGE_VALUE } else { k };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x3 terrain over [0, 20] x [0, 20] with a peak of height 10 in its center.
+    fn test_heightfield() -> Heightfield {
+        return Heightfield {
+            grid: HeightGrid {
+                width: 3,
+                depth: 3,
+                heights: vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+            },
+            min: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size: Vector3 {
+                x: 20.0,
+                y: 10.0,
+                z: 20.0,
+            },
+            material: Material::new(Color { r: 0, g: 255, b: 0 }, false),
+        };
+    }
+
+    #[test]
+    fn test_intersect() {
+        let heightfield = test_heightfield();
+
+        // straight down onto the peak:
+        let r = Ray {
+            origin: Vector3 {
+                x: 10.0,
+                y: 50.0,
+                z: 10.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        assert!((heightfield.intersect(&r) - 40.0).abs() < 0.000001);
+
+        // a grazing ray that crosses several cells before hitting the slope:
+        let r = Ray {
+            origin: Vector3 {
+                x: -10.0,
+                y: 4.0,
+                z: 10.0,
+            },
+            direction: Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        assert!((heightfield.intersect(&r) - 14.0).abs() < 0.000001);
+
+        // ...and one that passes above the peak:
+        let r = Ray {
+            origin: Vector3 {
+                x: -10.0,
+                y: 11.0,
+                z: 10.0,
+            },
+            ..r
+        };
+        assert_eq!(heightfield.intersect(&r), HUGE_VALUE);
+    }
+
+    #[test]
+    fn test_compute_normal() {
+        let heightfield = test_heightfield();
+        let n = heightfield.compute_normal(&Vector3 {
+            x: 10.0,
+            y: 10.0,
+            z: 10.0,
+        });
+        assert_eq!(
+            n,
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            }
+        );
+
+        // the normals of the slopes are interpolated:
+        let n = heightfield.compute_normal(&Vector3 {
+            x: 5.0,
+            y: 5.0,
+            z: 10.0,
+        });
+        assert!(n.x < 0.0 && n.y > 0.0 && n.z == 0.0);
+    }
+
+    #[test]
+    fn test_from_raw_f32() {
+        let mut bytes = Vec::new();
+        for h in [0.0f32, 0.5, 1.0, 0.25].iter() {
+            bytes.extend_from_slice(&h.to_le_bytes());
+        }
+        let (grid, lowest, highest) = HeightGrid::from_raw_f32(&bytes, 2, 2).unwrap();
+        assert_eq!(grid.heights, vec![0.0, 0.5, 1.0, 0.25]);
+        assert_eq!((lowest, highest), (0.0, 1.0));
+
+        assert!(HeightGrid::from_raw_f32(&bytes, 3, 2).is_err());
+        assert!(HeightGrid::from_raw_f32(&bytes[..4], 1, 1).is_err());
+        assert!(HeightGrid::from_raw_f32(&bytes, usize::MAX, 2).is_err());
+        assert!(HeightGrid::from_raw_f32(&bytes, usize::MAX / 4 + 1, 1).is_err());
+
+        let raw = |heights: &[f32]| -> Vec<u8> {
+            return heights
+                .iter()
+                .flat_map(|h| h.to_le_bytes().to_vec())
+                .collect();
+        };
+        assert!(HeightGrid::from_raw_f32(&raw(&[0.0, f32::NAN, 1.0, 2.0]), 2, 2).is_err());
+        assert!(HeightGrid::from_raw_f32(&raw(&[0.0, f32::INFINITY, 1.0, 2.0]), 2, 2).is_err());
+
+        // heights in world units are mapped to [0.0, 1.0], so that the terrain stays inside its box:
+        let (grid, lowest, highest) =
+            HeightGrid::from_raw_f32(&raw(&[-20.0, 30.0, 80.0, 30.0]), 2, 2).unwrap();
+        assert_eq!(grid.heights, vec![0.0, 0.5, 1.0, 0.5]);
+        assert_eq!((lowest, highest), (-20.0, 80.0));
+        let heightfield = Heightfield {
+            grid,
+            min: ZERO_VECTOR3,
+            size: Vector3 {
+                x: 10.0,
+                y: highest - lowest,
+                z: 10.0,
+            },
+            material: Material::new(
+                Color {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+                false,
+            ),
+        };
+        let down = Ray {
+            origin: Vector3 {
+                x: 9.0,
+                y: 200.0,
+                z: 9.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        assert!(heightfield.intersect(&down) < HUGE_VALUE);
+
+        let (flat, _, _) = HeightGrid::from_raw_f32(&raw(&[3.0, 3.0, 3.0, 3.0]), 2, 2).unwrap();
+        assert_eq!(flat.heights, vec![0.0; 4]);
+    }
+}
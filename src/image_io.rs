@@ -0,0 +1,402 @@
+//! Decoding of image files into normalized sample values, and encoding of rendered images.
+
+use std::io::Cursor;
+
+/// A decoded image, its samples are stored row by row (top to bottom). They are normalized to [0.0, 1.0],
+/// except for HDR images whose samples are linear (unbounded) radiance values.
+pub struct DecodedImage {
+    pub width: usize,
+    pub height: usize,
+    /// The number of samples per pixel (1: grey, 2: grey + alpha, 3: RGB, 4: RGBA).
+    pub channels: usize,
+    pub samples: Vec<f64>,
+}
+
+impl DecodedImage {
+    /// Computes the luminance of pixel (x, y), ignoring its alpha channel.
+    pub fn luminance(&self, x: usize, y: usize) -> f64 {
+        let i = (y * self.width + x) * self.channels;
+        return if self.channels < 3 {
+            self.samples[i]
+        } else {
+            0.2126 * self.samples[i] + 0.7152 * self.samples[i + 1] + 0.0722 * self.samples[i + 2]
+        };
+    }
+}
+
+/// Decodes a given PNG, JPEG or Radiance HDR file, depending on its signature.
+pub fn decode_image(bytes: &[u8]) -> Result<DecodedImage, String> {
+    return if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+        decode_png(&bytes)
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        decode_jpeg(&bytes)
+    } else if bytes.starts_with(b"#?") {
+        decode_hdr(&bytes)
+    } else {
+        Err("Unknown image format, expected a PNG, JPEG or HDR file!".to_string())
+    };
+}
+
+/// Decodes a given PNG file.
+pub fn decode_png(bytes: &[u8]) -> Result<DecodedImage, String> {
+    let mut decoder = png::Decoder::new(Cursor::new(bytes));
+    // expand palettes and bit depths below 8 bits into plain samples:
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+    buffer.truncate(info.buffer_size());
+
+    let channels = info.color_type.samples();
+    let samples: Vec<f64> = match info.bit_depth {
+        png::BitDepth::Sixteen => buffer
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]) as f64 / 65535.0)
+            .collect(),
+        _ => buffer.iter().map(|s| *s as f64 / 255.0).collect(),
+    };
+
+    return Ok(DecodedImage {
+        width: info.width as usize,
+        height: info.height as usize,
+        channels,
+        samples,
+    });
+}
+
+/// Encodes a given 8 bit RGBA image (row by row, top to bottom) as PNG.
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, String> {
+    check_rgba_size(width, height, &rgba)?;
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(&rgba).map_err(|e| e.to_string())?;
+    }
+    return Ok(bytes);
+}
+
+/// Encodes given 8 bit RGBA frames (row by row, top to bottom) as an animated PNG that loops forever.
+pub fn encode_apng(
+    width: u32,
+    height: u32,
+    frames: &[Vec<u8>],
+    frames_per_second: f64,
+) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 {
+        return Err(format!("A PNG can't be {}x{} pixels!", width, height));
+    }
+    if frames.is_empty() {
+        return Err("An animation needs at least one frame!".to_string());
+    }
+    for frame in frames {
+        check_rgba_size(width, height, &frame)?;
+    }
+    // (the delay is given in thousandths of a second:)
+    let delay = (1000.0 / frames_per_second)
+        .round()
+        .clamp(0.0, u16::MAX as f64) as u16;
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(frames.len() as u32, 0)
+            .map_err(|e| e.to_string())?;
+        encoder
+            .set_frame_delay(delay, 1000)
+            .map_err(|e| e.to_string())?;
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        for frame in frames {
+            writer.write_image_data(&frame).map_err(|e| e.to_string())?;
+        }
+        writer.finish().map_err(|e| e.to_string())?;
+    }
+    return Ok(bytes);
+}
+
+/// Checks that a given RGBA image has the bytes of a given size.
+pub fn check_rgba_size(width: u32, height: u32, rgba: &[u8]) -> Result<(), String> {
+    let expected = width as usize * height as usize * 4;
+    if rgba.len() != expected {
+        return Err(format!(
+            "Expected {} bytes for a {}x{} RGBA image, got {}!",
+            expected,
+            width,
+            height,
+            rgba.len()
+        ));
+    }
+    return Ok(());
+}
+
+/// Decodes a given (8 bit grayscale or RGB) JPEG file.
+pub fn decode_jpeg(bytes: &[u8]) -> Result<DecodedImage, String> {
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(bytes));
+    let pixels = decoder.decode().map_err(|e| e.to_string())?;
+    let info = match decoder.info() {
+        Some(info) => info,
+        None => return Err("The JPEG file has no image info!".to_string()),
+    };
+
+    let channels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => 1,
+        jpeg_decoder::PixelFormat::RGB24 => 3,
+        pixel_format => return Err(format!("Unsupported JPEG pixel format {:?}!", pixel_format)),
+    };
+
+    return Ok(DecodedImage {
+        width: info.width as usize,
+        height: info.height as usize,
+        channels,
+        samples: pixels.iter().map(|s| *s as f64 / 255.0).collect(),
+    });
+}
+
+/// Decodes a given Radiance HDR (RGBE) file, with flat or run-length encoded scanlines.
+pub fn decode_hdr(bytes: &[u8]) -> Result<DecodedImage, String> {
+    if !(bytes.starts_with(b"#?RADIANCE") || bytes.starts_with(b"#?RGBE")) {
+        return Err("Not a Radiance HDR file!".to_string());
+    }
+
+    // the header's lines end with an empty line:
+    let mut pos = 0;
+    loop {
+        let line = read_hdr_line(&bytes, &mut pos)?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(format!("Unsupported HDR format {}!", &line[7..]));
+        }
+    }
+
+    // ...followed by the resolution, we only support the standard orientation:
+    let resolution = read_hdr_line(&bytes, &mut pos)?;
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    if parts.len() != 4 || parts[0] != "-Y" || parts[2] != "+X" {
+        return Err(format!("Unsupported HDR resolution line {}!", resolution));
+    }
+    let parse = |s: &str| s.parse::<usize>().map_err(|e| e.to_string());
+    let height = parse(parts[1])?;
+    let width = parse(parts[3])?;
+    if width == 0 || height == 0 {
+        return Err(format!("Bad HDR size {}x{}!", width, height));
+    }
+
+    // (even run length encoded pixels take 8 bytes per 127, so a larger image must be truncated:)
+    let pixel_count = match width.checked_mul(height) {
+        Some(pixel_count) => pixel_count,
+        None => return Err(format!("HDR size {}x{} is too large!", width, height)),
+    };
+    if pixel_count / 16 > bytes.len() - pos {
+        return Err("Truncated HDR pixel data!".to_string());
+    }
+
+    let mut samples = Vec::with_capacity(pixel_count * 3);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_hdr_scanline(&bytes, &mut pos, &mut scanline)?;
+        for rgbe in &scanline {
+            // the shared exponent is biased by 128, the mantissas are 8 bit fractions:
+            let f = if rgbe[3] == 0 {
+                0.0
+            } else {
+                2.0_f64.powi(rgbe[3] as i32 - 136)
+            };
+            samples.push(rgbe[0] as f64 * f);
+            samples.push(rgbe[1] as f64 * f);
+            samples.push(rgbe[2] as f64 * f);
+        }
+    }
+
+    return Ok(DecodedImage {
+        width,
+        height,
+        channels: 3,
+        samples,
+    });
+}
+
+fn read_hdr_line(bytes: &[u8], pos: &mut usize) -> Result<String, String> {
+    let rest = &bytes[*pos..];
+    let end = match rest.iter().position(|b| *b == b'\n') {
+        Some(end) => end,
+        None => return Err("Truncated HDR header!".to_string()),
+    };
+    *pos += end + 1;
+    return Ok(String::from_utf8_lossy(&rest[..end]).trim_end().to_string());
+}
+
+fn read_hdr_byte(bytes: &[u8], pos: &mut usize) -> Result<u8, String> {
+    return match bytes.get(*pos) {
+        Some(b) => {
+            *pos += 1;
+            Ok(*b)
+        }
+        None => Err("Truncated HDR pixel data!".to_string()),
+    };
+}
+
+/// Reads one scanline of RGBE pixels.
+fn read_hdr_scanline(
+    bytes: &[u8],
+    pos: &mut usize,
+    scanline: &mut [[u8; 4]],
+) -> Result<(), String> {
+    let width = scanline.len();
+    let rest = &bytes[*pos..];
+    let run_length_encoded = (8..32768).contains(&width)
+        && rest.len() >= 4
+        && rest[0] == 2
+        && rest[1] == 2
+        && rest[2] < 128;
+    if !run_length_encoded {
+        for pixel in scanline.iter_mut() {
+            for sample in pixel.iter_mut() {
+                *sample = read_hdr_byte(&bytes, pos)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let encoded_width = ((rest[2] as usize) << 8) | rest[3] as usize;
+    if encoded_width != width {
+        return Err("Wrong HDR scanline width!".to_string());
+    }
+    *pos += 4;
+
+    // the four channels are encoded one after another, as runs or literal sequences:
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = read_hdr_byte(&bytes, pos)? as usize;
+            let (n, run) = if count > 128 {
+                (count - 128, true)
+            } else {
+                (count, false)
+            };
+            if n == 0 || x + n > width {
+                return Err("Bad HDR scanline!".to_string());
+            }
+            let value = if run { read_hdr_byte(&bytes, pos)? } else { 0 };
+            for pixel in scanline[x..x + n].iter_mut() {
+                pixel[channel] = if run {
+                    value
+                } else {
+                    read_hdr_byte(&bytes, pos)?
+                };
+            }
+            x += n;
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a given 8 bit grayscale image as PNG.
+    fn encode_grey_png(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, width, height);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&data).unwrap();
+        }
+        return bytes;
+    }
+
+    #[test]
+    fn test_decode_png() {
+        let bytes = encode_grey_png(2, 2, &[0, 51, 255, 102]);
+        let image = decode_png(&bytes).unwrap();
+        assert_eq!(image.width, 2);
+        assert_eq!(image.height, 2);
+        assert_eq!(image.channels, 1);
+        assert_eq!(image.samples, vec![0.0, 0.2, 1.0, 0.4]);
+        assert_eq!(image.luminance(1, 1), 0.4);
+
+        assert!(decode_png(&[1, 2, 3]).is_err());
+        assert_eq!(decode_image(&bytes).unwrap().samples, image.samples);
+        assert!(decode_image(&[1, 2, 3]).is_err());
+        assert!(decode_image(&[0xFF, 0xD8, 3]).is_err());
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let rgba = [255, 0, 0, 255, 0, 51, 255, 255];
+        let image = decode_png(&encode_png(2, 1, &rgba).unwrap()).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (2, 1, 4));
+        assert_eq!(image.samples, vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.2, 1.0, 1.0]);
+
+        assert!(encode_png(2, 2, &rgba).is_err());
+    }
+
+    #[test]
+    fn test_encode_apng() {
+        let frames = vec![
+            vec![255, 0, 0, 255],
+            vec![0, 0, 255, 255],
+            vec![0, 255, 0, 255],
+        ];
+        let bytes = encode_apng(1, 1, &frames, 25.0).unwrap();
+        let decoder = png::Decoder::new(Cursor::new(&bytes));
+        let reader = decoder.read_info().unwrap();
+        let animation = reader.info().animation_control().unwrap();
+        assert_eq!((animation.num_frames, animation.num_plays), (3, 0));
+        let frame = reader.info().frame_control().unwrap();
+        assert_eq!((frame.delay_num, frame.delay_den), (40, 1000));
+        // (the first frame is the default image:)
+        assert_eq!(
+            decode_png(&bytes).unwrap().samples,
+            vec![1.0, 0.0, 0.0, 1.0]
+        );
+
+        assert!(encode_apng(1, 1, &[], 25.0).is_err());
+        assert!(encode_apng(2, 1, &frames, 25.0).is_err());
+        assert!(encode_apng(0, 1, &[vec![]], 25.0).is_err());
+    }
+
+    #[test]
+    fn test_decode_hdr() {
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n";
+
+        // a flat scanline:
+        let mut bytes = header.to_vec();
+        bytes.extend_from_slice(b"-Y 1 +X 1\n");
+        bytes.extend_from_slice(&[128, 64, 32, 130]);
+        let image = decode_image(&bytes).unwrap();
+        assert_eq!(image.samples, vec![2.0, 1.0, 0.5]);
+
+        // a run-length encoded one, with runs (red, green, exponent) and a literal sequence (blue):
+        let mut bytes = header.to_vec();
+        bytes.extend_from_slice(b"-Y 1 +X 8\n");
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        bytes.extend_from_slice(&[128 + 8, 128]);
+        bytes.extend_from_slice(&[128 + 8, 64]);
+        bytes.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        bytes.extend_from_slice(&[128 + 8, 129]);
+        let image = decode_hdr(&bytes).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (8, 1, 3));
+        assert_eq!(&image.samples[0..3], &[1.0, 0.5, 0.0]);
+        assert_eq!(&image.samples[21..24], &[1.0, 0.5, 0.875]);
+
+        assert!(decode_hdr(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_hdr(b"#?RADIANCE\n\n+Y 1 +X 1\n").is_err());
+
+        // empty, overflowing and truncated sizes are rejected before allocating:
+        assert!(decode_hdr(b"#?RADIANCE\n\n-Y 0 +X 1\n").is_err());
+        assert!(decode_hdr(b"#?RADIANCE\n\n-Y 1 +X 0\n").is_err());
+        let huge = format!("#?RADIANCE\n\n-Y {} +X {}\n", usize::MAX / 2, 3);
+        assert!(decode_hdr(huge.as_bytes()).is_err());
+        assert!(decode_hdr(b"#?RADIANCE\n\n-Y 100000 +X 100000\n").is_err());
+    }
+}
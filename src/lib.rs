@@ -1,10 +1,39 @@
 //! lazor's library file that exposes the WebAssembly entry point.
 
-mod ray_tracer;
+pub mod animation;
+pub mod brdf;
+pub mod camera;
+pub mod csg;
+pub mod environment;
+pub mod gif;
+pub mod heightfield;
+pub mod image_io;
+pub mod material;
+pub mod normal_mapping;
+pub mod procedural_texture;
+pub mod quadric;
+pub mod ray_tracer;
+pub mod rgb;
+pub mod sampling;
+pub mod scene_edit;
+pub mod sdf;
+pub mod texture;
 mod utils;
-mod vector_arithmetic;
+pub mod vector_arithmetic;
+pub mod viewer;
 
+use crate::animation::*;
+use crate::brdf::*;
+use crate::camera::*;
+use crate::csg::*;
+use crate::environment::*;
+use crate::gif::*;
+use crate::image_io::*;
+use crate::material::*;
+use crate::procedural_texture::*;
 use crate::ray_tracer::*;
+use crate::rgb::*;
+use crate::texture::*;
 use crate::vector_arithmetic::*;
 
 use wasm_bindgen::prelude::*;
@@ -20,7 +49,43 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 pub fn render_scene() {
     console::log_1(&"Creating spheres, planes etc. for the scene...".into());
     let scene = create_test_scene();
+    render_on_canvas(&scene);
+}
+
+/// Renders a given frame of the test animation, frames after the last one start over.
+#[wasm_bindgen]
+pub fn render_frame(frame: u32) {
+    let mut scene = create_test_scene();
+    let animation = create_test_animation();
+    let frame = frame % animation.frame_count();
+    console::log_1(&format!("Animating the scene to frame {}...", frame).into());
+    animation.apply(&mut scene, animation.frame_time(frame));
+    render_on_canvas(&scene);
+}
+
+/// Renders all frames of the test animation and encodes them as an animated "gif" or "apng" file.
+#[wasm_bindgen]
+pub fn render_animation(format: &str, width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
+    let animation = create_test_animation();
+    let mut frames = vec![];
+    for frame in 0..animation.frame_count() {
+        console::log_1(&format!("Rendering frame {}...", frame).into());
+        let mut scene = create_test_scene();
+        animation.apply(&mut scene, animation.frame_time(frame));
+        frames.push(render_rgba(&scene, width, height).0);
+    }
 
+    let fps = animation.frames_per_second;
+    let encoded = match format {
+        "gif" => encode_gif(width, height, &frames, fps, true),
+        "apng" => encode_apng(width, height, &frames, fps),
+        _ => Err(format!("Unknown animation format: {}", format)),
+    };
+    return encoded.map_err(|e| JsValue::from_str(&e));
+}
+
+/// Renders a given scene on the result canvas.
+fn render_on_canvas(scene: &Scene) {
     console::log_1(&"Looking up the canvas DOM object...".into());
     let document = web_sys::window().unwrap().document().unwrap();
     let canvas: web_sys::HtmlCanvasElement = document
@@ -39,10 +104,11 @@ pub fn render_scene() {
 }
 
 /// A test scene.
-fn create_test_scene() -> Scene {
+pub fn create_test_scene() -> Scene {
     // spheres and planes:
     let test_spheres = create_test_spheres();
     let test_planes = create_test_planes();
+    let test_csg_objects = create_test_csg_objects();
 
     // a light source:
     let light1 = Light {
@@ -56,7 +122,81 @@ fn create_test_scene() -> Scene {
     return Scene {
         spheres: test_spheres,
         planes: test_planes,
+        cuboids: vec![],
+        csg_objects: test_csg_objects,
+        sdf_objects: vec![],
+        heightfields: vec![],
+        quadrics: vec![],
         light: light1,
+        // the scene's y axis points down:
+        camera: Camera {
+            up: Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            ..Camera::default()
+        },
+        background: Background::Solid(BLACK_RGB),
+        settings: RenderSettings::default(),
+    };
+}
+
+/// A test animation of the test scene: the red sphere rolls to the middle and back while the green
+/// one turns blue and the camera moves closer.
+pub fn create_test_animation() -> Animation {
+    let ease_in_out = Interpolation::CubicBezier {
+        x1: 0.42,
+        y1: 0.0,
+        x2: 0.58,
+        y2: 1.0,
+    };
+    let red_sphere_x = |time: f64, x: f64| Keyframe {
+        time,
+        value: Vector3 {
+            x,
+            y: -40.0,
+            z: 250.0,
+        },
+        interpolation: ease_in_out,
+    };
+    let green_sphere_color = |time: f64, value: Color| Keyframe {
+        time,
+        value,
+        interpolation: Interpolation::Linear,
+    };
+    let camera_z = |time: f64, z: f64| Keyframe {
+        time,
+        value: Vector3 { x: 0.0, y: 0.0, z },
+        interpolation: Interpolation::Linear,
+    };
+
+    return Animation {
+        properties: vec![
+            AnimatedProperty::SpherePosition(
+                0,
+                Track {
+                    keyframes: vec![
+                        red_sphere_x(0.0, 50.0),
+                        red_sphere_x(1.0, 0.0),
+                        red_sphere_x(2.0, 50.0),
+                    ],
+                },
+            ),
+            AnimatedProperty::SphereColor(
+                3,
+                Track {
+                    keyframes: vec![
+                        green_sphere_color(0.0, Color { r: 0, g: 255, b: 0 }),
+                        green_sphere_color(2.0, Color { r: 0, g: 0, b: 255 }),
+                    ],
+                },
+            ),
+            AnimatedProperty::CameraPosition(Track {
+                keyframes: vec![camera_z(0.0, 0.0), camera_z(2.0, 40.0)],
+            }),
+        ],
+        frames_per_second: 24.0,
     };
 }
 
@@ -64,57 +204,114 @@ fn create_test_scene() -> Scene {
 fn create_test_spheres() -> Vec<Sphere> {
     let sphere1 = Sphere {
         pos: Vector3 {
-            x: -50.0,
+            x: 50.0,
             y: -40.0,
             z: 250.0,
         },
         r: 25.0,
-        color: Color { r: 255, g: 0, b: 0 },
-        reflect: true,
+        velocity: ZERO_VECTOR3,
+        material: Material::new(Color { r: 255, g: 0, b: 0 }, true),
     };
 
     let sphere2 = Sphere {
         pos: Vector3 {
-            x: 35.0,
+            x: -35.0,
             y: -40.0,
             z: 300.0,
         },
         r: 50.0,
-        color: Color {
-            r: 255,
-            g: 255,
-            b: 255,
-        },
-        reflect: true,
+        velocity: ZERO_VECTOR3,
+        material: Material::new(
+            Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+            true,
+        ),
     };
 
     let sphere3 = Sphere {
         pos: Vector3 {
-            x: -40.0,
+            x: 40.0,
             y: 30.0,
             z: 300.0,
         },
         r: 25.0,
-        color: Color {
-            r: 50,
-            g: 50,
-            b: 255,
-        },
-        reflect: true,
+        velocity: ZERO_VECTOR3,
+        material: Material::new(
+            Color {
+                r: 50,
+                g: 50,
+                b: 255,
+            },
+            true,
+        ),
     };
 
     let sphere4 = Sphere {
         pos: Vector3 {
-            x: 50.0,
+            x: -50.0,
             y: 30.0,
             z: 200.0,
         },
         r: 30.0,
-        color: Color { r: 0, g: 255, b: 0 },
-        reflect: true,
+        velocity: ZERO_VECTOR3,
+        material: Material::new(Color { r: 0, g: 255, b: 0 }, true),
     };
 
-    return vec![sphere1, sphere2, sphere3, sphere4];
+    // a glowing ball on the floor that lights its surroundings:
+    let lamp = Sphere {
+        pos: Vector3 {
+            x: 20.0,
+            y: 50.0,
+            z: 200.0,
+        },
+        r: 10.0,
+        velocity: ZERO_VECTOR3,
+        material: Material {
+            emission: Some(Rgb {
+                r: 6.0,
+                g: 3.5,
+                b: 1.2,
+            }),
+            ..Material::new(
+                Color {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+                false,
+            )
+        },
+    };
+
+    // a slightly brushed gold ball on the floor:
+    let gold = Sphere {
+        pos: Vector3 {
+            x: -15.0,
+            y: 45.0,
+            z: 170.0,
+        },
+        r: 15.0,
+        velocity: ZERO_VECTOR3,
+        material: Material {
+            pbr: Some(MetallicRoughness {
+                metallic: 1.0,
+                roughness: 0.35,
+            }),
+            ..Material::new(
+                Color {
+                    r: 255,
+                    g: 196,
+                    b: 87,
+                },
+                true,
+            )
+        },
+    };
+
+    return vec![sphere1, sphere2, sphere3, sphere4, lamp, gold];
 }
 
 /// Some test planes.
@@ -125,6 +322,17 @@ fn create_test_planes() -> Vec<Plane> {
         b: 200,
     };
 
+    // a checkerboard floor:
+    let checkerboard = Texture::Pattern {
+        pattern: Pattern::Checker { size: 40.0 },
+        space: TextureSpace::Uv,
+        low: Box::new(Texture::Constant(light_grey)),
+        high: Box::new(Texture::Constant(Color {
+            r: 90,
+            g: 90,
+            b: 90,
+        })),
+    };
     let plane1 = Plane {
         n: Vector3 {
             x: 0.0,
@@ -132,8 +340,17 @@ fn create_test_planes() -> Vec<Plane> {
             z: 0.0,
         },
         d: 60.0,
-        color: light_grey,
-        reflect: false,
+        material: Material {
+            texture: Some(checkerboard),
+            ..Material::new(
+                Color {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+                false,
+            )
+        },
     };
 
     let plane2 = Plane {
@@ -143,30 +360,27 @@ fn create_test_planes() -> Vec<Plane> {
             z: -1.0,
         },
         d: 400.0,
-        color: light_grey,
-        reflect: false,
+        material: Material::new(light_grey, false),
     };
 
     let plane3 = Plane {
         n: Vector3 {
-            x: 1.0,
+            x: -1.0,
             y: 0.0,
             z: 0.0,
         },
         d: 110.0,
-        color: light_grey,
-        reflect: false,
+        material: Material::new(light_grey, false),
     };
 
     let plane4 = Plane {
         n: Vector3 {
-            x: -1.0,
+            x: 1.0,
             y: 0.0,
             z: 0.0,
         },
         d: 120.0,
-        color: light_grey,
-        reflect: false,
+        material: Material::new(light_grey, false),
     };
 
     let plane5 = Plane {
@@ -176,8 +390,7 @@ fn create_test_planes() -> Vec<Plane> {
             z: 0.0,
         },
         d: 110.0,
-        color: light_grey,
-        reflect: false,
+        material: Material::new(light_grey, false),
     };
 
     let plane6 = Plane {
@@ -187,9 +400,78 @@ fn create_test_planes() -> Vec<Plane> {
             z: 1.0,
         },
         d: 5.0,
-        color: light_grey,
-        reflect: false,
+        material: Material::new(light_grey, false),
     };
 
     return vec![plane1, plane2, plane3, plane4, plane5, plane6];
 }
+
+/// Some test CSG objects.
+fn create_test_csg_objects() -> Vec<Csg> {
+    let orange = Color {
+        r: 255,
+        g: 140,
+        b: 0,
+    };
+
+    // a cube with rounded edges:
+    let rounded_cube = Csg::Node {
+        operation: CsgOperation::Intersection,
+        left: Box::new(Csg::Cuboid(Cuboid {
+            min: Vector3 {
+                x: -15.0,
+                y: 5.0,
+                z: 215.0,
+            },
+            max: Vector3 {
+                x: 15.0,
+                y: 35.0,
+                z: 245.0,
+            },
+            velocity: ZERO_VECTOR3,
+            material: Material::new(orange, false),
+        })),
+        right: Box::new(Csg::Sphere(Sphere {
+            pos: Vector3 {
+                x: 0.0,
+                y: 20.0,
+                z: 230.0,
+            },
+            r: 20.0,
+            velocity: ZERO_VECTOR3,
+            material: Material::new(orange, false),
+        })),
+    };
+
+    // ...with a dimple carved into its front and its right side shaved off:
+    let cuts = Csg::Node {
+        operation: CsgOperation::Union,
+        left: Box::new(Csg::Sphere(Sphere {
+            pos: Vector3 {
+                x: 0.0,
+                y: 20.0,
+                z: 213.0,
+            },
+            r: 8.0,
+            velocity: ZERO_VECTOR3,
+            material: Material::new(orange, false),
+        })),
+        right: Box::new(Csg::Plane(Plane {
+            n: Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            d: 12.0,
+            material: Material::new(orange, false),
+        })),
+    };
+
+    let dice = Csg::Node {
+        operation: CsgOperation::Difference,
+        left: Box::new(rounded_cube),
+        right: Box::new(cuts),
+    };
+
+    return vec![dice];
+}
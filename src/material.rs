@@ -0,0 +1,110 @@
+//! Materials that describe the appearance of a primitive's surface.
+
+use crate::brdf::*;
+use crate::normal_mapping::*;
+use crate::ray_tracer::*;
+use crate::rgb::*;
+use crate::texture::*;
+use crate::vector_arithmetic::*;
+
+/// The appearance of a surface.
+#[derive(Clone, PartialEq)]
+pub struct Material {
+    pub color: Color,
+    pub reflect: bool,
+    /// Spreads reflections into a cone (0.0 is a perfect mirror, 1.0 is very rough).
+    pub roughness: f64,
+    /// A texture for the diffuse color, it is multiplied with the material's color.
+    pub texture: Option<Texture>,
+    /// A tangent-space normal map that replaces the surface's shading normal.
+    pub normal_map: Option<Texture>,
+    /// A height map whose slopes tilt the surface's shading normal.
+    pub bump_map: Option<BumpMap>,
+    /// Shades the surface with the metallic-roughness model instead of the Lambertian one.
+    pub pbr: Option<MetallicRoughness>,
+    /// The radiance the surface emits. Only emissive spheres and cuboids light other objects, other
+    /// primitives (including the solids of CSG objects) merely glow where they are seen.
+    pub emission: Option<Rgb>,
+}
+
+impl Material {
+    /// Creates an untextured material.
+    pub fn new(color: Color, reflect: bool) -> Material {
+        return Material {
+            color,
+            reflect,
+            roughness: 0.0,
+            texture: None,
+            normal_map: None,
+            bump_map: None,
+            pbr: None,
+            emission: None,
+        };
+    }
+
+    /// The roughness that spreads the surface's reflections, metallic-roughness materials use their own.
+    pub fn reflection_roughness(&self) -> f64 {
+        return match &self.pbr {
+            Some(pbr) => pbr.roughness,
+            None => self.roughness,
+        };
+    }
+
+    /// Computes the diffuse color at a given surface point with given texture coordinates,
+    /// filtering image textures over a given footprint.
+    pub fn diffuse_color(&self, uv: &Uv, point: &Vector3, footprint: &UvFootprint) -> Color {
+        return match &self.texture {
+            Some(texture) => multiply_colors(
+                &self.color,
+                &texture.sample_filtered(&uv, &point, &footprint),
+            ),
+            None => self.color,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_diffuse_color() {
+        let red = Color { r: 255, g: 0, b: 0 };
+        let uv = Uv { u: 0.5, v: 0.5 };
+        let p = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        assert_eq!(
+            Material::new(red, false).diffuse_color(&uv, &p, &POINT_FOOTPRINT),
+            red
+        );
+
+        let grey = Color {
+            r: 128,
+            g: 128,
+            b: 128,
+        };
+        let textured = Material {
+            texture: Some(Texture::Image {
+                image: Rc::new(
+                    MipMap::new(ImageTexture {
+                        width: 1,
+                        height: 1,
+                        texels: vec![grey],
+                        wrap: WrapMode::Repeat,
+                    })
+                    .unwrap(),
+                ),
+                uv_scale: 1.0,
+            }),
+            ..Material::new(red, false)
+        };
+        assert_eq!(
+            textured.diffuse_color(&uv, &p, &POINT_FOOTPRINT),
+            Color { r: 128, g: 0, b: 0 }
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Normal and bump mapping: textures that perturb a surface's shading normal.
+
+use crate::material::*;
+use crate::texture::*;
+use crate::vector_arithmetic::*;
+
+/// The step (in texture coordinates) of the central differences that compute a bump map's slope,
+/// large enough to span several levels of an 8-bit texture.
+const BUMP_DELTA: f64 = 0.01;
+
+/// A height map, the luminance of its texture is scaled by strength to get a height.
+#[derive(Clone, PartialEq)]
+pub struct BumpMap {
+    pub height: Texture,
+    pub strength: f64,
+}
+
+/// Perturbs a surface's normal by its material's normal map and bump map.
+/// dpdu and dpdv are the derivatives of the surface point with respect to the texture coordinates.
+pub fn compute_shading_normal(
+    material: &Material,
+    normal: &Vector3,
+    dpdu: &Vector3,
+    dpdv: &Vector3,
+    uv: &Uv,
+    point: &Vector3,
+) -> Vector3 {
+    let mut n = *normal;
+    if let Some(normal_map) = &material.normal_map {
+        n = apply_normal_map(&normal_map, &n, &dpdu, &dpdv, &uv, &point);
+    }
+    if let Some(bump_map) = &material.bump_map {
+        n = apply_bump_map(&bump_map, &n, &dpdu, &dpdv, &uv, &point);
+    }
+    return n;
+}
+
+/// Computes an orthonormal tangent and bitangent for a given normal, the bitangent points along dpdv.
+fn tangent_frame(normal: &Vector3, dpdu: &Vector3, dpdv: &Vector3) -> (Vector3, Vector3) {
+    // remove the normal's share of dpdu (Gram-Schmidt), degenerate frames fall back to any tangent:
+    let projected = difference(&dpdu, &scale_vector(dot_product(&normal, &dpdu), &normal));
+    let tangent = match try_normalize(&projected) {
+        Some(tangent) => tangent,
+        None => tangent_basis(&normal).0,
+    };
+    let bitangent = cross_product(&normal, &tangent);
+    return if dot_product(&bitangent, &dpdv) < 0.0 {
+        (tangent, scale_vector(-1.0, &bitangent))
+    } else {
+        (tangent, bitangent)
+    };
+}
+
+/// Replaces a normal by the (tangent-space) normal from a normal map, whose RGB values encode XYZ in [-1.0, 1.0].
+/// The green channel points along the direction of increasing v.
+fn apply_normal_map(
+    normal_map: &Texture,
+    normal: &Vector3,
+    dpdu: &Vector3,
+    dpdv: &Vector3,
+    uv: &Uv,
+    point: &Vector3,
+) -> Vector3 {
+    let c = normal_map.sample(&uv, &point);
+    let (tangent, bitangent) = tangent_frame(&normal, &dpdu, &dpdv);
+    let x = 2.0 * (c.r as f64) / 255.0 - 1.0;
+    let y = 2.0 * (c.g as f64) / 255.0 - 1.0;
+    let z = 2.0 * (c.b as f64) / 255.0 - 1.0;
+
+    let n = sum(
+        &sum(&scale_vector(x, &tangent), &scale_vector(y, &bitangent)),
+        &scale_vector(z, &normal),
+    );
+    return match try_normalize(&n) {
+        Some(n) => n,
+        None => *normal,
+    };
+}
+
+/// Tilts a normal as if the surface was displaced along it by a bump map's heights.
+fn apply_bump_map(
+    bump_map: &BumpMap,
+    normal: &Vector3,
+    dpdu: &Vector3,
+    dpdv: &Vector3,
+    uv: &Uv,
+    point: &Vector3,
+) -> Vector3 {
+    let hu = bump_slope(&bump_map, &uv, &point, 1.0, 0.0, &dpdu);
+    let hv = bump_slope(&bump_map, &uv, &point, 0.0, 1.0, &dpdv);
+
+    // the derivatives of the displaced surface span its new tangent plane:
+    let displaced_dpdu = sum(&dpdu, &scale_vector(hu, &normal));
+    let displaced_dpdv = sum(&dpdv, &scale_vector(hv, &normal));
+    let n = match try_normalize(&cross_product(&displaced_dpdu, &displaced_dpdv)) {
+        Some(n) => n,
+        None => return *normal,
+    };
+
+    // dpdu and dpdv don't necessarily form a right-handed frame with the normal:
+    let unperturbed = cross_product(&dpdu, &dpdv);
+    return if dot_product(&unperturbed, &normal) < 0.0 {
+        scale_vector(-1.0, &n)
+    } else {
+        n
+    };
+}
+
+/// Computes a bump map's slope along direction (du, dv) in texture space, which moves the point along dp.
+fn bump_slope(bump_map: &BumpMap, uv: &Uv, point: &Vector3, du: f64, dv: f64, dp: &Vector3) -> f64 {
+    let uv_ahead = Uv {
+        u: uv.u + du * BUMP_DELTA,
+        v: uv.v + dv * BUMP_DELTA,
+    };
+    let uv_behind = Uv {
+        u: uv.u - du * BUMP_DELTA,
+        v: uv.v - dv * BUMP_DELTA,
+    };
+    let point_ahead = sum(&point, &scale_vector(BUMP_DELTA, &dp));
+    let point_behind = difference(&point, &scale_vector(BUMP_DELTA, &dp));
+    let ahead = bump_height(&bump_map, &uv_ahead, &point_ahead);
+    let behind = bump_height(&bump_map, &uv_behind, &point_behind);
+    return (ahead - behind) / (2.0 * BUMP_DELTA);
+}
+
+fn bump_height(bump_map: &BumpMap, uv: &Uv, point: &Vector3) -> f64 {
+    let c = bump_map.height.sample(&uv, &point);
+    let luminance = (0.2126 * c.r as f64 + 0.7152 * c.g as f64 + 0.0722 * c.b as f64) / 255.0;
+    return bump_map.strength * luminance;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracer::*;
+    use std::rc::Rc;
+
+    const UP: Vector3 = Vector3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+    const DPDU: Vector3 = Vector3 {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+    };
+    const DPDV: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+    };
+
+    fn test_material() -> Material {
+        return Material::new(
+            Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+            false,
+        );
+    }
+
+    fn assert_close(u: &Vector3, v: &Vector3) {
+        // bump slopes are computed from 8-bit texels:
+        assert!(distance(&u, &v) < 0.05, "{:?} != {:?}", u, v);
+    }
+
+    #[test]
+    fn test_normal_map() {
+        let uv = Uv { u: 0.5, v: 0.5 };
+
+        // a flat normal map leaves the normal as it is:
+        let flat = Material {
+            normal_map: Some(Texture::Constant(Color {
+                r: 128,
+                g: 128,
+                b: 255,
+            })),
+            ..test_material()
+        };
+        let n = compute_shading_normal(&flat, &UP, &DPDU, &DPDV, &uv, &UP);
+        assert_close(&n, &UP);
+
+        // ...a map that points along the tangent tilts it:
+        let tilted = Material {
+            normal_map: Some(Texture::Constant(Color {
+                r: 255,
+                g: 128,
+                b: 255,
+            })),
+            ..test_material()
+        };
+        let n = compute_shading_normal(&tilted, &UP, &DPDU, &DPDV, &uv, &UP);
+        let e = normalize(&Vector3 {
+            x: 1.0,
+            y: 1.0,
+            z: 0.0,
+        });
+        assert_close(&n, &e);
+    }
+
+    #[test]
+    fn test_bump_map() {
+        // a ramp from black to white along u:
+        let ramp = Texture::Image {
+            image: Rc::new(
+                MipMap::new(ImageTexture {
+                    width: 2,
+                    height: 1,
+                    texels: vec![
+                        Color { r: 0, g: 0, b: 0 },
+                        Color {
+                            r: 255,
+                            g: 255,
+                            b: 255,
+                        },
+                    ],
+                    wrap: WrapMode::ClampToEdge,
+                })
+                .unwrap(),
+            ),
+            uv_scale: 1.0,
+        };
+        let bumpy = Material {
+            bump_map: Some(BumpMap {
+                height: ramp,
+                strength: 0.5,
+            }),
+            ..test_material()
+        };
+
+        // the surface rises along u, so its normal tilts backwards:
+        let n = compute_shading_normal(&bumpy, &UP, &DPDU, &DPDV, &Uv { u: 0.5, v: 0.5 }, &UP);
+        let e = normalize(&Vector3 {
+            x: -1.0,
+            y: 1.0,
+            z: 0.0,
+        });
+        assert_close(&n, &e);
+
+        // ...but stays flat where the ramp is clamped:
+        let n = compute_shading_normal(&bumpy, &UP, &DPDU, &DPDV, &Uv { u: 0.1, v: 0.5 }, &UP);
+        assert_close(&n, &UP);
+    }
+}
@@ -0,0 +1,231 @@
+//! Procedural patterns (checker, stripes, Perlin noise, turbulence, marble and wood) for textures.
+
+use crate::vector_arithmetic::*;
+use std::f64::consts::PI;
+
+/// A procedural pattern, it maps a point to a value in [0.0, 1.0].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// Alternating cubes (or squares, in UV space) of a given size.
+    Checker { size: f64 },
+    /// Alternating slabs of a given width along the x-axis (or u).
+    Stripes { width: f64 },
+    /// Perlin noise, scale is the size of a noise cell.
+    Noise { scale: f64 },
+    /// The sum of a number of octaves of absolute Perlin noise.
+    Turbulence { scale: f64, octaves: u32 },
+    /// Veins along the x-axis (or u), distorted by turbulence of a given strength.
+    Marble {
+        scale: f64,
+        octaves: u32,
+        turbulence: f64,
+    },
+    /// Growth rings around the y-axis (or v), distorted by turbulence of a given strength.
+    Wood {
+        ring_width: f64,
+        octaves: u32,
+        turbulence: f64,
+    },
+}
+
+impl Pattern {
+    /// Evaluates the pattern at a given point.
+    pub fn evaluate(&self, p: &Vector3) -> f64 {
+        return match *self {
+            Pattern::Checker { size } => {
+                let q = scale_vector(1.0 / size, &p);
+                let n = q.x.floor() + q.y.floor() + q.z.floor();
+                n.rem_euclid(2.0)
+            }
+            Pattern::Stripes { width } => (p.x / width).floor().rem_euclid(2.0),
+            Pattern::Noise { scale } => 0.5 * (perlin_noise(&scale_vector(1.0 / scale, &p)) + 1.0),
+            Pattern::Turbulence { scale, octaves } => {
+                f64::min(turbulence(&scale_vector(1.0 / scale, &p), octaves), 1.0)
+            }
+            Pattern::Marble {
+                scale,
+                octaves,
+                turbulence: strength,
+            } => {
+                let q = scale_vector(1.0 / scale, &p);
+                0.5 * (1.0 + (PI * q.x + strength * turbulence(&q, octaves)).sin())
+            }
+            Pattern::Wood {
+                ring_width,
+                octaves,
+                turbulence: strength,
+            } => {
+                let q = scale_vector(1.0 / ring_width, &p);
+                let rings = (q.x * q.x + q.z * q.z).sqrt() + strength * turbulence(&q, octaves);
+                rings - rings.floor()
+            }
+        };
+    }
+}
+
+/// Sums a number of octaves of absolute Perlin noise, each with twice the frequency and half the amplitude.
+pub fn turbulence(p: &Vector3, octaves: u32) -> f64 {
+    let mut t = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves {
+        t += perlin_noise(&scale_vector(frequency, &p)).abs() / frequency;
+        frequency *= 2.0;
+    }
+    return t;
+}
+
+/// Computes (improved) Perlin noise at a given point, the noise lies in [-1.0, 1.0] and is 0.0 at integer points.
+pub fn perlin_noise(p: &Vector3) -> f64 {
+    let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let xi = (fx as i64 & 255) as usize;
+    let yi = (fy as i64 & 255) as usize;
+    let zi = (fz as i64 & 255) as usize;
+    let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    // hash the coordinates of the cube's eight corners:
+    let a = perm(xi) + yi;
+    let aa = perm(a) + zi;
+    let ab = perm(a + 1) + zi;
+    let b = perm(xi + 1) + yi;
+    let ba = perm(b) + zi;
+    let bb = perm(b + 1) + zi;
+
+    return lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, grad(perm(aa), x, y, z), grad(perm(ba), x - 1.0, y, z)),
+            lerp(
+                u,
+                grad(perm(ab), x, y - 1.0, z),
+                grad(perm(bb), x - 1.0, y - 1.0, z),
+            ),
+        ),
+        lerp(
+            v,
+            lerp(
+                u,
+                grad(perm(aa + 1), x, y, z - 1.0),
+                grad(perm(ba + 1), x - 1.0, y, z - 1.0),
+            ),
+            lerp(
+                u,
+                grad(perm(ab + 1), x, y - 1.0, z - 1.0),
+                grad(perm(bb + 1), x - 1.0, y - 1.0, z - 1.0),
+            ),
+        ),
+    );
+}
+
+fn fade(t: f64) -> f64 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    return a + t * (b - a);
+}
+
+/// Computes the dot product of (x, y, z) with one of twelve gradient directions, picked by a hash.
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    let u = if h & 1 == 0 { u } else { -u };
+    let v = if h & 2 == 0 { v } else { -v };
+    return u + v;
+}
+
+fn perm(i: usize) -> usize {
+    return PERMUTATION[i & 255] as usize;
+}
+
+/// Ken Perlin's reference permutation of 0..255.
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64, z: f64) -> Vector3 {
+        return Vector3 { x, y, z };
+    }
+
+    #[test]
+    fn test_checker_and_stripes() {
+        let checker = Pattern::Checker { size: 10.0 };
+        assert_eq!(checker.evaluate(&point(5.0, 5.0, 5.0)), 0.0);
+        assert_eq!(checker.evaluate(&point(15.0, 5.0, 5.0)), 1.0);
+        assert_eq!(checker.evaluate(&point(-5.0, 5.0, 5.0)), 1.0);
+        assert_eq!(checker.evaluate(&point(15.0, 15.0, 5.0)), 0.0);
+
+        let stripes = Pattern::Stripes { width: 2.0 };
+        assert_eq!(stripes.evaluate(&point(1.0, 7.0, 3.0)), 0.0);
+        assert_eq!(stripes.evaluate(&point(3.0, 1.0, 9.0)), 1.0);
+        assert_eq!(stripes.evaluate(&point(-1.0, 1.0, 9.0)), 1.0);
+    }
+
+    #[test]
+    fn test_perlin_noise() {
+        // noise vanishes at the lattice points, but not in between:
+        assert_eq!(perlin_noise(&point(3.0, -2.0, 7.0)), 0.0);
+        assert_ne!(perlin_noise(&point(3.5, -2.25, 7.75)), 0.0);
+
+        // ...and is continuous and bounded:
+        let a = perlin_noise(&point(1.3, 2.7, 0.4));
+        let b = perlin_noise(&point(1.3001, 2.7, 0.4));
+        assert!((a - b).abs() < 0.001);
+        for i in 0..100 {
+            let n = perlin_noise(&point(i as f64 * 0.37, i as f64 * 0.11, i as f64 * 0.73));
+            assert!((-1.0..=1.0).contains(&n));
+        }
+    }
+
+    #[test]
+    fn test_patterns_are_bounded() {
+        let patterns = [
+            Pattern::Noise { scale: 3.0 },
+            Pattern::Turbulence {
+                scale: 3.0,
+                octaves: 4,
+            },
+            Pattern::Marble {
+                scale: 5.0,
+                octaves: 4,
+                turbulence: 5.0,
+            },
+            Pattern::Wood {
+                ring_width: 2.0,
+                octaves: 2,
+                turbulence: 0.5,
+            },
+        ];
+        for pattern in patterns.iter() {
+            for i in 0..100 {
+                let p = point(i as f64 * 1.37, i as f64 * -0.71, i as f64 * 0.53);
+                let t = pattern.evaluate(&p);
+                assert!((0.0..=1.0).contains(&t), "{:?} at {:?}: {}", pattern, p, t);
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Quadric surfaces such as ellipsoids, paraboloids and hyperboloids.
+
+use crate::material::*;
+use crate::ray_tracer::*;
+use crate::vector_arithmetic::*;
+
+/// The surface a x² + b y² + c z² + d xy + e xz + f yz + g x + h y + i z + j = 0,
+/// optionally clipped by an axis-aligned bounding box.
+#[derive(PartialEq)]
+pub struct Quadric {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64,
+    pub g: f64,
+    pub h: f64,
+    pub i: f64,
+    pub j: f64,
+    /// The minimum and maximum corner of the box outside of which the surface is cut away.
+    pub bounds: Option<(Vector3, Vector3)>,
+    pub material: Material,
+}
+
+impl Quadric {
+    /// Creates an ellipsoid with a given center and radii along the three axes.
+    pub fn ellipsoid(center: &Vector3, radii: &Vector3, material: Material) -> Quadric {
+        let (ax, ay, az) = (
+            1.0 / (radii.x * radii.x),
+            1.0 / (radii.y * radii.y),
+            1.0 / (radii.z * radii.z),
+        );
+        return Quadric {
+            a: ax,
+            b: ay,
+            c: az,
+            d: 0.0,
+            e: 0.0,
+            f: 0.0,
+            g: -2.0 * center.x * ax,
+            h: -2.0 * center.y * ay,
+            i: -2.0 * center.z * az,
+            j: center.x * center.x * ax + center.y * center.y * ay + center.z * center.z * az - 1.0,
+            bounds: None,
+            material,
+        };
+    }
+
+    /// Creates a paraboloid that opens along the y-axis, with a given vertex and focal length
+    /// (a negative focal length opens it downwards).
+    pub fn paraboloid(vertex: &Vector3, focal_length: f64, material: Material) -> Quadric {
+        let s = 1.0 / (4.0 * focal_length);
+        return Quadric {
+            a: s,
+            b: 0.0,
+            c: s,
+            d: 0.0,
+            e: 0.0,
+            f: 0.0,
+            g: -2.0 * vertex.x * s,
+            h: -1.0,
+            i: -2.0 * vertex.z * s,
+            j: (vertex.x * vertex.x + vertex.z * vertex.z) * s + vertex.y,
+            bounds: None,
+            material,
+        };
+    }
+
+    /// Creates a hyperboloid around the y-axis: (x² + z²) / radius² - y² / height² = 1 for one
+    /// sheet (with a waist of the given radius) or -1 for two sheets, moved to a given center.
+    pub fn hyperboloid(
+        center: &Vector3,
+        radius: f64,
+        height: f64,
+        one_sheet: bool,
+        material: Material,
+    ) -> Quadric {
+        let s = 1.0 / (radius * radius);
+        let t = 1.0 / (height * height);
+        let rhs = if one_sheet { 1.0 } else { -1.0 };
+        return Quadric {
+            a: s,
+            b: -t,
+            c: s,
+            d: 0.0,
+            e: 0.0,
+            f: 0.0,
+            g: -2.0 * center.x * s,
+            h: 2.0 * center.y * t,
+            i: -2.0 * center.z * s,
+            j: (center.x * center.x + center.z * center.z) * s - center.y * center.y * t - rhs,
+            bounds: None,
+            material,
+        };
+    }
+
+    /// Computes the ray parameter k of the closest hit in front of the ray's origin (HUGE_VALUE if there is none).
+    pub fn intersect(&self, r: &Ray) -> f64 {
+        let (o, dir) = (&r.origin, &r.direction);
+        let qa = self.a * dir.x * dir.x
+            + self.b * dir.y * dir.y
+            + self.c * dir.z * dir.z
+            + self.d * dir.x * dir.y
+            + self.e * dir.x * dir.z
+            + self.f * dir.y * dir.z;
+        let qb = 2.0 * (self.a * o.x * dir.x + self.b * o.y * dir.y + self.c * o.z * dir.z)
+            + self.d * (o.x * dir.y + o.y * dir.x)
+            + self.e * (o.x * dir.z + o.z * dir.x)
+            + self.f * (o.y * dir.z + o.z * dir.y)
+            + self.g * dir.x
+            + self.h * dir.y
+            + self.i * dir.z;
+        let qc = self.evaluate(&o);
+
+        let roots = if qa.abs() < 1e-12 {
+            // the ray runs parallel to the quadric's axis, the equation is linear:
+            if qb == 0.0 {
+                return HUGE_VALUE;
+            }
+            let k = -qc / qb;
+            (k, k)
+        } else {
+            match solve_quadratic(qa, qb, qc) {
+                Some(roots) => roots,
+                None => return HUGE_VALUE,
+            }
+        };
+
+        for k in [roots.0, roots.1].iter() {
+            if *k >= TINY_VALUE && self.contains(&sum(&o, &scale_vector(*k, &dir))) {
+                return *k;
+            }
+        }
+        return HUGE_VALUE;
+    }
+
+    /// Evaluates the quadric's equation at a given point.
+    fn evaluate(&self, p: &Vector3) -> f64 {
+        return self.a * p.x * p.x
+            + self.b * p.y * p.y
+            + self.c * p.z * p.z
+            + self.d * p.x * p.y
+            + self.e * p.x * p.z
+            + self.f * p.y * p.z
+            + self.g * p.x
+            + self.h * p.y
+            + self.i * p.z
+            + self.j;
+    }
+
+    /// Checks whether a given point lies within the quadric's bounding box.
+    fn contains(&self, p: &Vector3) -> bool {
+        return match &self.bounds {
+            Some((min, max)) => {
+                p.x >= min.x
+                    && p.x <= max.x
+                    && p.y >= min.y
+                    && p.y <= max.y
+                    && p.z >= min.z
+                    && p.z <= max.z
+            }
+            None => true,
+        };
+    }
+
+    /// Computes the normal at a given point as the (normalized) gradient of the quadric.
+    pub fn compute_normal(&self, p: &Vector3) -> Vector3 {
+        let gradient = Vector3 {
+            x: 2.0 * self.a * p.x + self.d * p.y + self.e * p.z + self.g,
+            y: 2.0 * self.b * p.y + self.d * p.x + self.f * p.z + self.h,
+            z: 2.0 * self.c * p.z + self.e * p.x + self.f * p.y + self.i,
+        };
+        return normalize(&gradient);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    fn test_ray(origin: Vector3) -> Ray {
+        return Ray {
+            origin,
+            direction: Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+    }
+
+    #[test]
+    fn test_ellipsoid() {
+        let center = Vector3 {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+        let ellipsoid = Quadric::ellipsoid(
+            &center,
+            &Vector3 {
+                x: 2.0,
+                y: 4.0,
+                z: 2.0,
+            },
+            Material::new(WHITE, false),
+        );
+
+        let r = test_ray(Vector3 {
+            x: 1.0,
+            y: 10.0,
+            z: 3.0,
+        });
+        assert!((ellipsoid.intersect(&r) - 4.0).abs() < 0.000001);
+
+        let n = ellipsoid.compute_normal(&Vector3 {
+            x: 3.0,
+            y: 2.0,
+            z: 3.0,
+        });
+        assert_eq!(
+            n,
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_clipped_paraboloid() {
+        // a parabolic dish with a focal length of 5, cut off at a height of 20:
+        let mut paraboloid = Quadric::paraboloid(
+            &Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            5.0,
+            Material::new(WHITE, true),
+        );
+        paraboloid.bounds = Some((
+            Vector3 {
+                x: -100.0,
+                y: -100.0,
+                z: -100.0,
+            },
+            Vector3 {
+                x: 100.0,
+                y: 20.0,
+                z: 100.0,
+            },
+        ));
+
+        // y = (x² + z²) / 20:
+        let r = test_ray(Vector3 {
+            x: 10.0,
+            y: 30.0,
+            z: 0.0,
+        });
+        assert!((paraboloid.intersect(&r) - 25.0).abs() < 0.000001);
+
+        // outside of the bounding box, the ray passes the dish:
+        let r = test_ray(Vector3 {
+            x: 30.0,
+            y: 30.0,
+            z: 0.0,
+        });
+        assert_eq!(paraboloid.intersect(&r), HUGE_VALUE);
+
+        // a ray that passes the dish's outside above its rim hits its inside:
+        let r = Ray {
+            origin: Vector3 {
+                x: -40.0,
+                y: 50.0,
+                z: 0.0,
+            },
+            direction: Vector3 {
+                x: 1.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        let k = paraboloid.intersect(&r);
+        assert!((k - (30.0 + 300.0f64.sqrt())).abs() < 0.000001);
+    }
+
+    #[test]
+    fn test_hyperboloid() {
+        let origin = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let one_sheet = Quadric::hyperboloid(&origin, 5.0, 5.0, true, Material::new(WHITE, false));
+        let r = Ray {
+            origin: Vector3 {
+                x: -20.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            direction: Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        assert!((one_sheet.intersect(&r) - 15.0).abs() < 0.000001);
+
+        let two_sheets =
+            Quadric::hyperboloid(&origin, 5.0, 5.0, false, Material::new(WHITE, false));
+        assert_eq!(two_sheets.intersect(&r), HUGE_VALUE);
+        let r = test_ray(Vector3 {
+            x: 0.0,
+            y: 20.0,
+            z: 0.0,
+        });
+        assert!((two_sheets.intersect(&r) - 15.0).abs() < 0.000001);
+    }
+}
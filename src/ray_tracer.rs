@@ -1,13 +1,39 @@
 //! A naive ray tracer implementation.
 
+use crate::brdf::*;
+use crate::camera::*;
+use crate::csg::*;
+use crate::environment::*;
+use crate::heightfield::*;
+use crate::material::*;
+use crate::normal_mapping::*;
+use crate::quadric::*;
 use crate::ray_tracer::GeomPrimitive::UNKNOWN;
+use crate::rgb::*;
+use crate::sampling::*;
+use crate::sdf::*;
+use crate::texture::*;
 use crate::vector_arithmetic::*;
 use std::cmp;
+use std::f64::consts::PI;
+use std::ops::Deref;
 use wasm_bindgen::{Clamped, JsCast, JsValue};
 use web_sys::{console, CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
 
-const HUGE_VALUE: f64 = 1000000.0;
-const TINY_VALUE: f64 = 0.1;
+pub const HUGE_VALUE: f64 = 1000000.0;
+pub const TINY_VALUE: f64 = 0.1;
+
+/// The number of samples that estimate the light from each emissive primitive.
+const EMITTER_SAMPLES: u32 = 8;
+
+/// The intensity of the ambient light, which also reaches surfaces that the light source doesn't.
+const AMBIENT_INTENSITY: f64 = 0.2;
+
+/// The number of reflection rays that are averaged for a rough reflective surface.
+const GLOSSY_SAMPLES: u32 = 8;
+
+/// The number of samples that estimate the light from the background.
+const ENVIRONMENT_SAMPLES: u32 = 8;
 
 /// Renders a given scene on a given HTML canvas element.
 pub fn render(scene: &Scene, canvas: &HtmlCanvasElement) -> Result<(), JsValue> {
@@ -16,25 +42,35 @@ pub fn render(scene: &Scene, canvas: &HtmlCanvasElement) -> Result<(), JsValue>
     let height: u32 = canvas.height();
     let width: u32 = canvas.width();
 
-    let ctx: CanvasRenderingContext2d = canvas
-        .get_context("2d")
-        .unwrap()
-        .unwrap()
-        .dyn_into::<CanvasRenderingContext2d>()
-        .unwrap();
+    let (updated_data, stats) = render_rgba(&scene, width, height);
+    if stats.nan_samples > 0 || stats.inf_samples > 0 {
+        console::log_1(
+            &format!(
+                "Encountered {} NaN and {} Inf samples while rendering the frame!",
+                stats.nan_samples, stats.inf_samples
+            )
+            .into(),
+        );
+    }
 
-    let image_data: ImageData = ctx
-        .create_image_data_with_sw_and_sh(width as f64, height as f64)
-        .unwrap()
-        .dyn_into::<ImageData>()
-        .unwrap();
+    return draw_rgba(&canvas, updated_data, width, height);
+}
 
-    let mut updated_data = render_with_data_array(&scene, &image_data.data(), width, height);
+/// Draws a given RGBA image (row by row, top to bottom) onto a given HTML canvas element.
+pub fn draw_rgba(
+    canvas: &HtmlCanvasElement,
+    mut rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+) -> Result<(), JsValue> {
+    let ctx: CanvasRenderingContext2d = canvas
+        .get_context("2d")?
+        .ok_or_else(|| JsValue::from_str("The canvas has no 2D context!"))?
+        .dyn_into::<CanvasRenderingContext2d>()?;
 
-    let updated_image_data =
-        ImageData::new_with_u8_clamped_array_and_sh(Clamped(&mut updated_data), width, height)
-            .unwrap();
-    return ctx.put_image_data(&updated_image_data, 0.0, 0.0);
+    let image_data =
+        ImageData::new_with_u8_clamped_array_and_sh(Clamped(&mut rgba), width, height)?;
+    return ctx.put_image_data(&image_data, 0.0, 0.0);
 }
 
 fn render_with_data_array(
@@ -42,39 +78,56 @@ fn render_with_data_array(
     imagedata_data: &Clamped<Vec<u8>>,
     width: u32,
     height: u32,
-) -> Clamped<Vec<u8>> {
+) -> (Clamped<Vec<u8>>, RenderStats) {
     let mut data: Clamped<Vec<u8>> = imagedata_data.clone();
+    let mut stats = RenderStats::default();
+    let scene = PreparedScene::new(&scene);
 
-    // TODO: the camera direction should be part of the scene struct?
-    let camera_direction = Vector3 {
-        x: 0.0,
-        y: 0.0,
-        z: 1.0,
-    };
-    let camera_up = Vector3 {
-        x: 0.0,
-        y: 1.0,
-        z: 0.0,
-    };
-    let camera_right = cross_product(&camera_direction, &camera_up);
+    for y in 0..height {
+        for x in 0..width {
+            let c = compute_and_trace_ray(x, y, width, height, &scene, &mut stats);
+            set_pixel_color(&mut data, width, x, y, &c);
+        }
+    }
+
+    return (data, stats);
+}
+
+/// Renders a given scene into an RGBA image like render_rgba(), but looks the primary hits of the pixels'
+/// samples up in a given cache, and adds those that it doesn't contain yet. The cache stays valid as long
+/// as neither the camera nor the geometry of the scene changes.
+pub fn render_rgba_cached(
+    scene: &Scene,
+    width: u32,
+    height: u32,
+    primary_hits: &mut Vec<PrimaryHit>,
+) -> (Vec<u8>, RenderStats) {
+    let mut data = Clamped(vec![0; (width * height * 4) as usize]);
+    let mut stats = RenderStats::default();
+    let samples = scene.camera.samples() as usize;
+    primary_hits.resize(
+        width as usize * height as usize * samples,
+        PrimaryHit::Unknown,
+    );
+    let scene = PreparedScene::new(&scene);
 
     for y in 0..height {
         for x in 0..width {
-            let c = compute_and_trace_ray(
-                x,
-                y,
-                width,
-                height,
-                &camera_direction,
-                &camera_up,
-                &camera_right,
-                &scene,
-            );
+            let i = (y * width + x) as usize * samples;
+            let pixel_hits = &mut primary_hits[i..i + samples];
+            let c = trace_pixel(x, y, width, height, &scene, pixel_hits, &mut stats);
             set_pixel_color(&mut data, width, x, y, &c);
         }
     }
 
-    return data;
+    return (data.0, stats);
+}
+
+/// Renders a given scene into an RGBA image (row by row, top to bottom), e.g. to write it into a file.
+pub fn render_rgba(scene: &Scene, width: u32, height: u32) -> (Vec<u8>, RenderStats) {
+    let data = Clamped(vec![0; (width * height * 4) as usize]);
+    let (data, stats) = render_with_data_array(&scene, &data, width, height);
+    return (data.0, stats);
 }
 
 fn compute_and_trace_ray(
@@ -82,103 +135,887 @@ fn compute_and_trace_ray(
     y: u32,
     width: u32,
     height: u32,
-    camera_direction: &Vector3,
-    camera_up: &Vector3,
-    camera_right: &Vector3,
-    scene: &Scene,
+    scene: &PreparedScene,
+    stats: &mut RenderStats,
+) -> Color {
+    let mut primary_hits = vec![PrimaryHit::Unknown; scene.camera.samples() as usize];
+    return trace_pixel(x, y, width, height, &scene, &mut primary_hits, stats);
+}
+
+/// Computes the color of a pixel. The primary hits of its samples are looked up in (or added to) the given
+/// cache entries, one per sample.
+fn trace_pixel(
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    scene: &PreparedScene,
+    primary_hits: &mut [PrimaryHit],
+    stats: &mut RenderStats,
 ) -> Color {
-    let r = compute_ray(
+    let camera = &scene.camera;
+    let r = match compute_ray(x, y, width, height, &camera) {
+        Some(r) => r,
+        None => return Color { r: 0, g: 0, b: 0 },
+    };
+    let differential = compute_ray_differential(x, y, width, height, &camera);
+    // every pixel has its own (reproducible) random numbers:
+    let mut rng = Rng::new((y as u64) * (width as u64) + (x as u64));
+
+    // a thin lens averages rays from different points on its aperture, a shutter rays at different times:
+    let samples = camera.samples();
+    debug_assert_eq!(primary_hits.len(), samples as usize);
+    // (the samples' rays are drawn before anything is shaded, so that they stay the same when the shading
+    // changes, and the cache entries remain theirs:)
+    let sample_numbers: Vec<(f64, f64, f64)> = (0..samples)
+        .map(|_| {
+            let (u1, u2) = (rng.next_f64(), rng.next_f64());
+            let u3 = if camera.shutter.is_some() {
+                rng.next_f64()
+            } else {
+                0.0
+            };
+            return (u1, u2, u3);
+        })
+        .collect();
+    let mut total = BLACK_RGB;
+    for (i, (&(u1, u2, u3), primary_hit)) in sample_numbers.iter().zip(primary_hits).enumerate() {
+        let mut lens_ray = camera.focus_ray(&r, u1, u2);
+        if camera.shutter.is_some() {
+            // (stratified, every sample gets its share of the shutter interval:)
+            let u = (i as f64 + u3) / samples as f64;
+            lens_ray = camera.move_ray(&lens_ray, camera.sample_time(u));
+        }
+        let closest_point = cached_intersection_point(&lens_ray, primary_hit, &scene);
+        let c = match scene.settings.output {
+            RenderOutput::Color => shade_intersection(
+                &lens_ray,
+                &closest_point,
+                Some(&differential),
+                &PRIMARY_RAY,
+                &scene,
+                stats,
+                &mut rng,
+            ),
+            RenderOutput::AmbientOcclusion => Rgb::from_color(&trace_ambient_occlusion(
+                &lens_ray,
+                &closest_point,
+                &scene,
+                &mut rng,
+            )),
+        };
+        stats.record(&c);
+        total = add_rgb(&total, &c);
+    }
+    return scale_rgb(1.0 / samples as f64, &total).to_color();
+}
+
+/// Looks up where a primary ray hits the scene in a given cache entry, or computes it and remembers it.
+fn cached_intersection_point<'b>(
+    r: &Ray,
+    primary_hit: &mut PrimaryHit,
+    scene: &'b Scene,
+) -> Intersection<'b> {
+    let geom_object = match *primary_hit {
+        PrimaryHit::Unknown => None,
+        PrimaryHit::Background => Some((HUGE_VALUE, UNKNOWN, false)),
+        PrimaryHit::Object {
+            primitive,
+            k,
+            flip_normal,
+        } => find_primitive(&scene, &primitive).map(|geom_object| (k, geom_object, flip_normal)),
+    };
+    if let Some((k, geom_object, flip_normal)) = geom_object {
+        return Intersection {
+            k,
+            time: r.time,
+            point: follow_ray(&r, k),
+            direction: r.direction,
+            geom_object,
+            flip_normal,
+        };
+    }
+
+    let intersection = closest_intersection_point(&r, &scene);
+    let a: f64 = intersection.k - HUGE_VALUE;
+    *primary_hit = if a.abs() < TINY_VALUE {
+        PrimaryHit::Background
+    } else {
+        match find_primitive_id(&scene, &intersection.geom_object) {
+            Some(primitive) => PrimaryHit::Object {
+                primitive,
+                k: intersection.k,
+                flip_normal: intersection.flip_normal,
+            },
+            None => PrimaryHit::Unknown,
+        }
+    };
+    return intersection;
+}
+
+/// Computes the ray of a pinhole camera (i.e. one without a lens) through a pixel,
+/// or None if the camera's projection doesn't cover the pixel.
+fn compute_ray(x: u32, y: u32, width: u32, height: u32, camera: &Camera) -> Option<Ray> {
+    let (view, p, _) = camera.view_of_pixel(x, y, width, height);
+    let r = view.primary_ray(&p)?;
+    debug_assert!(
+        is_finite(&r.direction),
+        "Non-finite ray direction for pixel ({}, {})!",
         x,
-        y,
-        width,
-        height,
-        &camera_direction,
-        &camera_up,
-        &camera_right,
+        y
     );
-    return trace_ray(&r, 1, &scene.spheres, &scene.planes, &scene.light);
+    return Some(r);
 }
 
-fn compute_ray(
+/// Computes how the origin and (unit) direction of a pixel's primary ray change towards its neighbouring pixels
+/// (in the same view). The differentials of a thin lens's rays are approximated by those of its pinhole rays.
+fn compute_ray_differential(
     x: u32,
     y: u32,
     width: u32,
     height: u32,
-    camera_direction: &Vector3,
-    camera_up: &Vector3,
-    camera_right: &Vector3,
-) -> Ray {
-    let p = normalize_x_y(x, y, width, height);
-    let t1 = scale_vector(p.x, &camera_right);
-    let t2 = scale_vector(p.y, &camera_up);
-    let t3 = sum(&t1, &t2);
-
-    let ray_direction = sum(&t3, &camera_direction);
-    return Ray {
-        origin: Vector3 {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        },
-        direction: ray_direction,
+    camera: &Camera,
+) -> RayDifferential {
+    let (view, p, pixel_size) = camera.view_of_pixel(x, y, width, height);
+    let unit_ray = |x: f64, y: f64| {
+        let r = view.primary_ray(&Point { x, y })?;
+        return Some((r.origin, normalize(&r.direction)));
+    };
+    let change = |from: Option<(Vector3, Vector3)>, to: Option<(Vector3, Vector3)>| {
+        return match (from, to) {
+            (Some((o1, d1)), Some((o2, d2))) => (difference(&o2, &o1), difference(&d2, &d1)),
+            // at the edge of a fisheye's image circle, we sample a single point:
+            _ => (ZERO_VECTOR3, ZERO_VECTOR3),
+        };
+    };
+    let r = unit_ray(p.x, p.y);
+    let (dodx, dddx) = change(r, unit_ray(p.x + pixel_size.x, p.y));
+    let (dody, dddy) = change(r, unit_ray(p.x, p.y + pixel_size.y));
+    return RayDifferential {
+        dodx,
+        dddx,
+        dody,
+        dddy,
+    };
+}
+
+/// Focuses the scene's thin lens on whatever a given pixel shows, returns the new focal distance
+/// (or None if the camera has no lens or the pixel shows the background).
+pub fn autofocus(scene: &mut Scene, x: u32, y: u32, width: u32, height: u32) -> Option<f64> {
+    scene.camera.lens?;
+    let r = compute_ray(x, y, width, height, &scene.camera)?;
+    let intersection = closest_intersection_point(&r, &scene);
+    let a: f64 = intersection.k - HUGE_VALUE;
+    if a.abs() < TINY_VALUE {
+        return None;
+    }
+    let focal_distance = dot_product(
+        &difference(&intersection.point, &r.origin),
+        &scene.camera.direction,
+    );
+    if let Some(lens) = &mut scene.camera.lens {
+        lens.focal_distance = focal_distance;
+    }
+    return Some(focal_distance);
+}
+
+/// Finds the object that a given pixel shows (None if it shows the background).
+pub fn pick(scene: &Scene, x: u32, y: u32, width: u32, height: u32) -> Option<Pick> {
+    let r = compute_ray(x, y, width, height, &scene.camera)?;
+    let intersection = closest_intersection_point(&r, &scene);
+    let a: f64 = intersection.k - HUGE_VALUE;
+    if a.abs() < TINY_VALUE {
+        return None;
+    }
+    return Some(Pick {
+        object: find_primitive_id(&scene, &intersection.geom_object)?.object,
+        point: intersection.point,
+        normal: intersection.compute_normal(),
+        distance: distance(&intersection.point, &r.origin),
+    });
+}
+
+/// Finds the primitive of a scene's objects that a given primitive is.
+fn find_primitive_id(scene: &Scene, primitive: &GeomPrimitive) -> Option<PrimitiveId> {
+    fn position<T>(objects: &[T], object: &T) -> Option<usize> {
+        return objects.iter().position(|o| std::ptr::eq(o, object));
+    }
+    let top_level = match primitive {
+        GeomPrimitive::Sphere(sphere) => {
+            position(&scene.spheres, sphere).map(|i| (ObjectKind::Sphere, i))
+        }
+        GeomPrimitive::Plane(plane) => {
+            position(&scene.planes, plane).map(|i| (ObjectKind::Plane, i))
+        }
+        GeomPrimitive::Cuboid(cuboid) => {
+            position(&scene.cuboids, cuboid).map(|i| (ObjectKind::Cuboid, i))
+        }
+        GeomPrimitive::Sdf(sdf_object) => {
+            position(&scene.sdf_objects, sdf_object).map(|i| (ObjectKind::Sdf, i))
+        }
+        GeomPrimitive::Heightfield(heightfield) => {
+            position(&scene.heightfields, heightfield).map(|i| (ObjectKind::Heightfield, i))
+        }
+        GeomPrimitive::Quadric(quadric) => {
+            position(&scene.quadrics, quadric).map(|i| (ObjectKind::Quadric, i))
+        }
+        GeomPrimitive::UNKNOWN => None,
+    };
+    if let Some((kind, index)) = top_level {
+        return Some(PrimitiveId {
+            object: ObjectId { kind, index },
+            leaf: 0,
+        });
+    }
+
+    // (the primitives of CSG objects are part of their trees:)
+    return scene
+        .csg_objects
+        .iter()
+        .enumerate()
+        .find_map(|(index, csg)| {
+            let leaf = csg.primitive_index(&primitive)?;
+            return Some(PrimitiveId {
+                object: ObjectId {
+                    kind: ObjectKind::Csg,
+                    index,
+                },
+                leaf,
+            });
+        });
+}
+
+/// Finds the primitive of a scene that a given ID refers to (None if the scene has no such primitive).
+fn find_primitive<'b>(scene: &'b Scene, id: &PrimitiveId) -> Option<GeomPrimitive<'b>> {
+    let i = id.object.index;
+    return match id.object.kind {
+        ObjectKind::Sphere => scene.spheres.get(i).map(GeomPrimitive::Sphere),
+        ObjectKind::Plane => scene.planes.get(i).map(GeomPrimitive::Plane),
+        ObjectKind::Cuboid => scene.cuboids.get(i).map(GeomPrimitive::Cuboid),
+        ObjectKind::Csg => scene.csg_objects.get(i)?.primitives().get(id.leaf).copied(),
+        ObjectKind::Sdf => scene.sdf_objects.get(i).map(GeomPrimitive::Sdf),
+        ObjectKind::Heightfield => scene.heightfields.get(i).map(GeomPrimitive::Heightfield),
+        ObjectKind::Quadric => scene.quadrics.get(i).map(GeomPrimitive::Quadric),
+    };
+}
+
+/// Computes how the point where a ray hits a surface changes towards the neighbouring pixels (Igehy, 1999),
+/// or None if the ray grazes the surface, so that the change is unbounded.
+fn transfer_ray_differential(
+    r: &Ray,
+    differential: &RayDifferential,
+    point: &Vector3,
+    normal: &Vector3,
+) -> Option<(Vector3, Vector3)> {
+    let d = normalize(&r.direction);
+    let t = dot_product(&difference(&point, &r.origin), &d);
+    let d_dot_n = dot_product(&d, &normal);
+    if d_dot_n.abs() < 1e-9 {
+        return None;
+    }
+
+    let transfer = |dodx: &Vector3, dddx: &Vector3| {
+        // move along the offset ray and then along the ray until we reach the tangent plane again:
+        let dp = sum(&dodx, &scale_vector(t, &dddx));
+        let dt = -dot_product(&dp, &normal) / d_dot_n;
+        return sum(&dp, &scale_vector(dt, &d));
+    };
+    return Some((
+        transfer(&differential.dodx, &differential.dddx),
+        transfer(&differential.dody, &differential.dddy),
+    ));
+}
+
+/// Computes the differential of a ray that is reflected at a point with a given normal,
+/// given how the point and the normal change towards the neighbouring pixels.
+fn reflect_ray_differential(
+    r: &Ray,
+    differential: &RayDifferential,
+    normal: &Vector3,
+    (dpdx, dpdy): (&Vector3, &Vector3),
+    (dndx, dndy): (&Vector3, &Vector3),
+) -> RayDifferential {
+    let d = normalize(&r.direction);
+    let d_dot_n = dot_product(&d, &normal);
+    let reflect = |dddx: &Vector3, dndx: &Vector3| {
+        // the derivative of d - 2 (d . n) n:
+        let dd_dot_n = dot_product(&dddx, &normal) + dot_product(&d, &dndx);
+        let change = sum(
+            &scale_vector(d_dot_n, &dndx),
+            &scale_vector(dd_dot_n, &normal),
+        );
+        return difference(&dddx, &scale_vector(2.0, &change));
+    };
+    return RayDifferential {
+        dodx: *dpdx,
+        dddx: reflect(&differential.dddx, &dndx),
+        dody: *dpdy,
+        dddy: reflect(&differential.dddy, &dndy),
     };
 }
 
-fn normalize_x_y(x: u32, y: u32, width: u32, height: u32) -> Point {
-    let normalized_x: f64 = ((x as f64) / (width as f64)) - 0.5;
-    let normalized_y: f64 = ((y as f64) / (height as f64)) - 0.5;
+/// Maps a pixel (whose row 0 is the image's top) to the point at its center on the image:
+/// relative to the image's center, with a y axis that points up, in units of the image's width.
+/// I.e. x is in [-0.5, 0.5] and y in [-0.5 height / width, 0.5 height / width].
+pub fn normalize_x_y(x: u32, y: u32, width: u32, height: u32) -> Point {
+    let normalized_x: f64 = ((x as f64 + 0.5) / (width as f64)) - 0.5;
+    let normalized_y: f64 = (0.5 * (height as f64) - (y as f64 + 0.5)) / (width as f64);
     return Point {
         x: normalized_x,
         y: normalized_y,
     };
 }
 
-fn trace_ray(r: &Ray, depth: u32, spheres: &[Sphere], planes: &[Plane], light: &Light) -> Color {
+fn trace_ray(
+    r: &Ray,
+    differential: Option<&RayDifferential>,
+    tree_depth: &RayTreeDepth,
+    scene: &PreparedScene,
+    stats: &mut RenderStats,
+    rng: &mut Rng,
+) -> Rgb {
     // compute the closest point that our ray intersects:
-    let closest_point = closest_intersection_point(&r, &spheres, &planes);
+    let closest_point = closest_intersection_point(&r, &scene);
+    return shade_intersection(
+        &r,
+        &closest_point,
+        differential,
+        tree_depth,
+        &scene,
+        stats,
+        rng,
+    );
+}
+
+/// Computes the color of a ray that hits a given intersection (or the background), rounded to 8 bits per
+/// channel unless it is NaN or infinite.
+fn shade_intersection(
+    r: &Ray,
+    closest_point: &Intersection,
+    differential: Option<&RayDifferential>,
+    tree_depth: &RayTreeDepth,
+    scene: &PreparedScene,
+    stats: &mut RenderStats,
+    rng: &mut Rng,
+) -> Rgb {
     let intersection_point = &closest_point.point;
     let closest_object = &closest_point.geom_object;
 
     let a: f64 = &closest_point.k - HUGE_VALUE;
     return if a.abs() < TINY_VALUE {
-        Color { r: 0, g: 0, b: 0 }
+        match try_normalize(&r.direction) {
+            Some(direction) => scene.background.radiance(&direction).quantize(),
+            None => BLACK_RGB,
+        }
     } else {
-        let normal = closest_object.compute_normal(&intersection_point);
-        let direction_to_light = normalize(&difference(&light.pos, &intersection_point));
-        let shadow_ray = Ray {
-            origin: *intersection_point,
-            direction: direction_to_light,
-        };
+        let geometric_normal = closest_point.compute_normal();
+        debug_assert!(
+            is_finite(&intersection_point),
+            "Non-finite intersection point {:?}!",
+            intersection_point
+        );
+        debug_assert!(
+            is_finite(&geometric_normal),
+            "Non-finite normal at {:?}!",
+            intersection_point
+        );
 
-        // compute the closest point in the direction of light:
-        let closest_point_l = closest_intersection_point(&shadow_ray, &spheres, &planes);
+        let material = closest_object.material();
+        let uv = closest_point.compute_uv();
+        // (solid textures move along with their objects:)
+        let object_point = closest_point.object_point();
+        let (dpdu, dpdv) = closest_object.compute_uv_derivatives(&object_point, &geometric_normal);
+        let normal = compute_shading_normal(
+            &material,
+            &geometric_normal,
+            &dpdu,
+            &dpdv,
+            &uv,
+            &object_point,
+        );
 
-        // if there is no intersection or the closest one is behind the light source:
-        let b: f64 = closest_point_l.k - HUGE_VALUE;
-        let d = distance(&intersection_point, &light.pos);
-        let raw_intensity = if b.abs() < TINY_VALUE || d < closest_point_l.k {
-            dot_product(&normal, &direction_to_light)
-        } else {
-            0.0
+        let raw_intensity = compute_light_intensity(&intersection_point, &normal, r.time, &scene);
+
+        // the ambient light reaches the parts of the surface that nearby objects don't occlude:
+        let ambient_occlusion =
+            compute_ambient_occlusion(&intersection_point, &geometric_normal, r.time, &scene, rng);
+        let ambient_intensity = f64::max(raw_intensity, AMBIENT_INTENSITY * ambient_occlusion);
+        // the changes of the hit point towards the neighbouring pixels determine the texture filter's size:
+        let point_differential = differential.and_then(|differential| {
+            transfer_ray_differential(&r, &differential, &intersection_point, &geometric_normal)
+        });
+        let footprint = match (differential, point_differential) {
+            (_, Some((dpdx, dpdy))) => compute_uv_footprint(&dpdx, &dpdy, &dpdu, &dpdv),
+            // (a grazing ray's footprint spreads over the whole texture:)
+            (Some(_), None) => UNBOUNDED_FOOTPRINT,
+            (None, None) => POINT_FOOTPRINT,
         };
+        let surface_color = material.diffuse_color(&uv, &object_point, &footprint);
+        let view = scale_vector(-1.0, &normalize(&r.direction));
+        let base_color = Rgb::from_color(&surface_color);
+        let brdf = |l: &Vector3| match &material.pbr {
+            Some(pbr) => pbr.evaluate(&base_color, &normal, &view, &l),
+            None => scale_rgb(1.0 / PI, &base_color),
+        };
+        let emitted_light = estimate_emitted_light(
+            &intersection_point,
+            &normal,
+            r.time,
+            &closest_object,
+            &scene,
+            rng,
+            &brdf,
+        );
+        let environment_light =
+            estimate_environment_light(&intersection_point, &normal, r.time, &scene, rng, &brdf);
+        let point_light_color = match &material.pbr {
+            Some(pbr) => shade_metallic_roughness(
+                &pbr,
+                &Rgb::from_color(&surface_color),
+                &normal,
+                &view,
+                &difference(&scene.light.pos, &intersection_point),
+                raw_intensity,
+                AMBIENT_INTENSITY * ambient_occlusion,
+            )
+            .quantize(),
+            None => Rgb::from_color(&scale_color(ambient_intensity, &surface_color)),
+        };
+        let mut local_light = add_rgb(
+            &add_rgb(&point_light_color, &emitted_light),
+            &environment_light,
+        );
+        if let Some(emission) = &material.emission {
+            local_light = add_rgb(&local_light, &emission);
+        }
+        let local_color = local_light.quantize();
+
+        // the share of the reflected light in the surface's color:
+        let reflectance = match &material.pbr {
+            // metallic-roughness materials reflect the share given by the Fresnel term:
+            Some(pbr) => fresnel_schlick(&pbr.f0(&base_color), dot_product(&normal, &view)),
+            None => Rgb {
+                r: 0.25,
+                g: 0.25,
+                b: 0.25,
+            },
+        };
+        let reflection_depth = tree_depth.reflected(reflectance.average());
 
-        let ambient_intensity = f64::max(raw_intensity, 0.2);
-        let ambient_color = scale_color(ambient_intensity, &closest_object.color());
-
-        if depth > 0 && closest_object.reflects() {
+        if material.reflect && reflection_depth.is_within(&scene.settings) {
             let q = 2.0 * dot_product(&r.direction, &normal);
             let p = scale_vector(q, &normal);
             let reflection_vector = difference(&r.direction, &p);
             let reflection_ray = Ray {
                 origin: *intersection_point,
                 direction: reflection_vector,
+                time: r.time,
             };
-            let reflected_color = trace_ray(&reflection_ray, depth - 1, spheres, planes, light);
-            let mixed_color = mix_colors(0.25, &reflected_color, 0.75, &ambient_color);
-            mixed_color
+            let reflection_differential = match (differential, point_differential) {
+                (Some(differential), Some((dpdx, dpdy))) => {
+                    let dndx = difference(
+                        &closest_point.compute_normal_at(&sum(&intersection_point, &dpdx)),
+                        &geometric_normal,
+                    );
+                    let dndy = difference(
+                        &closest_point.compute_normal_at(&sum(&intersection_point, &dpdy)),
+                        &geometric_normal,
+                    );
+                    Some(reflect_ray_differential(
+                        &r,
+                        &differential,
+                        &normal,
+                        (&dpdx, &dpdy),
+                        (&dndx, &dndy),
+                    ))
+                }
+                _ => None,
+            };
+            let roughness = material.reflection_roughness();
+            let reflected_color = if roughness > 0.0 {
+                // average reflection rays that are spread around the mirror direction:
+                let sample_depth =
+                    tree_depth.reflected(reflectance.average() / GLOSSY_SAMPLES as f64);
+                let mut reflected_light = BLACK_RGB;
+                for _ in 0..GLOSSY_SAMPLES {
+                    let glossy_ray = Ray {
+                        origin: *intersection_point,
+                        direction: sample_glossy_direction(
+                            &reflection_vector,
+                            &normal,
+                            roughness,
+                            rng.next_f64(),
+                            rng.next_f64(),
+                        ),
+                        time: r.time,
+                    };
+                    let c = trace_ray(
+                        &glossy_ray,
+                        reflection_differential.as_ref(),
+                        &sample_depth,
+                        scene,
+                        stats,
+                        rng,
+                    );
+                    let weight = 1.0 / GLOSSY_SAMPLES as f64;
+                    reflected_light = add_rgb(&reflected_light, &scale_rgb(weight, &c));
+                }
+                reflected_light.quantize()
+            } else {
+                trace_ray(
+                    &reflection_ray,
+                    reflection_differential.as_ref(),
+                    &reflection_depth,
+                    scene,
+                    stats,
+                    rng,
+                )
+            };
+            match &material.pbr {
+                Some(_) => {
+                    let reflected = multiply_rgb(&reflectance, &reflected_color);
+                    add_rgb(&local_color, &reflected).quantize()
+                }
+                None if reflected_color.is_finite() && local_color.is_finite() => {
+                    Rgb::from_color(&mix_colors(
+                        0.25,
+                        &reflected_color.to_color(),
+                        0.75,
+                        &local_color.to_color(),
+                    ))
+                }
+                None => add_rgb(
+                    &scale_rgb(0.25, &reflected_color),
+                    &scale_rgb(0.75, &local_color),
+                ),
+            }
         } else {
-            ambient_color
+            local_color
+        }
+    };
+}
+
+/// Perturbs a mirror direction within a cone whose half angle is atan(roughness^2) (the width of
+/// a GGX lobe), given two uniformly distributed numbers. Directions below the surface are mirrored back.
+fn sample_glossy_direction(
+    mirror: &Vector3,
+    normal: &Vector3,
+    roughness: f64,
+    u1: f64,
+    u2: f64,
+) -> Vector3 {
+    let axis = normalize(&mirror);
+    let cos_theta_max = (roughness * roughness).atan().cos();
+    let d = sample_cone(&axis, cos_theta_max, u1, u2);
+    let d_dot_n = dot_product(&d, &normal);
+    return if d_dot_n < 0.0 {
+        difference(&d, &scale_vector(2.0 * d_dot_n, &normal))
+    } else {
+        d
+    };
+}
+
+/// Estimates the light that the background sends from a point towards the viewer,
+/// brdf weights the light arriving from a given direction.
+fn estimate_environment_light(
+    point: &Vector3,
+    normal: &Vector3,
+    time: f64,
+    scene: &Scene,
+    rng: &mut Rng,
+    brdf: &dyn Fn(&Vector3) -> Rgb,
+) -> Rgb {
+    if scene.background.is_black() {
+        return BLACK_RGB;
+    }
+
+    let mut total = BLACK_RGB;
+    for _ in 0..ENVIRONMENT_SAMPLES {
+        let (l, pdf) = match scene
+            .background
+            .sample(&normal, rng.next_f64(), rng.next_f64())
+        {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let cos_theta = dot_product(&normal, &l);
+        if cos_theta <= 0.0 || pdf <= 0.0 {
+            continue;
+        }
+
+        // only rays that escape the scene see the background:
+        let ray = Ray {
+            origin: *point,
+            direction: l,
+            time,
+        };
+        let b: f64 = closest_intersection_point(&ray, &scene).k - HUGE_VALUE;
+        if b.abs() >= TINY_VALUE {
+            continue;
+        }
+
+        let weight = cos_theta / (pdf * ENVIRONMENT_SAMPLES as f64);
+        let contribution = multiply_rgb(&brdf(&l), &scene.background.radiance(&l));
+        total = add_rgb(&total, &scale_rgb(weight, &contribution));
+    }
+    return total;
+}
+
+/// Estimates the light that the scene's emitters (see PreparedScene) send from a point towards the viewer,
+/// brdf weights the light arriving from a given direction.
+fn estimate_emitted_light(
+    point: &Vector3,
+    normal: &Vector3,
+    time: f64,
+    shaded_object: &GeomPrimitive,
+    scene: &PreparedScene,
+    rng: &mut Rng,
+    brdf: &dyn Fn(&Vector3) -> Rgb,
+) -> Rgb {
+    let mut total = BLACK_RGB;
+    for &(emitter, emission) in &scene.emitters {
+        if emitter.is_same_as(&shaded_object) {
+            continue;
+        }
+        for _ in 0..EMITTER_SAMPLES {
+            let (u1, u2, u3) = (rng.next_f64(), rng.next_f64(), rng.next_f64());
+            // (we sample moving emitters in their place at time 0.0, from where the point is relative to them:)
+            let relative_point = difference(&point, &emitter.displacement(time));
+            let sample = match emitter {
+                GeomPrimitive::Sphere(sphere) => {
+                    sample_sphere_emitter(&sphere, &relative_point, u1, u2)
+                }
+                GeomPrimitive::Cuboid(cuboid) => {
+                    sample_cuboid_emitter(&cuboid, &relative_point, u1, u2, u3)
+                }
+                _ => None,
+            };
+            let (l, pdf) = match sample {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let cos_theta = dot_product(&normal, &l);
+            if cos_theta <= 0.0 || !is_emitter_visible(&point, &l, time, &emitter, &scene) {
+                continue;
+            }
+
+            let weight = cos_theta / (pdf * EMITTER_SAMPLES as f64);
+            let contribution = multiply_rgb(&brdf(&l), &emission);
+            total = add_rgb(&total, &scale_rgb(weight, &contribution));
+        }
+    }
+    return total;
+}
+
+/// Samples a direction from a point towards an emissive sphere (uniformly within the cone it subtends),
+/// returns the direction and its probability density.
+fn sample_sphere_emitter(
+    sphere: &Sphere,
+    point: &Vector3,
+    u1: f64,
+    u2: f64,
+) -> Option<(Vector3, f64)> {
+    let to_center = difference(&sphere.pos, &point);
+    let d = length(&to_center);
+    if d <= sphere.r {
+        // points within the sphere don't see it as a cone:
+        return None;
+    }
+    let sin_theta_max = sphere.r / d;
+    let cos_theta_max = (1.0 - sin_theta_max * sin_theta_max).sqrt();
+    let axis = scale_vector(1.0 / d, &to_center);
+    let l = sample_cone(&axis, cos_theta_max, u1, u2);
+    return Some((l, cone_pdf(cos_theta_max)));
+}
+
+/// Samples a direction from a point towards a uniformly chosen point on an emissive cuboid's surface,
+/// returns the direction and its probability density (per solid angle).
+fn sample_cuboid_emitter(
+    cuboid: &Cuboid,
+    point: &Vector3,
+    u1: f64,
+    u2: f64,
+    u3: f64,
+) -> Option<(Vector3, f64)> {
+    let mins = [cuboid.min.x, cuboid.min.y, cuboid.min.z];
+    let maxs = [cuboid.max.x, cuboid.max.y, cuboid.max.z];
+    let extents = [maxs[0] - mins[0], maxs[1] - mins[1], maxs[2] - mins[2]];
+    let face_areas = [
+        extents[1] * extents[2],
+        extents[0] * extents[2],
+        extents[0] * extents[1],
+    ];
+    let total_area = 2.0 * (face_areas[0] + face_areas[1] + face_areas[2]);
+
+    // choose one of the six faces by its area, u1 also decides between its min and max side:
+    let mut chosen = u1 * total_area;
+    let mut axis = 0;
+    while axis < 2 && chosen >= 2.0 * face_areas[axis] {
+        chosen -= 2.0 * face_areas[axis];
+        axis += 1;
+    }
+    let at_max = chosen >= face_areas[axis];
+
+    // ...and a point on it:
+    let (i, j) = match axis {
+        0 => (1, 2),
+        1 => (0, 2),
+        _ => (0, 1),
+    };
+    let mut face_point = [0.0; 3];
+    face_point[i] = mins[i] + u2 * extents[i];
+    face_point[j] = mins[j] + u3 * extents[j];
+    face_point[axis] = if at_max { maxs[axis] } else { mins[axis] };
+    let mut face_normal = [0.0; 3];
+    face_normal[axis] = if at_max { 1.0 } else { -1.0 };
+
+    let x = Vector3 {
+        x: face_point[0],
+        y: face_point[1],
+        z: face_point[2],
+    };
+    let n = Vector3 {
+        x: face_normal[0],
+        y: face_normal[1],
+        z: face_normal[2],
+    };
+    let to_face = difference(&x, &point);
+    let l = try_normalize(&to_face)?;
+
+    // faces that turn away from the point don't emit towards it:
+    let cos_light = -dot_product(&n, &l);
+    if cos_light <= 0.0 {
+        return None;
+    }
+    let d = length(&to_face);
+    return Some((l, d * d / (total_area * cos_light)));
+}
+
+/// Checks whether the first object in a given direction from a point is a given emitter.
+fn is_emitter_visible(
+    point: &Vector3,
+    direction: &Vector3,
+    time: f64,
+    emitter: &GeomPrimitive,
+    scene: &Scene,
+) -> bool {
+    let ray = Ray {
+        origin: *point,
+        direction: *direction,
+        time,
+    };
+    let closest_point = closest_intersection_point(&ray, &scene);
+    return closest_point.geom_object.is_same_as(&emitter);
+}
+
+/// Shades a point with the metallic-roughness model, lit by the scene's light (arriving from a given
+/// direction with a given intensity, see compute_light_intensity()) and the ambient light.
+fn shade_metallic_roughness(
+    pbr: &MetallicRoughness,
+    base_color: &Rgb,
+    normal: &Vector3,
+    view: &Vector3,
+    to_light: &Vector3,
+    light_intensity: f64,
+    ambient_intensity: f64,
+) -> Rgb {
+    let ambient = scale_rgb(ambient_intensity, &base_color);
+    if light_intensity <= 0.0 {
+        return ambient;
+    }
+
+    // the light is scaled such that a white Lambertian surface (whose BRDF is 1/PI) appears white:
+    let l = normalize(&to_light);
+    let brdf = pbr.evaluate(&base_color, &normal, &view, &l);
+    let direct = scale_rgb(PI * light_intensity, &brdf);
+    return Rgb {
+        r: f64::max(direct.r, ambient.r),
+        g: f64::max(direct.g, ambient.g),
+        b: f64::max(direct.b, ambient.b),
+    };
+}
+
+/// Computes the share of the hemisphere above a point that no object within the ambient occlusion distance
+/// occludes, weighted by the cosine (1.0 if the settings take no ambient occlusion samples).
+fn compute_ambient_occlusion(
+    point: &Vector3,
+    normal: &Vector3,
+    time: f64,
+    scene: &Scene,
+    rng: &mut Rng,
+) -> f64 {
+    let samples = scene.settings.ambient_occlusion_samples;
+    if samples == 0 {
+        return 1.0;
+    }
+
+    let mut unoccluded = 0;
+    for _ in 0..samples {
+        let ray = Ray {
+            origin: *point,
+            direction: sample_cosine_hemisphere(&normal, rng.next_f64(), rng.next_f64()),
+            time,
+        };
+        if closest_intersection_point(&ray, &scene).k >= scene.settings.ambient_occlusion_distance {
+            unoccluded += 1;
         }
+    }
+    return unoccluded as f64 / samples as f64;
+}
+
+/// Renders the ambient occlusion at the point a ray hits (its closest intersection) as a shade of grey
+/// (white where it hits nothing).
+fn trace_ambient_occlusion(
+    r: &Ray,
+    closest_point: &Intersection,
+    scene: &Scene,
+    rng: &mut Rng,
+) -> Color {
+    let a: f64 = closest_point.k - HUGE_VALUE;
+    let ambient_occlusion = if a.abs() < TINY_VALUE {
+        1.0
+    } else {
+        compute_ambient_occlusion(
+            &closest_point.point,
+            &closest_point.compute_normal(),
+            r.time,
+            &scene,
+            rng,
+        )
+    };
+    let grey = (ambient_occlusion * 255.0).round() as u8;
+    return Color {
+        r: grey,
+        g: grey,
+        b: grey,
+    };
+}
+
+/// Computes the intensity of the light arriving at a given point (at a given time), 0.0 if the point lies in the shadow.
+fn compute_light_intensity(point: &Vector3, normal: &Vector3, time: f64, scene: &Scene) -> f64 {
+    let light = &scene.light;
+
+    // a point that coincides with the light source has no direction to it:
+    let direction_to_light = match try_normalize(&difference(&light.pos, &point)) {
+        Some(direction) => direction,
+        None => return 0.0,
+    };
+    let shadow_ray = Ray {
+        origin: *point,
+        direction: direction_to_light,
+        time,
+    };
+
+    // compute the closest point in the direction of light:
+    let closest_point_l = closest_intersection_point(&shadow_ray, &scene);
+
+    // if there is no intersection or the closest one is behind the light source:
+    let b: f64 = closest_point_l.k - HUGE_VALUE;
+    let d = distance(&point, &light.pos);
+    return if b.abs() < TINY_VALUE || d < closest_point_l.k {
+        dot_product(&normal, &direction_to_light)
+    } else {
+        0.0
     };
 }
 
@@ -199,7 +1036,7 @@ fn scale_color(a: f64, c: &Color) -> Color {
     };
 }
 
-fn mix_colors(a: f64, c1: &Color, b: f64, c2: &Color) -> Color {
+pub fn mix_colors(a: f64, c1: &Color, b: f64, c2: &Color) -> Color {
     let red = (a * (c1.r as f64) + b * (c2.r as f64)).round() as u8;
     let green = (a * (c1.g as f64) + b * (c2.g as f64)).round() as u8;
     let blue = (a * (c1.b as f64) + b * (c2.b as f64)).round() as u8;
@@ -211,6 +1048,19 @@ fn mix_colors(a: f64, c1: &Color, b: f64, c2: &Color) -> Color {
     };
 }
 
+/// Multiplies two colors component-wise, as if their components were in [0.0, 1.0].
+pub fn multiply_colors(c1: &Color, c2: &Color) -> Color {
+    let red = ((c1.r as f64) * (c2.r as f64) / 255.0).round() as u8;
+    let green = ((c1.g as f64) * (c2.g as f64) / 255.0).round() as u8;
+    let blue = ((c1.b as f64) * (c2.b as f64) / 255.0).round() as u8;
+
+    return Color {
+        r: red,
+        g: green,
+        b: blue,
+    };
+}
+
 fn set_pixel_color(data: &mut Clamped<Vec<u8>>, width: u32, x: u32, y: u32, c: &Color) {
     let i = compute_image_data_array_index(width, x, y);
     data[i as usize] = c.r;
@@ -224,16 +1074,13 @@ fn compute_image_data_array_index(width: u32, x: u32, y: u32) -> u32 {
     return (x + y * width) * 4;
 }
 
-fn closest_intersection_point<'b>(
-    r: &Ray,
-    spheres: &'b [Sphere],
-    planes: &'b [Plane],
-) -> Intersection<'b> {
+fn closest_intersection_point<'b>(r: &Ray, scene: &'b Scene) -> Intersection<'b> {
     let mut smallest_k = HUGE_VALUE;
     let mut closest_object: GeomPrimitive = UNKNOWN;
+    let mut flip_normal = false;
 
     // does the ray intersect a sphere?
-    for sphere in spheres.iter() {
+    for sphere in scene.spheres.iter() {
         let k = ray_sphere_intersection_point(&r, &sphere);
         if k < TINY_VALUE {
             continue;
@@ -245,7 +1092,7 @@ fn closest_intersection_point<'b>(
     }
 
     // ...or any planes?
-    for plane in planes.iter() {
+    for plane in scene.planes.iter() {
         let k = ray_plane_intersection_point(&r, &plane);
         if k < TINY_VALUE {
             continue;
@@ -256,19 +1103,82 @@ fn closest_intersection_point<'b>(
         }
     }
 
+    // ...or any cuboids?
+    for cuboid in scene.cuboids.iter() {
+        let k = ray_cuboid_intersection_point(&r, &cuboid);
+        if k < TINY_VALUE {
+            continue;
+        }
+        if k < smallest_k {
+            smallest_k = k;
+            closest_object = GeomPrimitive::Cuboid(cuboid);
+        }
+    }
+
+    // ...or any SDF objects?
+    for sdf_object in scene.sdf_objects.iter() {
+        let k = sdf_object.intersect(&r);
+        if k < TINY_VALUE {
+            continue;
+        }
+        if k < smallest_k {
+            smallest_k = k;
+            closest_object = GeomPrimitive::Sdf(sdf_object);
+        }
+    }
+
+    // ...or any heightfields?
+    for heightfield in scene.heightfields.iter() {
+        let k = heightfield.intersect(&r);
+        if k < smallest_k {
+            smallest_k = k;
+            closest_object = GeomPrimitive::Heightfield(heightfield);
+        }
+    }
+
+    // ...or any quadrics?
+    for quadric in scene.quadrics.iter() {
+        let k = quadric.intersect(&r);
+        if k < smallest_k {
+            smallest_k = k;
+            closest_object = GeomPrimitive::Quadric(quadric);
+            // (a quadric's gradient may point towards the ray, e.g. inside a dish:)
+            let gradient = quadric.compute_normal(&follow_ray(&r, k));
+            flip_normal = dot_product(&gradient, &r.direction) > 0.0;
+        }
+    }
+
+    // ...or any CSG objects?
+    for csg in scene.csg_objects.iter() {
+        let hit = match csg.closest_hit(&r) {
+            Some(hit) => hit,
+            None => continue,
+        };
+        if hit.k < smallest_k {
+            smallest_k = hit.k;
+            closest_object = hit.geom_object;
+            flip_normal = hit.flip_normal;
+        }
+    }
+
     let p = follow_ray(&r, smallest_k);
     return Intersection {
         k: smallest_k,
+        time: r.time,
         point: p,
+        direction: r.direction,
         geom_object: closest_object,
+        flip_normal,
     };
 }
 
-fn ray_sphere_intersection_point(ray: &Ray, sphere: &Sphere) -> f64 {
+/// Computes the interval in which a given ray lies inside a given sphere, if it hits it at all.
+pub fn ray_sphere_span(ray: &Ray, sphere: &Sphere) -> Option<(f64, f64)> {
+    let pos = sphere.pos_at(ray.time);
     let new_origin = Vector3 {
-        x: ray.origin.x - sphere.pos.x,
-        y: ray.origin.y - sphere.pos.y,
-        z: ray.origin.z - sphere.pos.z,
+        x: ray.origin.x - pos.x,
+        y: ray.origin.y - pos.y,
+        z: ray.origin.z - pos.z,
     };
     let r = Ray {
         origin: new_origin,
@@ -279,37 +1189,98 @@ fn ray_sphere_intersection_point(ray: &Ray, sphere: &Sphere) -> f64 {
     let b = 2.0 * dot_product(&r.direction, &r.origin);
     let c = dot_product(&r.origin, &r.origin) - sphere.r * sphere.r;
 
+    return solve_quadratic(a, b, c);
+}
+
+/// Solves a x² + b x + c = 0 for x, returns the smaller and the larger root if there are any.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
     let discriminant = b * b - 4.0 * a * c;
     if discriminant < 0.0 {
-        return HUGE_VALUE;
+        return None;
     }
 
     let dsqrt = discriminant.sqrt();
     let t0 = (-b - dsqrt) / (2.0 * a);
     let t1 = (-b + dsqrt) / (2.0 * a);
 
-    let max = f64::max(t0, t1);
-    let min = f64::min(t0, t1);
+    return Some((f64::min(t0, t1), f64::max(t0, t1)));
+}
 
-    if max < 0.0 {
-        return HUGE_VALUE;
-    }
+fn ray_sphere_intersection_point(ray: &Ray, sphere: &Sphere) -> f64 {
+    return closest_span_point(ray_sphere_span(&ray, &sphere));
+}
 
-    return if min < 0.0 { max } else { min };
+/// Computes the interval in which a given ray lies inside a given cuboid, if it hits it at all.
+pub fn ray_cuboid_span(r: &Ray, cuboid: &Cuboid) -> Option<(f64, f64)> {
+    // a moving cuboid is where it was at time 0.0 for a ray that moves along with it:
+    let relative_ray = Ray {
+        origin: difference(&r.origin, &scale_vector(r.time, &cuboid.velocity)),
+        ..*r
+    };
+    return ray_box_span(&relative_ray, &cuboid.min, &cuboid.max);
 }
 
-fn ray_plane_intersection_point(r: &Ray, plane: &Plane) -> f64 {
-    let d = dot_product(&r.direction, &plane.n);
+/// Computes the interval in which a given ray lies inside the axis-aligned box spanned by min and max.
+pub fn ray_box_span(r: &Ray, min: &Vector3, max: &Vector3) -> Option<(f64, f64)> {
+    let (x_min, x_max) = ray_slab_span(r.origin.x, r.direction.x, min.x, max.x);
+    let (y_min, y_max) = ray_slab_span(r.origin.y, r.direction.y, min.y, max.y);
+    let (z_min, z_max) = ray_slab_span(r.origin.z, r.direction.z, min.z, max.z);
 
-    return if d.abs() < TINY_VALUE {
-        HUGE_VALUE
-    } else {
+    let min = f64::max(x_min, f64::max(y_min, z_min));
+    let max = f64::min(x_max, f64::min(y_max, z_max));
+    return if min > max { None } else { Some((min, max)) };
+}
+
+/// Computes the interval in which a ray lies between two parallel, axis-aligned planes.
+fn ray_slab_span(origin: f64, direction: f64, min: f64, max: f64) -> (f64, f64) {
+    if direction == 0.0 {
+        // the ray runs parallel to the planes:
+        return if origin < min || origin > max {
+            (f64::INFINITY, f64::NEG_INFINITY)
+        } else {
+            (f64::NEG_INFINITY, f64::INFINITY)
+        };
+    }
+
+    let t0 = (min - origin) / direction;
+    let t1 = (max - origin) / direction;
+    return (f64::min(t0, t1), f64::max(t0, t1));
+}
+
+fn ray_cuboid_intersection_point(r: &Ray, cuboid: &Cuboid) -> f64 {
+    return closest_span_point(ray_cuboid_span(&r, &cuboid));
+}
+
+/// Picks the closest point of an interval that lies in front of the ray's origin.
+fn closest_span_point(span: Option<(f64, f64)>) -> f64 {
+    let (min, max) = match span {
+        Some(span) => span,
+        None => return HUGE_VALUE,
+    };
+
+    if max < 0.0 {
+        return HUGE_VALUE;
+    }
+
+    return if min < 0.0 { max } else { min };
+}
+
+fn ray_plane_intersection_point(r: &Ray, plane: &Plane) -> f64 {
+    let d = dot_product(&r.direction, &plane.n);
+
+    return if d.abs() < TINY_VALUE {
+        HUGE_VALUE
+    } else {
         (-plane.d - dot_product(&r.origin, &plane.n)) / d
     };
 }
 
 fn follow_ray(r: &Ray, k: f64) -> Vector3 {
-    let dir = normalize(&r.direction);
+    // a degenerate ray doesn't go anywhere:
+    let dir = match try_normalize(&r.direction) {
+        Some(dir) => dir,
+        None => return r.origin,
+    };
     return Vector3 {
         x: &r.origin.x + k * dir.x,
         y: &r.origin.y + k * dir.y,
@@ -317,18 +1288,37 @@ fn follow_ray(r: &Ray, k: f64) -> Vector3 {
     };
 }
 
+#[derive(Clone, PartialEq)]
 pub struct Sphere {
+    /// The position at time 0.0.
     pub pos: Vector3,
     pub r: f64,
-    pub color: Color,
-    pub reflect: bool,
+    /// The distance the sphere moves per unit of time.
+    pub velocity: Vector3,
+    pub material: Material,
 }
 
+impl Sphere {
+    pub fn pos_at(&self, time: f64) -> Vector3 {
+        return sum(&self.pos, &scale_vector(time, &self.velocity));
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Plane {
     pub n: Vector3,
     pub d: f64,
-    pub color: Color,
-    pub reflect: bool,
+    pub material: Material,
+}
+
+/// An axis-aligned box, spanned by its minimum and maximum corner (at time 0.0).
+#[derive(Clone, PartialEq)]
+pub struct Cuboid {
+    pub min: Vector3,
+    pub max: Vector3,
+    /// The distance the cuboid moves per unit of time.
+    pub velocity: Vector3,
+    pub material: Material,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -338,6 +1328,7 @@ pub struct Color {
     pub b: u8,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Light {
     pub pos: Vector3,
 }
@@ -345,203 +1336,1312 @@ pub struct Light {
 pub struct Scene {
     pub spheres: Vec<Sphere>,
     pub planes: Vec<Plane>,
+    pub cuboids: Vec<Cuboid>,
+    pub csg_objects: Vec<Csg>,
+    pub sdf_objects: Vec<SdfObject>,
+    pub heightfields: Vec<Heightfield>,
+    pub quadrics: Vec<Quadric>,
     pub light: Light,
+    pub camera: Camera,
+    pub background: Background,
+    pub settings: RenderSettings,
+}
+
+/// A scene together with what rendering it needs that only changes with the scene, which is collected
+/// once per render instead of at every shading point.
+struct PreparedScene<'a> {
+    scene: &'a Scene,
+    /// The emissive spheres and cuboids (the only emitters that light other objects), with their emission.
+    emitters: Vec<(GeomPrimitive<'a>, &'a Rgb)>,
+}
+
+impl<'a> PreparedScene<'a> {
+    fn new(scene: &'a Scene) -> PreparedScene<'a> {
+        let mut emitters = vec![];
+        for sphere in &scene.spheres {
+            if let Some(emission) = &sphere.material.emission {
+                emitters.push((GeomPrimitive::Sphere(sphere), emission));
+            }
+        }
+        for cuboid in &scene.cuboids {
+            if let Some(emission) = &cuboid.material.emission {
+                emitters.push((GeomPrimitive::Cuboid(cuboid), emission));
+            }
+        }
+        return PreparedScene { scene, emitters };
+    }
+}
+
+impl Deref for PreparedScene<'_> {
+    type Target = Scene;
+
+    fn deref(&self) -> &Scene {
+        return self.scene;
+    }
+}
+
+/// Settings that trade the quality of a rendering for its speed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    /// The maximum number of secondary rays between a primary ray and a leaf of its ray tree,
+    /// i.e. of reflections and refractions combined.
+    pub max_depth: u32,
+    pub max_reflection_depth: u32,
+    /// Secondary rays that would contribute less than this share of their pixel's color aren't traced.
+    pub min_contribution: f64,
+    /// The number of rays that estimate how much of the ambient light reaches a point (0 disables it).
+    pub ambient_occlusion_samples: u32,
+    /// Objects farther away than this don't occlude the ambient light.
+    pub ambient_occlusion_distance: f64,
+    pub output: RenderOutput,
+}
+
+/// What a rendering shows.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RenderOutput {
+    /// The shaded scene.
+    Color,
+    /// Only the ambient occlusion of the surfaces the primary rays hit.
+    AmbientOcclusion,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        return RenderSettings {
+            max_depth: 5,
+            max_reflection_depth: 5,
+            min_contribution: 0.01,
+            ambient_occlusion_samples: 8,
+            ambient_occlusion_distance: 50.0,
+            output: RenderOutput::Color,
+        };
+    }
+}
+
+/// The position of a ray in its pixel's ray tree.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct RayTreeDepth {
+    reflections: u32,
+    refractions: u32,
+    /// The share of the ray's color in its pixel's color.
+    contribution: f64,
+}
+
+const PRIMARY_RAY: RayTreeDepth = RayTreeDepth {
+    reflections: 0,
+    refractions: 0,
+    contribution: 1.0,
+};
+
+impl RayTreeDepth {
+    /// The position of a ray that is reflected with a given weight.
+    fn reflected(&self, weight: f64) -> RayTreeDepth {
+        return RayTreeDepth {
+            reflections: self.reflections + 1,
+            refractions: self.refractions,
+            contribution: self.contribution * weight,
+        };
+    }
+
+    /// Checks whether the settings allow a ray at this position to be traced.
+    fn is_within(&self, settings: &RenderSettings) -> bool {
+        return self.reflections + self.refractions <= settings.max_depth
+            && self.reflections <= settings.max_reflection_depth
+            && self.contribution >= settings.min_contribution;
+    }
+}
+
+/// Diagnostics that are collected while rendering a frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// The number of pixel samples whose color had a NaN channel.
+    pub nan_samples: u32,
+    /// The number of (other) pixel samples whose color had an infinite channel.
+    pub inf_samples: u32,
+}
+
+impl RenderStats {
+    /// Counts a given sample's color if it is not finite.
+    fn record(&mut self, c: &Rgb) {
+        if c.r.is_nan() || c.g.is_nan() || c.b.is_nan() {
+            self.nan_samples += 1;
+        } else if !c.is_finite() {
+            self.inf_samples += 1;
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Ray {
     pub origin: Vector3,
     pub direction: Vector3,
+    /// The time at which the ray travels through the scene, moving objects are hit where they are then.
+    pub time: f64,
+}
+
+/// The derivatives of a ray's origin and (unit) direction with respect to the pixel coordinates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayDifferential {
+    pub dodx: Vector3,
+    pub dddx: Vector3,
+    pub dody: Vector3,
+    pub dddy: Vector3,
 }
 
 struct Intersection<'a> {
     pub k: f64,
+    /// The time of the ray that hits the object.
+    pub time: f64,
     pub point: Vector3,
+    /// The direction of the ray that hits the object.
+    pub direction: Vector3,
     pub geom_object: GeomPrimitive<'a>,
+    pub flip_normal: bool,
+}
+
+impl Intersection<'_> {
+    /// Computes the texture coordinates at the intersection point.
+    fn compute_uv(&self) -> Uv {
+        return self
+            .geom_object
+            .compute_uv(&self.object_point(), &self.compute_normal());
+    }
+
+    /// The intersection point relative to where the hit object was at time 0.0.
+    fn object_point(&self) -> Vector3 {
+        return difference(&self.point, &self.geom_object.displacement(self.time));
+    }
+
+    /// Computes the normal at the intersection point, pointing away from the hit object.
+    fn compute_normal(&self) -> Vector3 {
+        return self.compute_normal_at(&self.point);
+    }
+
+    /// Computes the hit object's normal at a point close to the intersection point.
+    fn compute_normal_at(&self, point: &Vector3) -> Vector3 {
+        let object_point = difference(&point, &self.geom_object.displacement(self.time));
+        let normal = self.geom_object.compute_normal(&object_point);
+        // (the normal is undefined where the gradient vanishes, e.g. at a cone's apex:)
+        if !is_finite(&normal) {
+            return match try_normalize(&self.direction) {
+                Some(dir) => scale_vector(-1.0, &dir),
+                None => normal,
+            };
+        }
+        return if self.flip_normal {
+            scale_vector(-1.0, &normal)
+        } else {
+            normal
+        };
+    }
 }
 
-struct Point {
+/// A point on the image, relative to its center.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Point {
     pub x: f64,
     pub y: f64,
 }
 
-enum GeomPrimitive<'a> {
+/// The kinds of objects that a scene consists of.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ObjectKind {
+    Sphere,
+    Plane,
+    Cuboid,
+    Csg,
+    Sdf,
+    Heightfield,
+    Quadric,
+}
+
+impl ObjectKind {
+    /// The kind's name, e.g. "sphere".
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ObjectKind::Sphere => "sphere",
+            ObjectKind::Plane => "plane",
+            ObjectKind::Cuboid => "cuboid",
+            ObjectKind::Csg => "csg",
+            ObjectKind::Sdf => "sdf",
+            ObjectKind::Heightfield => "heightfield",
+            ObjectKind::Quadric => "quadric",
+        };
+    }
+
+    /// Finds the kind with a given name.
+    pub fn from_name(name: &str) -> Option<ObjectKind> {
+        return [
+            ObjectKind::Sphere,
+            ObjectKind::Plane,
+            ObjectKind::Cuboid,
+            ObjectKind::Csg,
+            ObjectKind::Sdf,
+            ObjectKind::Heightfield,
+            ObjectKind::Quadric,
+        ]
+        .iter()
+        .find(|kind| kind.name() == name)
+        .copied();
+    }
+}
+
+/// Identifies an object of a scene by its kind and its index in the scene's objects of that kind.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ObjectId {
+    pub kind: ObjectKind,
+    pub index: usize,
+}
+
+/// Identifies a primitive of a scene: an object, and for CSG objects, the index of the primitive among
+/// the solids that it combines (otherwise 0).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PrimitiveId {
+    pub object: ObjectId,
+    pub leaf: usize,
+}
+
+/// What the primary ray of a pixel hits, remembered while the camera and the scene's geometry stay the same.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PrimaryHit {
+    /// The ray wasn't traced yet.
+    Unknown,
+    Background,
+    Object {
+        primitive: PrimitiveId,
+        k: f64,
+        flip_normal: bool,
+    },
+}
+
+/// The object that a pixel shows, and where its primary ray hits it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pick {
+    pub object: ObjectId,
+    pub point: Vector3,
+    /// The (unit) normal of the object's surface, pointing away from it.
+    pub normal: Vector3,
+    /// The distance from the ray's origin to the point.
+    pub distance: f64,
+}
+
+#[derive(Copy, Clone)]
+pub enum GeomPrimitive<'a> {
     Plane(&'a Plane),
     Sphere(&'a Sphere),
+    Cuboid(&'a Cuboid),
+    Sdf(&'a SdfObject),
+    Heightfield(&'a Heightfield),
+    Quadric(&'a Quadric),
     UNKNOWN,
 }
 
 impl GeomPrimitive<'_> {
+    /// Checks whether two primitives are the same sphere or cuboid of a scene.
+    fn is_same_as(&self, other: &GeomPrimitive) -> bool {
+        return match (self, other) {
+            (GeomPrimitive::Sphere(a), GeomPrimitive::Sphere(b)) => std::ptr::eq(*a, *b),
+            (GeomPrimitive::Cuboid(a), GeomPrimitive::Cuboid(b)) => std::ptr::eq(*a, *b),
+            _ => false,
+        };
+    }
+
+    /// How far the primitive has moved at a given time (its other methods take points relative to
+    /// where it was at time 0.0).
+    fn displacement(&self, time: f64) -> Vector3 {
+        return match self {
+            GeomPrimitive::Sphere(sphere) => scale_vector(time, &sphere.velocity),
+            GeomPrimitive::Cuboid(cuboid) => scale_vector(time, &cuboid.velocity),
+            _ => ZERO_VECTOR3,
+        };
+    }
+
     fn compute_normal(&self, point: &Vector3) -> Vector3 {
         let v: Vector3 = match self {
             GeomPrimitive::Plane(plane) => plane.n,
             GeomPrimitive::Sphere(sphere) => difference(&point, &sphere.pos),
+            GeomPrimitive::Cuboid(cuboid) => compute_cuboid_normal(&cuboid, &point),
+            GeomPrimitive::Sdf(sdf_object) => sdf_object.compute_normal(&point),
+            GeomPrimitive::Heightfield(heightfield) => heightfield.compute_normal(&point),
+            GeomPrimitive::Quadric(quadric) => quadric.compute_normal(&point),
             GeomPrimitive::UNKNOWN => panic!("Unknown geom. primitive!"),
         };
         return normalize(&v);
     }
 
-    fn reflects(&self) -> bool {
+    /// Computes the texture coordinates at a given point on the primitive, with a given normal.
+    fn compute_uv(&self, point: &Vector3, normal: &Vector3) -> Uv {
         return match self {
-            GeomPrimitive::Plane(plane) => plane.reflect,
-            GeomPrimitive::Sphere(sphere) => sphere.reflect,
+            GeomPrimitive::Plane(plane) => planar_uv(&point, &plane.n),
+            GeomPrimitive::Sphere(sphere) => spherical_uv(&difference(&point, &sphere.pos)),
+            GeomPrimitive::Heightfield(heightfield) => heightfield.compute_uv(&point),
+            GeomPrimitive::Cuboid(_) | GeomPrimitive::Sdf(_) | GeomPrimitive::Quadric(_) => {
+                box_uv(&point, &normal)
+            }
             GeomPrimitive::UNKNOWN => panic!("Unknown geom. primitive!"),
         };
     }
 
-    fn color(&self) -> Color {
+    /// Computes the derivatives of a point on the primitive with respect to its texture coordinates.
+    fn compute_uv_derivatives(&self, point: &Vector3, normal: &Vector3) -> (Vector3, Vector3) {
         return match self {
-            GeomPrimitive::Plane(plane) => plane.color.clone(), // TODO: should work without the clone trait, see compute_normal()!
-            GeomPrimitive::Sphere(sphere) => sphere.color.clone(), // TODO: should work without the clone trait, see compute_normal()!
+            GeomPrimitive::Plane(plane) => tangent_basis(&plane.n),
+            GeomPrimitive::Sphere(sphere) => {
+                spherical_uv_derivatives(&difference(&point, &sphere.pos))
+            }
+            GeomPrimitive::Heightfield(heightfield) => heightfield.compute_uv_derivatives(),
+            GeomPrimitive::Cuboid(_) | GeomPrimitive::Sdf(_) | GeomPrimitive::Quadric(_) => {
+                box_uv_derivatives(&normal)
+            }
+            GeomPrimitive::UNKNOWN => panic!("Unknown geom. primitive!"),
+        };
+    }
+
+    pub fn material(&self) -> &Material {
+        return match self {
+            GeomPrimitive::Plane(plane) => &plane.material,
+            GeomPrimitive::Sphere(sphere) => &sphere.material,
+            GeomPrimitive::Cuboid(cuboid) => &cuboid.material,
+            GeomPrimitive::Sdf(sdf_object) => &sdf_object.material,
+            GeomPrimitive::Heightfield(heightfield) => &heightfield.material,
+            GeomPrimitive::Quadric(quadric) => &quadric.material,
             GeomPrimitive::UNKNOWN => panic!("Unknown geom. primitive!"),
         };
     }
 }
 
+/// Computes the normal of the cuboid's face that lies closest to a given point.
+fn compute_cuboid_normal(cuboid: &Cuboid, point: &Vector3) -> Vector3 {
+    let faces = [
+        (
+            point.x - cuboid.min.x,
+            Vector3 {
+                x: -1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        ),
+        (
+            cuboid.max.x - point.x,
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        ),
+        (
+            point.y - cuboid.min.y,
+            Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+        ),
+        (
+            cuboid.max.y - point.y,
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        ),
+        (
+            point.z - cuboid.min.z,
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+        ),
+        (
+            cuboid.max.z - point.z,
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+        ),
+    ];
+
+    let mut closest_face = faces[0];
+    for face in faces.iter() {
+        if face.0.abs() < closest_face.0.abs() {
+            closest_face = *face;
+        }
+    }
+    return closest_face.1;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    // TODO: the camera direction should be part of the scene struct?
-    const TEST_CAMERA_DIRECTION: Vector3 = Vector3 {
-        x: 0.0,
-        y: 0.0,
-        z: 1.0,
-    };
-
-    const TEST_CAMERA_UP: Vector3 = Vector3 {
-        x: 0.0,
-        y: 1.0,
-        z: 0.0,
-    };
-
-    const ZERO_VECTOR3: Vector3 = Vector3 {
-        x: 0.0,
-        y: 0.0,
-        z: 0.0,
-    };
-
     #[test]
     fn test_normalize_x_y() {
         let width = 12;
         let height = 12;
+        let assert_point = |p: Point, x: f64, y: f64| {
+            assert!(
+                (p.x - x).abs() < 0.000001 && (p.y - y).abs() < 0.000001,
+                "{:?}",
+                p
+            );
+        };
 
-        let p1 = normalize_x_y(0, 0, width, height);
-        assert_eq!(p1.x, -0.5);
-        assert_eq!(p1.y, -0.5);
+        // pixel centers, with the image's top row at the top:
+        assert_point(
+            normalize_x_y(0, 0, width, height),
+            -0.5 + 1.0 / 24.0,
+            0.5 - 1.0 / 24.0,
+        );
+        assert_point(normalize_x_y(6, 9, width, height), 1.0 / 24.0, -3.5 / 12.0);
 
-        let p2 = normalize_x_y(6, 9, width, height);
-        assert_eq!(p2.x, 0.0);
-        assert_eq!(p2.y, 0.25);
+        // the pixels of a wide (or tall) image are square, too:
+        assert_point(
+            normalize_x_y(39, 19, 40, 20),
+            0.5 - 1.0 / 80.0,
+            -0.25 + 1.0 / 80.0,
+        );
+        assert_point(
+            normalize_x_y(0, 0, 20, 40),
+            -0.5 + 1.0 / 40.0,
+            1.0 - 1.0 / 40.0,
+        );
     }
 
     #[test]
     fn test_compute_ray() {
-        let camera_right = cross_product(&TEST_CAMERA_DIRECTION, &TEST_CAMERA_UP);
+        let camera = Camera::default();
         let width = 12;
         let height = 12;
+        let assert_ray = |r: Option<Ray>, direction: &Vector3| {
+            let r = r.unwrap();
+            assert_eq!(r.origin, ZERO_VECTOR3);
+            assert!(
+                distance(&r.direction, &normalize(&direction)) < 0.000001,
+                "{:?}",
+                r
+            );
+        };
 
-        // compute some rays:
-        let r1 = compute_ray(
-            0,
-            0,
-            width,
-            height,
-            &TEST_CAMERA_DIRECTION,
-            &TEST_CAMERA_UP,
-            &camera_right,
-        );
-        let er1 = Ray {
-            origin: ZERO_VECTOR3,
-            direction: Vector3 {
-                x: 0.5,
-                y: -0.5,
+        // compute some rays (the camera's right vector is -x):
+        let r1 = compute_ray(0, 0, width, height, &camera);
+        assert_ray(
+            r1,
+            &Vector3 {
+                x: 11.0 / 24.0,
+                y: 11.0 / 24.0,
                 z: 1.0,
             },
-        };
-        assert_eq!(r1, er1);
+        );
 
-        let r2 = compute_ray(
-            6,
-            9,
-            width,
-            height,
-            &TEST_CAMERA_DIRECTION,
-            &TEST_CAMERA_UP,
-            &camera_right,
+        let r2 = compute_ray(6, 9, width, height, &camera);
+        assert_ray(
+            r2,
+            &Vector3 {
+                x: -1.0 / 24.0,
+                y: -3.5 / 12.0,
+                z: 1.0,
+            },
         );
-        let er2 = Ray {
-            origin: ZERO_VECTOR3,
-            direction: Vector3 {
-                x: 0.0,
-                y: 0.25,
+
+        // a wide image has a narrower vertical field of view, with square pixels:
+        let r3 = compute_ray(39, 0, 40, 20, &camera);
+        assert_ray(
+            r3,
+            &Vector3 {
+                x: -0.5 + 1.0 / 80.0,
+                y: 0.25 - 1.0 / 80.0,
                 z: 1.0,
             },
-        };
-        assert_eq!(r2, er2);
+        );
+        let differential = compute_ray_differential(20, 10, 40, 20, &camera);
+        assert!((length(&differential.dddx) - length(&differential.dddy)).abs() < 0.000001);
+        assert!(differential.dddx.x < 0.0 && differential.dddy.y < 0.0);
     }
 
     #[test]
     fn test_compute_and_trace_ray() {
-        let camera_right = cross_product(&TEST_CAMERA_DIRECTION, &TEST_CAMERA_UP);
         let width = 12;
         let height = 12;
 
         // a test scene:
         let scene = create_small_test_scene();
+        let mut stats = RenderStats::default();
 
         // trace some rays:
         let c1 = compute_and_trace_ray(
             0,
-            0,
+            11,
             width,
             height,
-            &TEST_CAMERA_DIRECTION,
-            &TEST_CAMERA_UP,
-            &camera_right,
-            &scene,
+            &PreparedScene::new(&scene),
+            &mut stats,
         );
         assert_eq!(c1, Color { r: 0, g: 0, b: 0 });
 
-        let c2 = compute_and_trace_ray(
-            6,
-            9,
-            width,
-            height,
-            &TEST_CAMERA_DIRECTION,
-            &TEST_CAMERA_UP,
-            &camera_right,
-            &scene,
-        );
+        let c2 =
+            compute_and_trace_ray(6, 2, width, height, &PreparedScene::new(&scene), &mut stats);
         assert_eq!(
             c2,
             Color {
-                r: 148,
-                g: 148,
-                b: 148,
+                r: 182,
+                g: 182,
+                b: 182,
             }
         );
 
         let c3 = compute_and_trace_ray(
             11,
-            11,
+            0,
             width,
             height,
-            &TEST_CAMERA_DIRECTION,
-            &TEST_CAMERA_UP,
-            &camera_right,
-            &scene,
+            &PreparedScene::new(&scene),
+            &mut stats,
         );
         assert_eq!(
             c3,
             Color {
-                r: 112,
-                g: 112,
-                b: 112,
+                r: 122,
+                g: 122,
+                b: 122,
             }
         );
     }
 
+    #[test]
+    fn test_depth_of_field() {
+        let mut scene = create_small_test_scene();
+        let (width, height) = (100, 100);
+
+        // a pinhole camera has nothing to focus:
+        assert_eq!(autofocus(&mut scene, 50, 25, width, height), None);
+
+        scene.camera.lens = Some(ThinLens {
+            aperture_radius: 10.0,
+            focal_distance: 1000.0,
+            aperture: Aperture::Blades {
+                count: 6,
+                rotation: 0.0,
+            },
+            samples: 16,
+        });
+        // a pixel just outside of the sphere's silhouette, which is blurred into it:
+        let mut stats = RenderStats::default();
+        let blurred = compute_and_trace_ray(
+            81,
+            66,
+            width,
+            height,
+            &PreparedScene::new(&scene),
+            &mut stats,
+        );
+        assert!(blurred.r > 0, "{:?}", blurred);
+
+        // ...unless we focus on the sphere:
+        let focal_distance = autofocus(&mut scene, 70, 66, width, height).unwrap();
+        let r = compute_ray(70, 66, width, height, &scene.camera).unwrap();
+        let (k, _) = ray_sphere_span(&r, &scene.spheres[0]).unwrap();
+        assert!(
+            (focal_distance - k * r.direction.z).abs() < 0.000001,
+            "{}",
+            focal_distance
+        );
+        let sharp = compute_and_trace_ray(
+            81,
+            66,
+            width,
+            height,
+            &PreparedScene::new(&scene),
+            &mut stats,
+        );
+        assert_eq!(sharp, Color { r: 0, g: 0, b: 0 });
+
+        // the floor is hit 60 / 0.245 units away, the background never:
+        let focal_distance = autofocus(&mut scene, 50, 25, width, height).unwrap();
+        assert!((focal_distance - 60.0 / 0.245).abs() < 0.000001);
+        assert_eq!(autofocus(&mut scene, 0, 99, width, height), None);
+        assert_eq!(scene.camera.lens.unwrap().focal_distance, focal_distance);
+        assert_eq!(stats, RenderStats::default());
+    }
+
+    #[test]
+    fn test_pick() {
+        let mut scene = create_empty_test_scene();
+        let sphere = |x: f64| Sphere {
+            pos: Vector3 {
+                x,
+                y: 0.0,
+                z: 100.0,
+            },
+            r: 10.0,
+            velocity: ZERO_VECTOR3,
+            material: Material::new(Color { r: 255, g: 0, b: 0 }, false),
+        };
+        scene.spheres.push(sphere(100.0));
+        scene.spheres.push(sphere(0.0));
+        // (the camera's right is -x:)
+        scene.csg_objects.push(Csg::Node {
+            operation: CsgOperation::Union,
+            left: Box::new(Csg::Sphere(sphere(200.0))),
+            right: Box::new(Csg::Sphere(sphere(-30.0))),
+        });
+
+        let center = pick(&scene, 50, 50, 101, 101).unwrap();
+        assert_eq!(
+            center.object,
+            ObjectId {
+                kind: ObjectKind::Sphere,
+                index: 1
+            }
+        );
+        assert_eq!(center.object.kind.name(), "sphere");
+        assert_eq!(
+            center.point,
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 90.0
+            }
+        );
+        assert_eq!(
+            center.normal,
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0
+            }
+        );
+        assert_eq!(center.distance, 90.0);
+
+        let csg = pick(&scene, 80, 50, 101, 101).unwrap();
+        assert_eq!(
+            csg.object,
+            ObjectId {
+                kind: ObjectKind::Csg,
+                index: 0
+            }
+        );
+        assert!((distance(&csg.point, &ZERO_VECTOR3) - csg.distance).abs() < 0.000001);
+        assert!(csg.point.x < -20.0);
+
+        assert_eq!(pick(&scene, 0, 0, 101, 101), None);
+    }
+
+    #[test]
+    fn test_motion_blur() {
+        let mut scene = create_empty_test_scene();
+        scene.spheres.push(Sphere {
+            pos: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 100.0,
+            },
+            r: 10.0,
+            velocity: Vector3 {
+                x: 40.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            material: Material {
+                emission: Some(Rgb {
+                    r: 1.0,
+                    g: 1.0,
+                    b: 1.0,
+                }),
+                ..Material::new(
+                    Color {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                    },
+                    false,
+                )
+            },
+        });
+
+        // the sphere has moved out of the way of a ray at time 1.0...
+        let r = Ray {
+            origin: ZERO_VECTOR3,
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            time: 0.0,
+        };
+        assert_eq!(closest_intersection_point(&r, &scene).k, 90.0);
+        let later = Ray { time: 1.0, ..r };
+        assert_eq!(closest_intersection_point(&later, &scene).k, HUGE_VALUE);
+
+        // ...and is hit (with its normals) where it is then:
+        let towards = Ray {
+            direction: normalize(&Vector3 {
+                x: 40.0,
+                y: 0.0,
+                z: 100.0,
+            }),
+            ..later
+        };
+        let intersection = closest_intersection_point(&towards, &scene);
+        assert!((intersection.k - (length(&scene.spheres[0].pos_at(1.0)) - 10.0)).abs() < 0.000001);
+        assert!(
+            distance(
+                &intersection.compute_normal(),
+                &scale_vector(-1.0, &towards.direction)
+            ) < 0.000001
+        );
+
+        // a pixel on its path is covered for a quarter of the exposure:
+        scene.camera.shutter = Some(Shutter {
+            open: 0.0,
+            close: 1.0,
+            samples: 64,
+        });
+        let mut stats = RenderStats::default();
+        let blurred =
+            compute_and_trace_ray(50, 50, 101, 101, &PreparedScene::new(&scene), &mut stats);
+        assert!(blurred.r > 30 && blurred.r < 100, "{:?}", blurred);
+
+        // ...unless the camera follows it:
+        scene.camera.velocity = scene.spheres[0].velocity;
+        let tracked =
+            compute_and_trace_ray(50, 50, 101, 101, &PreparedScene::new(&scene), &mut stats);
+        assert_eq!(tracked.r, 255);
+    }
+
+    #[test]
+    fn test_render_stats() {
+        let scene = create_small_test_scene();
+        let width = 12;
+        let height = 12;
+        let data = Clamped(vec![0; (width * height * 4) as usize]);
+
+        let (_, stats) = render_with_data_array(&scene, &data, width, height);
+        assert_eq!(stats, RenderStats::default());
+
+        let mut stats = RenderStats::default();
+        let grey = |x: f64| Rgb {
+            r: 0.5,
+            g: x,
+            b: 0.5,
+        };
+        stats.record(&grey(0.5));
+        stats.record(&grey(f64::NAN));
+        stats.record(&grey(f64::INFINITY));
+        stats.record(&grey(f64::NEG_INFINITY));
+        assert_eq!(
+            stats,
+            RenderStats {
+                nan_samples: 1,
+                inf_samples: 2,
+            }
+        );
+
+        // a NaN from anywhere in the shading (here a material's emission) is counted once per sample:
+        let mut scene = create_small_test_scene();
+        for sphere in &mut scene.spheres {
+            sphere.material.emission = Some(grey(f64::NAN));
+        }
+        let (_, stats) = render_with_data_array(&scene, &data, width, height);
+        assert!(stats.nan_samples > 0);
+        assert!(stats.nan_samples <= width * height);
+    }
+
+    #[test]
+    fn test_ray_differentials() {
+        let r = Ray {
+            origin: ZERO_VECTOR3,
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 2.0,
+            },
+            time: 0.0,
+        };
+        let differential = RayDifferential {
+            dodx: ZERO_VECTOR3,
+            dddx: Vector3 {
+                x: 0.01,
+                y: 0.0,
+                z: 0.0,
+            },
+            dody: ZERO_VECTOR3,
+            dddy: Vector3 {
+                x: 0.0,
+                y: 0.01,
+                z: 0.0,
+            },
+        };
+        let point = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 100.0,
+        };
+
+        // a pixel covers more of a surface the farther away and the more tilted it is:
+        let facing = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        };
+        let (dpdx, dpdy) = transfer_ray_differential(&r, &differential, &point, &facing).unwrap();
+        assert!((length(&dpdx) - 1.0).abs() < 0.000001);
+        assert!((length(&dpdy) - 1.0).abs() < 0.000001);
+
+        let tilted = normalize(&Vector3 {
+            x: 0.0,
+            y: -1.0,
+            z: -1.0,
+        });
+        let (dpdx, dpdy) = transfer_ray_differential(&r, &differential, &point, &tilted).unwrap();
+        assert!((length(&dpdx) - 1.0).abs() < 0.000001);
+        assert!((length(&dpdy) - 2.0_f64.sqrt()).abs() < 0.000001);
+
+        // ...while a grazing ray's footprint is unbounded:
+        let grazing = Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        assert_eq!(
+            transfer_ray_differential(&r, &differential, &point, &grazing),
+            None
+        );
+
+        // a flat mirror keeps the spread of the rays:
+        let reflected = reflect_ray_differential(
+            &r,
+            &differential,
+            &facing,
+            (&dpdx, &dpdy),
+            (&ZERO_VECTOR3, &ZERO_VECTOR3),
+        );
+        assert_eq!(reflected.dddx, differential.dddx);
+        assert_eq!(reflected.dddy, differential.dddy);
+    }
+
+    #[test]
+    fn test_emitted_light() {
+        let glow = Material {
+            emission: Some(Rgb {
+                r: 2.0,
+                g: 1.0,
+                b: 0.5,
+            }),
+            ..Material::new(
+                Color {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+                false,
+            )
+        };
+        let mut sphere_scene = create_empty_test_scene();
+        sphere_scene.spheres.push(Sphere {
+            pos: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 100.0,
+            },
+            r: 20.0,
+            velocity: ZERO_VECTOR3,
+            material: glow.clone(),
+        });
+        let mut cuboid_scene = create_empty_test_scene();
+        cuboid_scene.cuboids.push(Cuboid {
+            min: Vector3 {
+                x: -50.0,
+                y: -50.0,
+                z: 50.0,
+            },
+            max: Vector3 {
+                x: 50.0,
+                y: 50.0,
+                z: 51.0,
+            },
+            velocity: ZERO_VECTOR3,
+            material: glow,
+        });
+
+        // the light a white Lambertian surface facing the emitter reflects, averaged over many estimates:
+        let normal = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        };
+        let lambertian = |_: &Vector3| Rgb {
+            r: 1.0 / PI,
+            g: 1.0 / PI,
+            b: 1.0 / PI,
+        };
+        let mean_emitted_light = |scene: &Scene| {
+            let scene = PreparedScene::new(&scene);
+            let mut rng = Rng::new(7);
+            let estimates = 1000;
+            let mut mean = BLACK_RGB;
+            for _ in 0..estimates {
+                let e = estimate_emitted_light(
+                    &ZERO_VECTOR3,
+                    &normal,
+                    0.0,
+                    &GeomPrimitive::UNKNOWN,
+                    &scene,
+                    &mut rng,
+                    &lambertian,
+                );
+                mean = add_rgb(&mean, &scale_rgb(1.0 / estimates as f64, &e));
+            }
+            return mean;
+        };
+
+        // a sphere subtending an angle theta lets the surface reflect L sin^2(theta):
+        let e = mean_emitted_light(&sphere_scene);
+        assert!((e.r - 2.0 * 0.04).abs() < 0.002, "{:?}", e);
+        assert!((e.b - 0.5 * 0.04).abs() < 0.0005, "{:?}", e);
+
+        // ...a square (as wide as it is far away) lets it reflect L times its form factor:
+        let x = 1.0 / 2.0_f64.sqrt();
+        let form_factor = 4.0 / (2.0 * PI) * 2.0 * x * (x).atan();
+        let e = mean_emitted_light(&cuboid_scene);
+        assert!((e.r / (2.0 * form_factor) - 1.0).abs() < 0.03, "{:?}", e);
+
+        // an emitter doesn't light itself:
+        let mut rng = Rng::new(7);
+        let e = estimate_emitted_light(
+            &Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 80.0,
+            },
+            &scale_vector(-1.0, &normal),
+            0.0,
+            &GeomPrimitive::Sphere(&sphere_scene.spheres[0]),
+            &PreparedScene::new(&sphere_scene),
+            &mut rng,
+            &lambertian,
+        );
+        assert_eq!(e, BLACK_RGB);
+    }
+
+    #[test]
+    fn test_ray_tree_depth() {
+        let settings = RenderSettings {
+            max_depth: 3,
+            max_reflection_depth: 2,
+            min_contribution: 0.1,
+            ..RenderSettings::default()
+        };
+        assert!(PRIMARY_RAY.is_within(&settings));
+        let once = PRIMARY_RAY.reflected(0.5);
+        assert!(once.is_within(&settings));
+        assert!(once.reflected(0.5).is_within(&settings));
+        assert!(!once.reflected(0.5).reflected(0.5).is_within(&settings));
+        assert!(!once.reflected(0.1).is_within(&settings));
+        let refracted = RayTreeDepth {
+            refractions: 2,
+            ..once
+        };
+        assert!(!refracted.reflected(0.5).is_within(&settings));
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        let mut scene = create_empty_test_scene();
+        scene.settings.ambient_occlusion_samples = 1000;
+        scene.planes.push(Plane {
+            n: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            d: 0.0,
+            material: Material::new(Color { r: 0, g: 0, b: 0 }, false),
+        });
+        let up = scene.planes[0].n;
+        let mut rng = Rng::new(1);
+
+        // nothing occludes an open floor:
+        let ao = compute_ambient_occlusion(&ZERO_VECTOR3, &up, 0.0, &scene, &mut rng);
+        assert_eq!(ao, 1.0);
+
+        // ...but a wall next to it does (the half of the hemisphere towards it, within the AO distance):
+        scene.cuboids.push(Cuboid {
+            min: Vector3 {
+                x: 1.0,
+                y: -100.0,
+                z: -100.0,
+            },
+            max: Vector3 {
+                x: 2.0,
+                y: 100.0,
+                z: 100.0,
+            },
+            velocity: ZERO_VECTOR3,
+            material: Material::new(Color { r: 0, g: 0, b: 0 }, false),
+        });
+        let ao = compute_ambient_occlusion(&ZERO_VECTOR3, &up, 0.0, &scene, &mut rng);
+        assert!(ao > 0.45 && ao < 0.55, "{}", ao);
+        scene.settings.ambient_occlusion_samples = 0;
+        assert_eq!(
+            compute_ambient_occlusion(&ZERO_VECTOR3, &up, 0.0, &scene, &mut rng),
+            1.0
+        );
+
+        // the standalone output shows it in shades of grey:
+        scene.settings.ambient_occlusion_samples = 1000;
+        let r = Ray {
+            origin: Vector3 {
+                x: 0.0,
+                y: 10.0,
+                z: 0.0,
+            },
+            direction: scale_vector(-1.0, &up),
+            time: 0.0,
+        };
+        let closest_point = closest_intersection_point(&r, &scene);
+        let c = trace_ambient_occlusion(&r, &closest_point, &scene, &mut rng);
+        assert!(
+            c.r > 100 && c.r < 155 && c.r == c.g && c.g == c.b,
+            "{:?}",
+            c
+        );
+        let r = Ray { direction: up, ..r };
+        let closest_point = closest_intersection_point(&r, &scene);
+        let c = trace_ambient_occlusion(&r, &closest_point, &scene, &mut rng);
+        assert_eq!(c.r, 255);
+    }
+
+    #[test]
+    fn test_glossy_reflection() {
+        // a black mirror on the ground, under a sky that is white straight up:
+        let mut scene = create_empty_test_scene();
+        scene.background = Background::Gradient {
+            bottom: BLACK_RGB,
+            top: Rgb {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+            },
+        };
+        scene.planes.push(Plane {
+            n: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            d: 0.0,
+            material: Material::new(Color { r: 0, g: 0, b: 0 }, true),
+        });
+        let r = Ray {
+            origin: Vector3 {
+                x: 0.0,
+                y: 10.0,
+                z: 0.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        let mut stats = RenderStats::default();
+        let mut rng = Rng::new(1);
+        let mirrored = trace_ray(
+            &r,
+            None,
+            &PRIMARY_RAY,
+            &PreparedScene::new(&scene),
+            &mut stats,
+            &mut rng,
+        )
+        .to_color();
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        assert_eq!(mirrored, scale_color(0.25, &white));
+
+        // a rough mirror also reflects the darker sky around the zenith:
+        scene.planes[0].material.roughness = 1.0;
+        let glossy = trace_ray(
+            &r,
+            None,
+            &PRIMARY_RAY,
+            &PreparedScene::new(&scene),
+            &mut stats,
+            &mut rng,
+        )
+        .to_color();
+        assert!(
+            glossy.r < mirrored.r && glossy.r > mirrored.r - 10,
+            "{:?}",
+            glossy
+        );
+
+        // no reflections are traced beyond the maximum depth or below the minimum contribution:
+        scene.planes[0].material.roughness = 0.0;
+        scene.settings.max_reflection_depth = 0;
+        let c = trace_ray(
+            &r,
+            None,
+            &PRIMARY_RAY,
+            &PreparedScene::new(&scene),
+            &mut stats,
+            &mut rng,
+        )
+        .to_color();
+        assert_eq!(c, Color { r: 0, g: 0, b: 0 });
+        scene.settings = RenderSettings {
+            min_contribution: 0.3,
+            ..RenderSettings::default()
+        };
+        let c = trace_ray(
+            &r,
+            None,
+            &PRIMARY_RAY,
+            &PreparedScene::new(&scene),
+            &mut stats,
+            &mut rng,
+        )
+        .to_color();
+        assert_eq!(c, Color { r: 0, g: 0, b: 0 });
+
+        // ...which is sampled within a cone around the mirror direction, above the surface:
+        let normal = scene.planes[0].n;
+        let mirror = normalize(&Vector3 {
+            x: 1.0,
+            y: 0.2,
+            z: 0.0,
+        });
+        let cos_theta_max = (0.25_f64).atan().cos();
+        for _ in 0..100 {
+            let d = sample_glossy_direction(&mirror, &normal, 0.5, rng.next_f64(), rng.next_f64());
+            assert!(dot_product(&d, &normal) >= 0.0);
+            let mirrored_d = Vector3 { y: -d.y, ..d };
+            let cos_theta = f64::max(dot_product(&d, &mirror), dot_product(&mirrored_d, &mirror));
+            assert!(cos_theta >= cos_theta_max - 0.000001);
+        }
+    }
+
+    #[test]
+    fn test_follow_degenerate_ray() {
+        let r = Ray {
+            origin: Vector3 {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            direction: ZERO_VECTOR3,
+            time: 0.0,
+        };
+        let p = follow_ray(&r, 10.0);
+        assert_eq!(p, r.origin);
+    }
+
+    #[test]
+    fn test_quadric_normals() {
+        let mut scene = create_empty_test_scene();
+        scene.quadrics.push(Quadric::paraboloid(
+            &ZERO_VECTOR3,
+            10.0,
+            Material::new(Color { r: 255, g: 0, b: 0 }, false),
+        ));
+
+        // a dish that is hit from the inside faces the ray...
+        let inside = Ray {
+            origin: Vector3 {
+                x: 1.0,
+                y: 10.0,
+                z: 0.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        let intersection = closest_intersection_point(&inside, &scene);
+        assert!(intersection.k < HUGE_VALUE);
+        assert!(dot_product(&intersection.compute_normal(), &inside.direction) < 0.0);
+
+        // ...and so does one that is hit from the outside:
+        let outside = Ray {
+            origin: Vector3 {
+                x: 1.0,
+                y: -10.0,
+                z: 0.0,
+            },
+            direction: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        let intersection = closest_intersection_point(&outside, &scene);
+        assert!(intersection.k < HUGE_VALUE);
+        assert!(dot_product(&intersection.compute_normal(), &outside.direction) < 0.0);
+
+        // a ray through a cone's apex falls back to facing the ray:
+        scene.quadrics = vec![Quadric {
+            a: 1.0,
+            b: -1.0,
+            c: 1.0,
+            d: 0.0,
+            e: 0.0,
+            f: 0.0,
+            g: 0.0,
+            h: 0.0,
+            i: 0.0,
+            j: 0.0,
+            bounds: None,
+            material: Material::new(Color { r: 255, g: 0, b: 0 }, false),
+        }];
+        let apex = Ray {
+            origin: Vector3 {
+                x: 0.0,
+                y: 10.0,
+                z: 0.0,
+            },
+            ..inside
+        };
+        let intersection = closest_intersection_point(&apex, &scene);
+        assert!((intersection.k - 10.0).abs() < 0.000001);
+        assert!(
+            distance(
+                &intersection.compute_normal(),
+                &scale_vector(-1.0, &apex.direction)
+            ) < 0.000001
+        );
+    }
+
+    fn create_empty_test_scene() -> Scene {
+        return Scene {
+            spheres: vec![],
+            planes: vec![],
+            cuboids: vec![],
+            csg_objects: vec![],
+            sdf_objects: vec![],
+            heightfields: vec![],
+            quadrics: vec![],
+            light: Light {
+                pos: Vector3 {
+                    x: 0.0,
+                    y: -1000.0,
+                    z: 0.0,
+                },
+            },
+            camera: Camera::default(),
+            background: Background::Solid(BLACK_RGB),
+            settings: RenderSettings::default(),
+        };
+    }
+
     fn create_small_test_scene() -> Scene {
         let test_spheres = vec![Sphere {
             pos: Vector3 {
@@ -550,8 +2650,8 @@ mod tests {
                 z: 250.0,
             },
             r: 25.0,
-            color: Color { r: 255, g: 0, b: 0 },
-            reflect: true,
+            velocity: ZERO_VECTOR3,
+            material: Material::new(Color { r: 255, g: 0, b: 0 }, true),
         }];
 
         let test_planes = vec![Plane {
@@ -561,12 +2661,14 @@ mod tests {
                 z: 0.0,
             },
             d: 60.0,
-            color: Color {
-                r: 200,
-                g: 200,
-                b: 200,
-            },
-            reflect: false,
+            material: Material::new(
+                Color {
+                    r: 200,
+                    g: 200,
+                    b: 200,
+                },
+                false,
+            ),
         }];
 
         let light1 = Light {
@@ -580,7 +2682,15 @@ mod tests {
         return Scene {
             spheres: test_spheres,
             planes: test_planes,
+            cuboids: vec![],
+            csg_objects: vec![],
+            sdf_objects: vec![],
+            heightfields: vec![],
+            quadrics: vec![],
             light: light1,
+            camera: Camera::default(),
+            background: Background::Solid(BLACK_RGB),
+            settings: RenderSettings::default(),
         };
     }
 }
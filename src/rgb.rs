@@ -0,0 +1,125 @@
+//! Floating point colors, for intermediate results that don't fit into 8 bits per channel.
+
+use crate::ray_tracer::*;
+
+/// A color with (unbounded) floating point channels, 1.0 corresponds to 255 of an 8-bit color.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rgb {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+pub const BLACK_RGB: Rgb = Rgb {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+};
+
+impl Rgb {
+    /// Converts an 8-bit color.
+    pub fn from_color(c: &Color) -> Rgb {
+        return Rgb {
+            r: c.r as f64 / 255.0,
+            g: c.g as f64 / 255.0,
+            b: c.b as f64 / 255.0,
+        };
+    }
+
+    /// Converts the color to an 8-bit color, clamping channels to [0.0, 1.0].
+    pub fn to_color(&self) -> Color {
+        let channel = |x: f64| (x.max(0.0).min(1.0) * 255.0).round() as u8;
+        return Color {
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+        };
+    }
+
+    /// Rounds the color to 8 bits per channel like to_color(), but keeps it as it is if it has a NaN or
+    /// infinite channel (so that it can still be told apart).
+    pub fn quantize(&self) -> Rgb {
+        return if self.is_finite() {
+            Rgb::from_color(&self.to_color())
+        } else {
+            *self
+        };
+    }
+
+    pub fn is_finite(&self) -> bool {
+        return self.r.is_finite() && self.g.is_finite() && self.b.is_finite();
+    }
+
+    /// The mean of all three channels.
+    pub fn average(&self) -> f64 {
+        return (self.r + self.g + self.b) / 3.0;
+    }
+}
+
+pub fn add_rgb(a: &Rgb, b: &Rgb) -> Rgb {
+    return Rgb {
+        r: a.r + b.r,
+        g: a.g + b.g,
+        b: a.b + b.b,
+    };
+}
+
+pub fn scale_rgb(a: f64, c: &Rgb) -> Rgb {
+    return Rgb {
+        r: a * c.r,
+        g: a * c.g,
+        b: a * c.b,
+    };
+}
+
+pub fn multiply_rgb(a: &Rgb, b: &Rgb) -> Rgb {
+    return Rgb {
+        r: a.r * b.r,
+        g: a.g * b.g,
+        b: a.b * b.b,
+    };
+}
+
+/// Interpolates linearly between two colors, t = 0.0 yields a and t = 1.0 yields b.
+pub fn lerp_rgb(a: &Rgb, b: &Rgb, t: f64) -> Rgb {
+    return add_rgb(&scale_rgb(1.0 - t, &a), &scale_rgb(t, &b));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_conversion() {
+        let c = Color {
+            r: 0,
+            g: 128,
+            b: 255,
+        };
+        assert_eq!(Rgb::from_color(&c).to_color(), c);
+
+        let bright = Rgb {
+            r: -1.0,
+            g: 0.5,
+            b: 7.0,
+        };
+        assert_eq!(
+            bright.to_color(),
+            Color {
+                r: 0,
+                g: 128,
+                b: 255,
+            }
+        );
+        assert_eq!(bright.quantize(), Rgb::from_color(&bright.to_color()));
+
+        let broken = Rgb {
+            r: f64::NAN,
+            g: 0.5,
+            b: f64::INFINITY,
+        };
+        assert!(!broken.is_finite());
+        assert!(broken.quantize().r.is_nan());
+        assert_eq!(broken.quantize().b, f64::INFINITY);
+    }
+}
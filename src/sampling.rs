@@ -0,0 +1,199 @@
+//! Random numbers and the sampling of directions, for Monte Carlo estimates.
+
+use crate::vector_arithmetic::*;
+use std::f64::consts::PI;
+
+/// A small and fast pseudo random number generator (xorshift64*), deterministic for a given seed.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator, similar seeds (e.g. neighbouring pixels) yield unrelated sequences.
+    pub fn new(seed: u64) -> Rng {
+        // scramble the seed (splitmix64), xorshift's state must never be zero:
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        return Rng {
+            state: if z == 0 { 1 } else { z },
+        };
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545F4914F6CDD1D);
+    }
+
+    /// Returns a uniformly distributed number in [0.0, 1.0).
+    pub fn next_f64(&mut self) -> f64 {
+        // use the upper 53 bits, as many as the mantissa holds:
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+}
+
+/// Computes two unit vectors that form an orthonormal basis with a given unit vector.
+pub fn orthonormal_basis(n: &Vector3) -> (Vector3, Vector3) {
+    let helper = if n.x.abs() > 0.9 {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    } else {
+        Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    };
+    let tangent = normalize(&cross_product(&helper, &n));
+    let bitangent = cross_product(&n, &tangent);
+    return (tangent, bitangent);
+}
+
+/// Samples a direction uniformly within a cone around a (unit) axis, given two uniformly distributed numbers.
+pub fn sample_cone(axis: &Vector3, cos_theta_max: f64, u1: f64, u2: f64) -> Vector3 {
+    let cos_theta = 1.0 - u1 * (1.0 - cos_theta_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    let (tangent, bitangent) = orthonormal_basis(&axis);
+    return sum(
+        &sum(
+            &scale_vector(sin_theta * phi.cos(), &tangent),
+            &scale_vector(sin_theta * phi.sin(), &bitangent),
+        ),
+        &scale_vector(cos_theta, &axis),
+    );
+}
+
+/// Samples a direction in the hemisphere around a (unit) normal, with a density proportional to the cosine.
+pub fn sample_cosine_hemisphere(normal: &Vector3, u1: f64, u2: f64) -> Vector3 {
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let (tangent, bitangent) = orthonormal_basis(&normal);
+    return sum(
+        &sum(
+            &scale_vector(r * phi.cos(), &tangent),
+            &scale_vector(r * phi.sin(), &bitangent),
+        ),
+        &scale_vector((1.0 - u1).max(0.0).sqrt(), &normal),
+    );
+}
+
+/// Samples a point uniformly on the unit disk, given two uniformly distributed numbers
+/// (Shirley's concentric mapping, which keeps neighbouring numbers close on the disk).
+pub fn sample_disk(u1: f64, u2: f64) -> (f64, f64) {
+    let a = 2.0 * u1 - 1.0;
+    let b = 2.0 * u2 - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, 0.25 * PI * (b / a))
+    } else {
+        (b, 0.5 * PI - 0.25 * PI * (a / b))
+    };
+    return (r * phi.cos(), r * phi.sin());
+}
+
+/// Samples a point uniformly in a regular polygon that is inscribed in the unit circle,
+/// with one corner at a given angle, given two uniformly distributed numbers.
+pub fn sample_regular_polygon(corners: u32, rotation: f64, u1: f64, u2: f64) -> (f64, f64) {
+    let corners = corners.max(3);
+    // reuse u1 to choose one of the triangles between the center and two neighbouring corners:
+    let scaled = u1 * corners as f64;
+    let triangle = (scaled as u32).min(corners - 1);
+    let u = scaled - triangle as f64;
+    let angle = |i: u32| rotation + 2.0 * PI * (i as f64) / (corners as f64);
+    let (a0, a1) = (angle(triangle), angle(triangle + 1));
+
+    // uniform barycentric coordinates, the center's weight is 1 - s:
+    let s = u.sqrt();
+    let t = u2 * s;
+    return (
+        (s - t) * a0.cos() + t * a1.cos(),
+        (s - t) * a0.sin() + t * a1.sin(),
+    );
+}
+
+/// The probability density (per solid angle) of sample_cone().
+pub fn cone_pdf(cos_theta_max: f64) -> f64 {
+    return 1.0 / (2.0 * PI * (1.0 - cos_theta_max));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+        let mut mean = 0.0;
+        for _ in 0..10000 {
+            let x = a.next_f64();
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(x, b.next_f64());
+            assert_ne!(x, c.next_f64());
+            mean += x / 10000.0;
+        }
+        assert!((mean - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_sample_cone() {
+        let axis = normalize(&Vector3 {
+            x: 1.0,
+            y: 2.0,
+            z: -2.0,
+        });
+        let cos_theta_max = 0.9;
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            let d = sample_cone(&axis, cos_theta_max, rng.next_f64(), rng.next_f64());
+            assert!((length(&d) - 1.0).abs() < 0.000001);
+            assert!(dot_product(&d, &axis) >= cos_theta_max - 0.000001);
+        }
+        // the cone covers 2 PI (1 - cos_theta_max) steradians:
+        assert!((cone_pdf(cos_theta_max) * 2.0 * PI * 0.1 - 1.0).abs() < 0.000001);
+    }
+
+    #[test]
+    fn test_sample_aperture() {
+        let mut rng = Rng::new(2);
+        let samples = 20000;
+        let (mut disk_inner, mut hexagon_inner) = (0, 0);
+        for _ in 0..samples {
+            let (x, y) = sample_disk(rng.next_f64(), rng.next_f64());
+            assert!(x * x + y * y <= 1.000001);
+            if x * x + y * y < 0.25 {
+                disk_inner += 1;
+            }
+
+            // a hexagon's corner is at angle 0, so its edges are at a distance of cos(30°) from the center:
+            let (x, y) = sample_regular_polygon(6, 0.0, rng.next_f64(), rng.next_f64());
+            for i in 0..6 {
+                let phi = PI / 6.0 + PI / 3.0 * i as f64;
+                assert!(x * phi.cos() + y * phi.sin() <= (PI / 6.0).cos() + 0.000001);
+            }
+            if x * x + y * y < 0.25 {
+                hexagon_inner += 1;
+            }
+        }
+        // uniform samples: the inner circle of radius 0.5 gets its share of the area:
+        let disk_share = disk_inner as f64 / samples as f64;
+        assert!((disk_share - 0.25).abs() < 0.01, "{}", disk_share);
+        let hexagon_area = 1.5 * 3.0f64.sqrt();
+        let hexagon_share = hexagon_inner as f64 / samples as f64;
+        assert!(
+            (hexagon_share - 0.25 * PI / hexagon_area).abs() < 0.01,
+            "{}",
+            hexagon_share
+        );
+    }
+}
@@ -0,0 +1,650 @@
+//! Editing a scene: edits that add, remove and change its objects, materials, light and camera, track
+//! what they changed, so that a renderer only redoes the work that the changes invalidate.
+
+use crate::camera::*;
+use crate::csg::*;
+use crate::heightfield::*;
+use crate::material::*;
+use crate::quadric::*;
+use crate::ray_tracer::*;
+use crate::sdf::*;
+
+/// An object of any kind, to add to a scene.
+pub enum SceneObject {
+    Sphere(Sphere),
+    Plane(Plane),
+    Cuboid(Cuboid),
+    Csg(Csg),
+    Sdf(SdfObject),
+    Heightfield(Heightfield),
+    Quadric(Quadric),
+}
+
+impl SceneObject {
+    pub fn kind(&self) -> ObjectKind {
+        return match self {
+            SceneObject::Sphere(_) => ObjectKind::Sphere,
+            SceneObject::Plane(_) => ObjectKind::Plane,
+            SceneObject::Cuboid(_) => ObjectKind::Cuboid,
+            SceneObject::Csg(_) => ObjectKind::Csg,
+            SceneObject::Sdf(_) => ObjectKind::Sdf,
+            SceneObject::Heightfield(_) => ObjectKind::Heightfield,
+            SceneObject::Quadric(_) => ObjectKind::Quadric,
+        };
+    }
+}
+
+/// A change to a scene.
+pub enum SceneEdit {
+    AddObject(SceneObject),
+    /// Removes an object, the following objects of its kind move up by one index.
+    RemoveObject(ObjectId),
+    /// Replaces an object with another one of the same kind.
+    UpdateObject(ObjectId, SceneObject),
+    /// Sets the material of an object (of all solids of a CSG object).
+    SetMaterial(ObjectId, Material),
+    SetLight(Light),
+    SetCamera(Camera),
+    SetSettings(RenderSettings),
+}
+
+/// What edits changed in a scene.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Changes {
+    /// The camera changed, and with it every primary ray.
+    pub view: bool,
+    /// Objects were added, removed or replaced.
+    pub geometry: bool,
+    /// Materials, the light or the render settings changed: the colors, but not what the primary rays hit.
+    pub shading: bool,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        return !self.view && !self.geometry && !self.shading;
+    }
+
+    /// Combines the changes of two (sets of) edits.
+    pub fn merge(&self, other: &Changes) -> Changes {
+        return Changes {
+            view: self.view || other.view,
+            geometry: self.geometry || other.geometry,
+            shading: self.shading || other.shading,
+        };
+    }
+
+    /// Whether the primary rays have to be traced again (otherwise only their hits are shaded again).
+    pub fn invalidates_primary_hits(&self) -> bool {
+        return self.view || self.geometry;
+    }
+}
+
+const VIEW_CHANGED: Changes = Changes {
+    view: true,
+    geometry: false,
+    shading: false,
+};
+
+const GEOMETRY_CHANGED: Changes = Changes {
+    view: false,
+    geometry: true,
+    shading: false,
+};
+
+const SHADING_CHANGED: Changes = Changes {
+    view: false,
+    geometry: false,
+    shading: true,
+};
+
+impl SceneEdit {
+    /// Applies the edit to a given scene, returns what it changed (nothing if it set something to the value
+    /// it already had).
+    pub fn apply(self, scene: &mut Scene) -> Result<Changes, String> {
+        return match self {
+            SceneEdit::AddObject(object) => {
+                match object {
+                    SceneObject::Sphere(sphere) => scene.spheres.push(sphere),
+                    SceneObject::Plane(plane) => scene.planes.push(plane),
+                    SceneObject::Cuboid(cuboid) => scene.cuboids.push(cuboid),
+                    SceneObject::Csg(csg) => scene.csg_objects.push(csg),
+                    SceneObject::Sdf(sdf_object) => scene.sdf_objects.push(sdf_object),
+                    SceneObject::Heightfield(heightfield) => scene.heightfields.push(heightfield),
+                    SceneObject::Quadric(quadric) => scene.quadrics.push(quadric),
+                }
+                Ok(GEOMETRY_CHANGED)
+            }
+            SceneEdit::RemoveObject(id) => {
+                check_object_id(&scene, &id)?;
+                let i = id.index;
+                match id.kind {
+                    ObjectKind::Sphere => {
+                        scene.spheres.remove(i);
+                    }
+                    ObjectKind::Plane => {
+                        scene.planes.remove(i);
+                    }
+                    ObjectKind::Cuboid => {
+                        scene.cuboids.remove(i);
+                    }
+                    ObjectKind::Csg => {
+                        scene.csg_objects.remove(i);
+                    }
+                    ObjectKind::Sdf => {
+                        scene.sdf_objects.remove(i);
+                    }
+                    ObjectKind::Heightfield => {
+                        scene.heightfields.remove(i);
+                    }
+                    ObjectKind::Quadric => {
+                        scene.quadrics.remove(i);
+                    }
+                }
+                Ok(GEOMETRY_CHANGED)
+            }
+            SceneEdit::UpdateObject(id, object) => {
+                check_object_id(&scene, &id)?;
+                let i = id.index;
+                let changes = match object {
+                    SceneObject::Sphere(sphere) if id.kind == ObjectKind::Sphere => {
+                        replace_object(&mut scene.spheres[i], sphere, |sphere| {
+                            vec![&mut sphere.material]
+                        })
+                    }
+                    SceneObject::Plane(plane) if id.kind == ObjectKind::Plane => {
+                        replace_object(&mut scene.planes[i], plane, |plane| {
+                            vec![&mut plane.material]
+                        })
+                    }
+                    SceneObject::Cuboid(cuboid) if id.kind == ObjectKind::Cuboid => {
+                        replace_object(&mut scene.cuboids[i], cuboid, |cuboid| {
+                            vec![&mut cuboid.material]
+                        })
+                    }
+                    SceneObject::Csg(csg) if id.kind == ObjectKind::Csg => {
+                        replace_object(&mut scene.csg_objects[i], csg, Csg::materials_mut)
+                    }
+                    SceneObject::Sdf(sdf_object) if id.kind == ObjectKind::Sdf => {
+                        replace_object(&mut scene.sdf_objects[i], sdf_object, |sdf_object| {
+                            vec![&mut sdf_object.material]
+                        })
+                    }
+                    SceneObject::Heightfield(heightfield) if id.kind == ObjectKind::Heightfield => {
+                        replace_object(&mut scene.heightfields[i], heightfield, |heightfield| {
+                            vec![&mut heightfield.material]
+                        })
+                    }
+                    SceneObject::Quadric(quadric) if id.kind == ObjectKind::Quadric => {
+                        replace_object(&mut scene.quadrics[i], quadric, |quadric| {
+                            vec![&mut quadric.material]
+                        })
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Cannot replace {} {} with a {}!",
+                            id.kind.name(),
+                            i,
+                            object.kind().name()
+                        ))
+                    }
+                };
+                Ok(changes)
+            }
+            SceneEdit::SetMaterial(id, material) => {
+                check_object_id(&scene, &id)?;
+                if has_material(&scene, &id, &material) {
+                    return Ok(Changes::default());
+                }
+                let i = id.index;
+                match id.kind {
+                    ObjectKind::Sphere => scene.spheres[i].material = material,
+                    ObjectKind::Plane => scene.planes[i].material = material,
+                    ObjectKind::Cuboid => scene.cuboids[i].material = material,
+                    ObjectKind::Csg => scene.csg_objects[i].set_material(&material),
+                    ObjectKind::Sdf => scene.sdf_objects[i].material = material,
+                    ObjectKind::Heightfield => scene.heightfields[i].material = material,
+                    ObjectKind::Quadric => scene.quadrics[i].material = material,
+                }
+                Ok(SHADING_CHANGED)
+            }
+            SceneEdit::SetLight(light) if light == scene.light => Ok(Changes::default()),
+            SceneEdit::SetLight(light) => {
+                scene.light = light;
+                Ok(SHADING_CHANGED)
+            }
+            SceneEdit::SetCamera(camera) if camera == scene.camera => Ok(Changes::default()),
+            SceneEdit::SetCamera(camera) => {
+                scene.camera = camera;
+                Ok(VIEW_CHANGED)
+            }
+            SceneEdit::SetSettings(settings) if settings == scene.settings => {
+                Ok(Changes::default())
+            }
+            SceneEdit::SetSettings(settings) => {
+                scene.settings = settings;
+                Ok(SHADING_CHANGED)
+            }
+        };
+    }
+}
+
+/// The number of objects of a given kind in a scene.
+pub fn object_count(scene: &Scene, kind: ObjectKind) -> usize {
+    return match kind {
+        ObjectKind::Sphere => scene.spheres.len(),
+        ObjectKind::Plane => scene.planes.len(),
+        ObjectKind::Cuboid => scene.cuboids.len(),
+        ObjectKind::Csg => scene.csg_objects.len(),
+        ObjectKind::Sdf => scene.sdf_objects.len(),
+        ObjectKind::Heightfield => scene.heightfields.len(),
+        ObjectKind::Quadric => scene.quadrics.len(),
+    };
+}
+
+/// Finds the material of an object (of the first solid of a CSG object).
+pub fn object_material<'a>(scene: &'a Scene, id: &ObjectId) -> Option<&'a Material> {
+    let i = id.index;
+    return match id.kind {
+        ObjectKind::Sphere => scene.spheres.get(i).map(|sphere| &sphere.material),
+        ObjectKind::Plane => scene.planes.get(i).map(|plane| &plane.material),
+        ObjectKind::Cuboid => scene.cuboids.get(i).map(|cuboid| &cuboid.material),
+        ObjectKind::Csg => scene
+            .csg_objects
+            .get(i)?
+            .primitives()
+            .into_iter()
+            .next()
+            .map(|primitive| match primitive {
+                GeomPrimitive::Sphere(sphere) => &sphere.material,
+                GeomPrimitive::Cuboid(cuboid) => &cuboid.material,
+                GeomPrimitive::Plane(plane) => &plane.material,
+                _ => panic!("Unexpected CSG solid!"),
+            }),
+        ObjectKind::Sdf => scene
+            .sdf_objects
+            .get(i)
+            .map(|sdf_object| &sdf_object.material),
+        ObjectKind::Heightfield => scene
+            .heightfields
+            .get(i)
+            .map(|heightfield| &heightfield.material),
+        ObjectKind::Quadric => scene.quadrics.get(i).map(|quadric| &quadric.material),
+    };
+}
+
+/// Checks whether an object (all solids of a CSG object) already has a given material.
+fn has_material(scene: &Scene, id: &ObjectId, material: &Material) -> bool {
+    return match id.kind {
+        ObjectKind::Csg => scene.csg_objects[id.index]
+            .primitives()
+            .iter()
+            .all(|primitive| primitive.material() == material),
+        _ => object_material(&scene, &id) == Some(material),
+    };
+}
+
+/// Replaces an object with an updated one, returns what that changed: nothing, only the shading if the
+/// objects differ only in their materials, or the geometry.
+fn replace_object<T: PartialEq>(
+    object: &mut T,
+    mut updated: T,
+    materials: fn(&mut T) -> Vec<&mut Material>,
+) -> Changes {
+    if updated == *object {
+        return Changes::default();
+    }
+
+    // (swap the old materials into the updated object for a moment, to compare the rest:)
+    let mut materials_to_swap: Vec<Material> =
+        materials(object).into_iter().map(|m| m.clone()).collect();
+    let mut same_shape = false;
+    if materials(&mut updated).len() == materials_to_swap.len() {
+        for (m, other) in materials(&mut updated)
+            .into_iter()
+            .zip(materials_to_swap.iter_mut())
+        {
+            std::mem::swap(m, other);
+        }
+        same_shape = updated == *object;
+        for (m, other) in materials(&mut updated)
+            .into_iter()
+            .zip(materials_to_swap.iter_mut())
+        {
+            std::mem::swap(m, other);
+        }
+    }
+
+    *object = updated;
+    return if same_shape {
+        SHADING_CHANGED
+    } else {
+        GEOMETRY_CHANGED
+    };
+}
+
+/// Checks that a scene has an object with a given ID.
+fn check_object_id(scene: &Scene, id: &ObjectId) -> Result<(), String> {
+    if id.index >= object_count(&scene, id.kind) {
+        return Err(format!("The scene has no {} {}!", id.kind.name(), id.index));
+    }
+    return Ok(());
+}
+
+/// Renders a scene again after it was edited, but only redoes what the edits invalidated: nothing if nothing
+/// changed, only the shading of the primary rays' (cached) hits if neither the camera nor the geometry
+/// changed, and everything otherwise.
+pub struct IncrementalRenderer {
+    scene: Scene,
+    width: u32,
+    height: u32,
+    /// The changes since the last rendering.
+    changes: Changes,
+    primary_hits: Vec<PrimaryHit>,
+    image: Option<Vec<u8>>,
+}
+
+impl IncrementalRenderer {
+    pub fn new(scene: Scene, width: u32, height: u32) -> IncrementalRenderer {
+        return IncrementalRenderer {
+            scene,
+            width,
+            height,
+            changes: Changes::default(),
+            primary_hits: vec![],
+            image: None,
+        };
+    }
+
+    /// The scene, which only changes through edits.
+    pub fn scene(&self) -> &Scene {
+        return &self.scene;
+    }
+
+    /// The changes since the last rendering.
+    pub fn changes(&self) -> Changes {
+        return self.changes;
+    }
+
+    /// Applies an edit to the scene, returns what it changed.
+    pub fn edit(&mut self, edit: SceneEdit) -> Result<Changes, String> {
+        let changes = edit.apply(&mut self.scene)?;
+        self.changes = self.changes.merge(&changes);
+        return Ok(changes);
+    }
+
+    /// Changes the size of the rendered images.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.changes.view = true;
+        }
+    }
+
+    /// Renders the scene into an RGBA image (row by row, top to bottom).
+    pub fn render(&mut self) -> (Vec<u8>, RenderStats) {
+        if let Some(image) = &self.image {
+            if self.changes.is_empty() {
+                return (image.clone(), RenderStats::default());
+            }
+        }
+        if self.changes.invalidates_primary_hits() {
+            self.primary_hits.clear();
+        }
+
+        let (image, stats) =
+            render_rgba_cached(&self.scene, self.width, self.height, &mut self.primary_hits);
+        self.image = Some(image.clone());
+        self.changes = Changes::default();
+        return (image, stats);
+    }
+
+    /// Renders the scene with other settings and another camera (e.g. a quick preview), which neither uses
+    /// nor changes what was cached.
+    pub fn render_with(
+        &mut self,
+        settings: &RenderSettings,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+    ) -> (Vec<u8>, RenderStats) {
+        let (own_settings, own_camera) = (self.scene.settings, self.scene.camera);
+        self.scene.settings = *settings;
+        self.scene.camera = *camera;
+        let rendered = render_rgba(&self.scene, width, height);
+        self.scene.settings = own_settings;
+        self.scene.camera = own_camera;
+        return rendered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector_arithmetic::*;
+
+    fn sphere(x: f64, color: Color) -> Sphere {
+        return Sphere {
+            pos: Vector3 {
+                x,
+                y: 0.0,
+                z: 100.0,
+            },
+            r: 20.0,
+            velocity: ZERO_VECTOR3,
+            material: Material::new(color, false),
+        };
+    }
+
+    fn sphere_id(index: usize) -> ObjectId {
+        return ObjectId {
+            kind: ObjectKind::Sphere,
+            index,
+        };
+    }
+
+    const RED: Color = Color { r: 255, g: 0, b: 0 };
+    const BLUE: Color = Color { r: 0, g: 0, b: 255 };
+
+    #[test]
+    fn test_scene_edits() {
+        let mut scene = crate::create_test_scene();
+        let spheres = scene.spheres.len();
+
+        let changes = SceneEdit::AddObject(SceneObject::Sphere(sphere(0.0, RED)))
+            .apply(&mut scene)
+            .unwrap();
+        assert_eq!(changes, GEOMETRY_CHANGED);
+        assert_eq!(object_count(&scene, ObjectKind::Sphere), spheres + 1);
+
+        let material = Material::new(BLUE, false);
+        let changes =
+            SceneEdit::SetMaterial(sphere_id(spheres), material.clone()).apply(&mut scene);
+        assert_eq!(changes, Ok(SHADING_CHANGED));
+        assert_eq!(
+            object_material(&scene, &sphere_id(spheres)).unwrap().color,
+            BLUE
+        );
+        // (setting it again changes nothing:)
+        let changes = SceneEdit::SetMaterial(sphere_id(spheres), material).apply(&mut scene);
+        assert_eq!(changes, Ok(Changes::default()));
+
+        // the CSG object's solids all get the material:
+        let csg = ObjectId {
+            kind: ObjectKind::Csg,
+            index: 0,
+        };
+        SceneEdit::SetMaterial(csg, Material::new(BLUE, false))
+            .apply(&mut scene)
+            .unwrap();
+        for primitive in scene.csg_objects[0].primitives() {
+            assert_eq!(primitive.material().color, BLUE);
+        }
+
+        let light = Light { pos: ZERO_VECTOR3 };
+        assert_eq!(
+            SceneEdit::SetLight(light).apply(&mut scene),
+            Ok(SHADING_CHANGED)
+        );
+        assert_eq!(
+            SceneEdit::SetLight(light).apply(&mut scene),
+            Ok(Changes::default())
+        );
+        let camera = Camera::default();
+        assert_eq!(
+            SceneEdit::SetCamera(camera).apply(&mut scene),
+            Ok(VIEW_CHANGED)
+        );
+
+        assert_eq!(
+            SceneEdit::UpdateObject(sphere_id(0), SceneObject::Sphere(sphere(5.0, RED)))
+                .apply(&mut scene),
+            Ok(GEOMETRY_CHANGED)
+        );
+        assert_eq!(scene.spheres[0].pos.x, 5.0);
+        // (an update that only changes the material only changes the shading, and one that changes
+        // nothing changes nothing:)
+        assert_eq!(
+            SceneEdit::UpdateObject(sphere_id(0), SceneObject::Sphere(sphere(5.0, BLUE)))
+                .apply(&mut scene),
+            Ok(SHADING_CHANGED)
+        );
+        assert_eq!(scene.spheres[0].material.color, BLUE);
+        assert_eq!(
+            SceneEdit::UpdateObject(sphere_id(0), SceneObject::Sphere(sphere(5.0, BLUE)))
+                .apply(&mut scene),
+            Ok(Changes::default())
+        );
+
+        // the same goes for the solids of a CSG object:
+        let union = |left: Color, right: Color| Csg::Node {
+            operation: CsgOperation::Union,
+            left: Box::new(Csg::Sphere(sphere(0.0, left))),
+            right: Box::new(Csg::Sphere(sphere(10.0, right))),
+        };
+        assert_eq!(
+            SceneEdit::UpdateObject(csg, SceneObject::Csg(union(RED, BLUE))).apply(&mut scene),
+            Ok(GEOMETRY_CHANGED)
+        );
+        assert_eq!(
+            SceneEdit::UpdateObject(csg, SceneObject::Csg(union(BLUE, RED))).apply(&mut scene),
+            Ok(SHADING_CHANGED)
+        );
+        assert_eq!(scene.csg_objects[0].primitives()[0].material().color, BLUE);
+        assert_eq!(
+            SceneEdit::UpdateObject(csg, SceneObject::Csg(union(BLUE, RED))).apply(&mut scene),
+            Ok(Changes::default())
+        );
+        let plane = scene.planes[0].clone();
+        assert!(
+            SceneEdit::UpdateObject(sphere_id(0), SceneObject::Plane(plane))
+                .apply(&mut scene)
+                .is_err()
+        );
+
+        assert_eq!(
+            SceneEdit::RemoveObject(sphere_id(0)).apply(&mut scene),
+            Ok(GEOMETRY_CHANGED)
+        );
+        assert_eq!(scene.spheres.len(), spheres);
+        assert!(SceneEdit::RemoveObject(sphere_id(spheres))
+            .apply(&mut scene)
+            .is_err());
+        assert!(
+            SceneEdit::SetMaterial(sphere_id(99), Material::new(RED, false))
+                .apply(&mut scene)
+                .is_err()
+        );
+
+        let changes = Changes::default().merge(&SHADING_CHANGED);
+        assert!(!changes.is_empty() && !changes.invalidates_primary_hits());
+        assert!(changes.merge(&VIEW_CHANGED).invalidates_primary_hits());
+    }
+
+    #[test]
+    fn test_incremental_renderer() {
+        let mut scene = crate::create_test_scene();
+        scene.camera = Camera::default();
+        scene.spheres = vec![sphere(0.0, RED)];
+        let (width, height) = (8, 8);
+        let mut renderer = IncrementalRenderer::new(scene, width, height);
+
+        let (first, _) = renderer.render();
+        assert!(renderer.changes().is_empty());
+        assert!(!renderer.primary_hits.contains(&PrimaryHit::Unknown));
+        assert_eq!(renderer.render().0, first);
+
+        // a new color is shaded at the cached primary hits...
+        renderer
+            .edit(SceneEdit::SetMaterial(
+                sphere_id(0),
+                Material::new(BLUE, false),
+            ))
+            .unwrap();
+        renderer.primary_hits[0] = PrimaryHit::Background;
+        let (recolored, _) = renderer.render();
+        let expected = render_rgba(renderer.scene(), width, height).0;
+        assert_eq!(recolored[4..], expected[4..]);
+        assert_ne!(recolored, first);
+        // (...so a pixel whose cached hit was tampered with shows the background:)
+        assert_eq!(recolored[..3], [0, 0, 0]);
+        assert_ne!(expected[..3], [0, 0, 0]);
+
+        // ...while a new camera traces the primary rays again:
+        let camera = Camera {
+            pos: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            ..Camera::default()
+        };
+        renderer.edit(SceneEdit::SetCamera(camera)).unwrap();
+        renderer.primary_hits[0] = PrimaryHit::Background;
+        let (moved, _) = renderer.render();
+        assert_eq!(moved, render_rgba(renderer.scene(), width, height).0);
+        assert_ne!(moved[..3], [0, 0, 0]);
+
+        // a preview with other settings leaves the cache alone:
+        let settings = RenderSettings {
+            max_depth: 0,
+            ..RenderSettings::default()
+        };
+        let (preview, _) = renderer.render_with(&settings, &Camera::default(), 4, 4);
+        assert_eq!(preview.len(), 4 * 4 * 4);
+        assert_eq!(renderer.scene().camera, camera);
+        assert_eq!(renderer.render().0, moved);
+
+        renderer.resize(4, 8);
+        assert!(renderer.changes().view);
+        assert_eq!(renderer.render().0.len(), 4 * 8 * 4);
+
+        // a thin lens's samples are cached as well:
+        let lens_camera = Camera {
+            lens: Some(ThinLens {
+                aperture_radius: 2.0,
+                focal_distance: 100.0,
+                aperture: Aperture::Circle,
+                samples: 4,
+            }),
+            ..Camera::default()
+        };
+        renderer.edit(SceneEdit::SetCamera(lens_camera)).unwrap();
+        renderer.render();
+        assert_eq!(renderer.primary_hits.len(), 4 * 8 * 4);
+        renderer
+            .edit(SceneEdit::SetMaterial(
+                sphere_id(0),
+                Material::new(RED, false),
+            ))
+            .unwrap();
+        for primary_hit in &mut renderer.primary_hits[..4] {
+            *primary_hit = PrimaryHit::Background;
+        }
+        let (recolored, _) = renderer.render();
+        let expected = render_rgba(renderer.scene(), 4, 8).0;
+        assert_eq!(recolored[4..], expected[4..]);
+        assert_eq!(recolored[..3], [0, 0, 0]);
+        assert_ne!(expected[..3], [0, 0, 0]);
+    }
+}
@@ -0,0 +1,326 @@
+//! Signed distance field (SDF) primitives that are rendered by sphere tracing.
+
+use crate::material::*;
+use crate::ray_tracer::*;
+use crate::vector_arithmetic::*;
+
+/// A signed distance field: negative inside a shape, positive outside, zero on its surface.
+#[derive(PartialEq)]
+pub enum Sdf {
+    /// A sphere with a given radius, centered at the origin.
+    Sphere { r: f64 },
+    /// A box with given half extents and rounded edges, centered at the origin.
+    RoundedBox { half_extents: Vector3, radius: f64 },
+    /// A line segment from a to b, thickened by a given radius.
+    Capsule { a: Vector3, b: Vector3, r: f64 },
+    /// A Mandelbulb fractal (roughly of radius 1.2), centered at the origin.
+    Mandelbulb { power: f64, iterations: u32 },
+    /// Moves a field by a given offset.
+    Translate { offset: Vector3, sdf: Box<Sdf> },
+    /// Scales a field uniformly by a given factor.
+    Scale { factor: f64, sdf: Box<Sdf> },
+    /// Blends two fields, the smoothing radius k controls how far the blend reaches.
+    SmoothUnion {
+        k: f64,
+        left: Box<Sdf>,
+        right: Box<Sdf>,
+    },
+    /// Repeats a field infinitely, a period of 0.0 disables the repetition along that axis.
+    Repetition { period: Vector3, sdf: Box<Sdf> },
+    /// Twists a field around the y-axis by a given angle (in radians) per unit of height.
+    Twist { rate: f64, sdf: Box<Sdf> },
+}
+
+/// An object whose shape is given by a signed distance field.
+#[derive(PartialEq)]
+pub struct SdfObject {
+    pub sdf: Sdf,
+    /// The maximum number of sphere tracing steps per ray.
+    pub max_steps: u32,
+    /// The distance to the surface at which a ray counts as hitting it.
+    pub epsilon: f64,
+    pub material: Material,
+}
+
+impl Sdf {
+    /// Computes the signed distance from a given point to the field's surface.
+    pub fn distance(&self, p: &Vector3) -> f64 {
+        return match self {
+            Sdf::Sphere { r } => length(&p) - r,
+            Sdf::RoundedBox {
+                half_extents,
+                radius,
+            } => {
+                let q = Vector3 {
+                    x: p.x.abs() - half_extents.x,
+                    y: p.y.abs() - half_extents.y,
+                    z: p.z.abs() - half_extents.z,
+                };
+                let outside = Vector3 {
+                    x: f64::max(q.x, 0.0),
+                    y: f64::max(q.y, 0.0),
+                    z: f64::max(q.z, 0.0),
+                };
+                let inside = f64::min(f64::max(q.x, f64::max(q.y, q.z)), 0.0);
+                length(&outside) + inside - radius
+            }
+            Sdf::Capsule { a, b, r } => {
+                let pa = difference(&p, &a);
+                let ba = difference(&b, &a);
+                let h = clamp(dot_product(&pa, &ba) / dot_product(&ba, &ba), 0.0, 1.0);
+                length(&difference(&pa, &scale_vector(h, &ba))) - r
+            }
+            Sdf::Mandelbulb { power, iterations } => mandelbulb_distance(&p, *power, *iterations),
+            Sdf::Translate { offset, sdf } => sdf.distance(&difference(&p, &offset)),
+            Sdf::Scale { factor, sdf } => sdf.distance(&scale_vector(1.0 / factor, &p)) * factor,
+            Sdf::SmoothUnion { k, left, right } => {
+                let d1 = left.distance(&p);
+                let d2 = right.distance(&p);
+                let h = clamp(0.5 + 0.5 * (d2 - d1) / k, 0.0, 1.0);
+                d2 + (d1 - d2) * h - k * h * (1.0 - h)
+            }
+            Sdf::Repetition { period, sdf } => {
+                let q = Vector3 {
+                    x: repeat(p.x, period.x),
+                    y: repeat(p.y, period.y),
+                    z: repeat(p.z, period.z),
+                };
+                sdf.distance(&q)
+            }
+            Sdf::Twist { rate, sdf } => {
+                let (s, c) = (rate * p.y).sin_cos();
+                let q = Vector3 {
+                    x: c * p.x - s * p.z,
+                    y: p.y,
+                    z: s * p.x + c * p.z,
+                };
+                sdf.distance(&q)
+            }
+        };
+    }
+}
+
+impl SdfObject {
+    /// Sphere traces a given ray, returns the ray parameter k of the first surface hit (HUGE_VALUE if there is none).
+    pub fn intersect(&self, r: &Ray) -> f64 {
+        let l = length(&r.direction);
+        let dir = match try_normalize(&r.direction) {
+            Some(dir) => dir,
+            None => return HUGE_VALUE,
+        };
+
+        // start a little away from the origin, so that rays leaving the surface don't hit it right away:
+        let mut t = TINY_VALUE;
+        for _ in 0..self.max_steps {
+            let p = sum(&r.origin, &scale_vector(t, &dir));
+            let d = self.sdf.distance(&p);
+            if d.abs() < self.epsilon {
+                // the field is marched along the normalized direction:
+                return t / l;
+            }
+            t += d.abs();
+            if t >= HUGE_VALUE {
+                break;
+            }
+        }
+        return HUGE_VALUE;
+    }
+
+    /// Computes the surface normal at a given point as the (normalized) gradient of the field.
+    pub fn compute_normal(&self, point: &Vector3) -> Vector3 {
+        let h = self.epsilon;
+        let dx = Vector3 {
+            x: h,
+            y: 0.0,
+            z: 0.0,
+        };
+        let dy = Vector3 {
+            x: 0.0,
+            y: h,
+            z: 0.0,
+        };
+        let dz = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: h,
+        };
+        let gradient = Vector3 {
+            x: self.sdf.distance(&sum(&point, &dx)) - self.sdf.distance(&difference(&point, &dx)),
+            y: self.sdf.distance(&sum(&point, &dy)) - self.sdf.distance(&difference(&point, &dy)),
+            z: self.sdf.distance(&sum(&point, &dz)) - self.sdf.distance(&difference(&point, &dz)),
+        };
+        return normalize(&gradient);
+    }
+}
+
+/// Estimates the distance to a Mandelbulb of a given power.
+fn mandelbulb_distance(p: &Vector3, power: f64, iterations: u32) -> f64 {
+    let mut z = *p;
+    let mut dr = 1.0;
+    let mut r = length(&z);
+
+    for _ in 0..iterations {
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+
+        // raise z to the given power in spherical coordinates:
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        let zr = r.powf(power);
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+
+        z = Vector3 {
+            x: zr * theta.sin() * phi.cos() + p.x,
+            y: zr * theta.sin() * phi.sin() + p.y,
+            z: zr * theta.cos() + p.z,
+        };
+        r = length(&z);
+    }
+
+    return if r == 0.0 { 0.0 } else { 0.5 * r.ln() * r / dr };
+}
+
+/// Maps a coordinate into the cell of a given period that is centered at the origin.
+fn repeat(x: f64, period: f64) -> f64 {
+    return if period > 0.0 {
+        x - period * (x / period).round()
+    } else {
+        x
+    };
+}
+
+fn clamp(x: f64, min: f64, max: f64) -> f64 {
+    return f64::min(f64::max(x, min), max);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    fn test_object(sdf: Sdf) -> SdfObject {
+        return SdfObject {
+            sdf,
+            max_steps: 128,
+            epsilon: 0.001,
+            material: Material::new(Color { r: 255, g: 0, b: 0 }, false),
+        };
+    }
+
+    #[test]
+    fn test_distances() {
+        let p = Vector3 {
+            x: 3.0,
+            y: 0.0,
+            z: 0.0,
+        };
+
+        let sphere = Sdf::Sphere { r: 1.0 };
+        assert_eq!(sphere.distance(&p), 2.0);
+        assert_eq!(sphere.distance(&ORIGIN), -1.0);
+
+        let rounded_box = Sdf::RoundedBox {
+            half_extents: Vector3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            radius: 0.5,
+        };
+        assert_eq!(rounded_box.distance(&p), 1.5);
+
+        let capsule = Sdf::Capsule {
+            a: Vector3 {
+                x: 0.0,
+                y: -2.0,
+                z: 0.0,
+            },
+            b: Vector3 {
+                x: 0.0,
+                y: 2.0,
+                z: 0.0,
+            },
+            r: 1.0,
+        };
+        assert_eq!(capsule.distance(&p), 2.0);
+
+        let repetition = Sdf::Repetition {
+            period: Vector3 {
+                x: 10.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            sdf: Box::new(Sdf::Sphere { r: 1.0 }),
+        };
+        let q = Vector3 {
+            x: 23.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        assert_eq!(repetition.distance(&q), 2.0);
+
+        // the Mandelbulb's center lies inside of it:
+        let mandelbulb = Sdf::Mandelbulb {
+            power: 8.0,
+            iterations: 10,
+        };
+        assert!(mandelbulb.distance(&p) > 1.0);
+        assert_eq!(mandelbulb.distance(&ORIGIN), 0.0);
+    }
+
+    #[test]
+    fn test_sphere_tracing() {
+        let object = test_object(Sdf::Translate {
+            offset: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 50.0,
+            },
+            sdf: Box::new(Sdf::SmoothUnion {
+                k: 2.0,
+                left: Box::new(Sdf::Sphere { r: 10.0 }),
+                right: Box::new(Sdf::Twist {
+                    rate: 0.1,
+                    sdf: Box::new(Sdf::Sphere { r: 5.0 }),
+                }),
+            }),
+        });
+
+        let r = Ray {
+            origin: ORIGIN,
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 2.0,
+            },
+            time: 0.0,
+        };
+        let k = object.intersect(&r);
+        assert!((k - 20.0).abs() < 0.001);
+
+        let p = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 40.0,
+        };
+        let n = object.compute_normal(&p);
+        assert!((n.z + 1.0).abs() < 0.000001);
+
+        let miss = Ray {
+            origin: ORIGIN,
+            direction: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+        };
+        assert_eq!(object.intersect(&miss), HUGE_VALUE);
+    }
+}
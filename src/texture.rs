@@ -0,0 +1,643 @@
+//! Textures and the mapping of surface points to texture coordinates.
+
+use crate::image_io::*;
+use crate::procedural_texture::*;
+use crate::ray_tracer::*;
+use crate::vector_arithmetic::*;
+use std::cmp;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+/// Texture coordinates, a texture covers [0.0, 1.0] in both directions (v = 0.0 is its top row).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Uv {
+    pub u: f64,
+    pub v: f64,
+}
+
+/// How texture coordinates outside of [0.0, 1.0] are mapped onto a texture.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+/// An image texture, its texels are stored row by row (top to bottom).
+#[derive(PartialEq)]
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<Color>,
+    pub wrap: WrapMode,
+}
+
+/// An image texture and its successively halved (box-filtered) versions, down to a single texel.
+#[derive(PartialEq)]
+pub struct MipMap {
+    pub levels: Vec<ImageTexture>,
+}
+
+/// The derivatives of the texture coordinates with respect to the pixel coordinates,
+/// i.e. the extent of a pixel in texture space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UvFootprint {
+    pub duv_dx: Uv,
+    pub duv_dy: Uv,
+}
+
+/// The footprint of an infinitely thin ray, sampling with it reads the full resolution image.
+pub const POINT_FOOTPRINT: UvFootprint = UvFootprint {
+    duv_dx: Uv { u: 0.0, v: 0.0 },
+    duv_dy: Uv { u: 0.0, v: 0.0 },
+};
+
+/// The footprint of a ray that grazes a surface, sampling with it reads the coarsest level of a mip map.
+pub const UNBOUNDED_FOOTPRINT: UvFootprint = UvFootprint {
+    duv_dx: Uv { u: 1e9, v: 1e9 },
+    duv_dy: Uv { u: 1e9, v: 1e9 },
+};
+
+/// Where a procedural pattern is evaluated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextureSpace {
+    /// At the surface point, as if the object was carved out of a solid block.
+    Solid,
+    /// At the surface's texture coordinates (u, v, 0.0).
+    Uv,
+}
+
+/// A texture that can be assigned to any of a material's slots.
+#[derive(Clone, PartialEq)]
+pub enum Texture {
+    /// A single color everywhere.
+    Constant(Color),
+    /// An image, its texture coordinates are scaled by uv_scale (e.g. to tile it on a plane).
+    Image { image: Rc<MipMap>, uv_scale: f64 },
+    /// A procedural pattern that blends from one texture (at 0.0) to another (at 1.0).
+    Pattern {
+        pattern: Pattern,
+        space: TextureSpace,
+        low: Box<Texture>,
+        high: Box<Texture>,
+    },
+}
+
+impl ImageTexture {
+    /// Decodes a given PNG or JPEG file into a texture.
+    pub fn decode(bytes: &[u8], wrap: WrapMode) -> Result<ImageTexture, String> {
+        let image = decode_image(&bytes)?;
+        let mut texels = Vec::with_capacity(image.width * image.height);
+        for pixel in image.samples.chunks_exact(image.channels) {
+            // grey images have one, images with an alpha channel have a second (or fourth) sample:
+            let (r, g, b) = if image.channels < 3 {
+                (pixel[0], pixel[0], pixel[0])
+            } else {
+                (pixel[0], pixel[1], pixel[2])
+            };
+            texels.push(Color {
+                r: (r * 255.0).round() as u8,
+                g: (g * 255.0).round() as u8,
+                b: (b * 255.0).round() as u8,
+            });
+        }
+
+        return Ok(ImageTexture {
+            width: image.width,
+            height: image.height,
+            texels,
+            wrap,
+        });
+    }
+
+    /// Samples the texture at given texture coordinates, interpolating bilinearly between the four closest texels.
+    pub fn sample(&self, uv: &Uv) -> Color {
+        // texel centers lie at half-integer positions:
+        let x = uv.u * self.width as f64 - 0.5;
+        let y = uv.v * self.height as f64 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let c00 = self.texel(x0 as i64, y0 as i64);
+        let c10 = self.texel(x0 as i64 + 1, y0 as i64);
+        let c01 = self.texel(x0 as i64, y0 as i64 + 1);
+        let c11 = self.texel(x0 as i64 + 1, y0 as i64 + 1);
+
+        let top = mix_colors(1.0 - fx, &c00, fx, &c10);
+        let bottom = mix_colors(1.0 - fx, &c01, fx, &c11);
+        return mix_colors(1.0 - fy, &top, fy, &bottom);
+    }
+
+    /// Looks up texel (x, y), wrapping coordinates that lie outside of the image.
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let wx = wrap_index(x, self.width as i64, self.wrap);
+        let wy = wrap_index(y, self.height as i64, self.wrap);
+        return self.texels[wy * self.width + wx];
+    }
+
+    /// Halves the texture's size, every texel is the average of (up to) four texels.
+    fn downsample(&self) -> ImageTexture {
+        let width = self.width / 2 + self.width % 2;
+        let height = self.height / 2 + self.height % 2;
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (mut r, mut g, mut b) = (0, 0, 0);
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    // odd sizes repeat their last column or row:
+                    let sx = cmp::min(2 * x + dx, self.width - 1);
+                    let sy = cmp::min(2 * y + dy, self.height - 1);
+                    let c = self.texels[sy * self.width + sx];
+                    r += c.r as u32;
+                    g += c.g as u32;
+                    b += c.b as u32;
+                }
+                texels.push(Color {
+                    r: ((r + 2) / 4) as u8,
+                    g: ((g + 2) / 4) as u8,
+                    b: ((b + 2) / 4) as u8,
+                });
+            }
+        }
+
+        return ImageTexture {
+            width,
+            height,
+            texels,
+            wrap: self.wrap,
+        };
+    }
+}
+
+impl MipMap {
+    /// Computes all levels of a given image's mip map.
+    pub fn new(image: ImageTexture) -> Result<MipMap, String> {
+        if image.width == 0 || image.height == 0 {
+            return Err(format!(
+                "A texture needs at least 1x1 texels, got {}x{}!",
+                image.width, image.height
+            ));
+        }
+        if image.texels.len() != image.width * image.height {
+            return Err(format!(
+                "Expected {} texels for a {}x{} texture, got {}!",
+                image.width * image.height,
+                image.width,
+                image.height,
+                image.texels.len()
+            ));
+        }
+        let mut levels = vec![image];
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+        return Ok(MipMap { levels });
+    }
+
+    /// Samples the mip map with a given footprint, interpolating (trilinearly) between the two
+    /// levels whose texels are closest to the footprint's size.
+    pub fn sample(&self, uv: &Uv, footprint: &UvFootprint) -> Color {
+        let base = &self.levels[0];
+        let (w, h) = (base.width as f64, base.height as f64);
+        let length_x = (footprint.duv_dx.u * w).hypot(footprint.duv_dx.v * h);
+        let length_y = (footprint.duv_dy.u * w).hypot(footprint.duv_dy.v * h);
+        let texels = f64::max(length_x, length_y);
+
+        let max_level = (self.levels.len() - 1) as f64;
+        let level = if texels > 1.0 {
+            f64::min(texels.log2(), max_level)
+        } else {
+            0.0
+        };
+        let lower = level.floor();
+        let t = level - lower;
+        let c = self.levels[lower as usize].sample(&uv);
+        return if t > 0.0 {
+            let d = self.levels[lower as usize + 1].sample(&uv);
+            mix_colors(1.0 - t, &c, t, &d)
+        } else {
+            c
+        };
+    }
+}
+
+impl Texture {
+    /// Samples the texture at a given surface point with given texture coordinates.
+    pub fn sample(&self, uv: &Uv, point: &Vector3) -> Color {
+        return self.sample_filtered(&uv, &point, &POINT_FOOTPRINT);
+    }
+
+    /// Samples the texture, images are filtered over a given footprint.
+    pub fn sample_filtered(&self, uv: &Uv, point: &Vector3, footprint: &UvFootprint) -> Color {
+        return match self {
+            Texture::Constant(color) => *color,
+            Texture::Image { image, uv_scale } => {
+                let scaled_uv = Uv {
+                    u: uv.u * uv_scale,
+                    v: uv.v * uv_scale,
+                };
+                let scaled_footprint = UvFootprint {
+                    duv_dx: Uv {
+                        u: footprint.duv_dx.u * uv_scale,
+                        v: footprint.duv_dx.v * uv_scale,
+                    },
+                    duv_dy: Uv {
+                        u: footprint.duv_dy.u * uv_scale,
+                        v: footprint.duv_dy.v * uv_scale,
+                    },
+                };
+                image.sample(&scaled_uv, &scaled_footprint)
+            }
+            Texture::Pattern {
+                pattern,
+                space,
+                low,
+                high,
+            } => {
+                let p = match space {
+                    TextureSpace::Solid => *point,
+                    TextureSpace::Uv => Vector3 {
+                        x: uv.u,
+                        y: uv.v,
+                        z: 0.0,
+                    },
+                };
+                let t = pattern.evaluate(&p);
+                mix_colors(
+                    1.0 - t,
+                    &low.sample_filtered(&uv, &point, &footprint),
+                    t,
+                    &high.sample_filtered(&uv, &point, &footprint),
+                )
+            }
+        };
+    }
+}
+
+fn wrap_index(i: i64, n: i64, wrap: WrapMode) -> usize {
+    let wrapped = match wrap {
+        WrapMode::Repeat => i.rem_euclid(n),
+        WrapMode::MirroredRepeat => {
+            let m = i.rem_euclid(2 * n);
+            if m < n {
+                m
+            } else {
+                2 * n - 1 - m
+            }
+        }
+        WrapMode::ClampToEdge => i64::min(i64::max(i, 0), n - 1),
+    };
+    return wrapped as usize;
+}
+
+/// Maps a direction (from a sphere's center) to latitude/longitude texture coordinates.
+pub fn spherical_uv(direction: &Vector3) -> Uv {
+    let d = normalize(&direction);
+    let y = f64::min(f64::max(d.y, -1.0), 1.0);
+    return Uv {
+        u: 0.5 + d.z.atan2(d.x) / (2.0 * PI),
+        v: y.acos() / PI,
+    };
+}
+
+/// Computes the derivatives of a point on a sphere (at a given direction from its center) with
+/// respect to its latitude/longitude texture coordinates.
+pub fn spherical_uv_derivatives(direction: &Vector3) -> (Vector3, Vector3) {
+    let d = direction;
+    let rho = (d.x * d.x + d.z * d.z).sqrt();
+    if rho == 0.0 {
+        // the poles have no well-defined longitude:
+        return tangent_basis(&normalize(&d));
+    }
+
+    let dpdu = Vector3 {
+        x: -2.0 * PI * d.z,
+        y: 0.0,
+        z: 2.0 * PI * d.x,
+    };
+    let dpdv = Vector3 {
+        x: PI * d.y * d.x / rho,
+        y: -PI * rho,
+        z: PI * d.y * d.z / rho,
+    };
+    return (dpdu, dpdv);
+}
+
+/// Computes two directions that are perpendicular to a given normal and to each other.
+pub fn tangent_basis(normal: &Vector3) -> (Vector3, Vector3) {
+    let helper = if normal.x.abs() < 0.9 {
+        Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    } else {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    };
+    let tangent = normalize(&cross_product(&helper, &normal));
+    let bitangent = cross_product(&normal, &tangent);
+    return (tangent, bitangent);
+}
+
+/// Projects a point onto the plane with a given normal, texture coordinates are in units of length.
+pub fn planar_uv(point: &Vector3, normal: &Vector3) -> Uv {
+    let (tangent, bitangent) = tangent_basis(&normal);
+    return Uv {
+        u: dot_product(&point, &tangent),
+        v: dot_product(&point, &bitangent),
+    };
+}
+
+/// Projects a point along the axis its normal is closest to, texture coordinates are in units of length.
+pub fn box_uv(point: &Vector3, normal: &Vector3) -> Uv {
+    let (x, y, z) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+    return if x >= y && x >= z {
+        Uv {
+            u: point.z,
+            v: point.y,
+        }
+    } else if y >= z {
+        Uv {
+            u: point.x,
+            v: point.z,
+        }
+    } else {
+        Uv {
+            u: point.x,
+            v: point.y,
+        }
+    };
+}
+
+/// Computes the footprint of a pixel whose surface points change by dpdx and dpdy (per pixel), given the
+/// derivatives of the surface points with respect to the texture coordinates.
+pub fn compute_uv_footprint(
+    dpdx: &Vector3,
+    dpdy: &Vector3,
+    dpdu: &Vector3,
+    dpdv: &Vector3,
+) -> UvFootprint {
+    // solve dp = du * dpdu + dv * dpdv in the least squares sense (the normal equations):
+    let uu = dot_product(&dpdu, &dpdu);
+    let uv = dot_product(&dpdu, &dpdv);
+    let vv = dot_product(&dpdv, &dpdv);
+    let det = uu * vv - uv * uv;
+    if det.abs() < 1e-12 {
+        return POINT_FOOTPRINT;
+    }
+
+    let solve = |dp: &Vector3| {
+        let a = dot_product(&dpdu, &dp);
+        let b = dot_product(&dpdv, &dp);
+        return Uv {
+            u: (vv * a - uv * b) / det,
+            v: (uu * b - uv * a) / det,
+        };
+    };
+    return UvFootprint {
+        duv_dx: solve(&dpdx),
+        duv_dy: solve(&dpdy),
+    };
+}
+
+/// Computes the derivatives of a point with respect to the texture coordinates of box_uv().
+pub fn box_uv_derivatives(normal: &Vector3) -> (Vector3, Vector3) {
+    let unit_x = Vector3 {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+    };
+    let unit_y = Vector3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+    let unit_z = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+
+    let (x, y, z) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+    return if x >= y && x >= z {
+        (unit_z, unit_y)
+    } else if y >= z {
+        (unit_x, unit_z)
+    } else {
+        (unit_x, unit_y)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+    const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    /// A 2x2 checkerboard.
+    fn test_texture(wrap: WrapMode) -> ImageTexture {
+        return ImageTexture {
+            width: 2,
+            height: 2,
+            texels: vec![BLACK, WHITE, WHITE, BLACK],
+            wrap,
+        };
+    }
+
+    #[test]
+    fn test_bilinear_sampling() {
+        let texture = test_texture(WrapMode::Repeat);
+
+        // texel centers:
+        assert_eq!(texture.sample(&Uv { u: 0.25, v: 0.25 }), BLACK);
+        assert_eq!(texture.sample(&Uv { u: 0.75, v: 0.25 }), WHITE);
+
+        // halfway between two texels:
+        let c = texture.sample(&Uv { u: 0.5, v: 0.25 });
+        assert_eq!(
+            c,
+            Color {
+                r: 128,
+                g: 128,
+                b: 128,
+            }
+        );
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        let uv = Uv { u: 1.25, v: 0.25 };
+        assert_eq!(test_texture(WrapMode::Repeat).sample(&uv), BLACK);
+        assert_eq!(test_texture(WrapMode::MirroredRepeat).sample(&uv), WHITE);
+        assert_eq!(test_texture(WrapMode::ClampToEdge).sample(&uv), WHITE);
+
+        assert_eq!(wrap_index(-1, 4, WrapMode::Repeat), 3);
+        assert_eq!(wrap_index(-1, 4, WrapMode::MirroredRepeat), 0);
+        assert_eq!(wrap_index(6, 4, WrapMode::MirroredRepeat), 1);
+        assert_eq!(wrap_index(9, 4, WrapMode::MirroredRepeat), 1);
+        assert_eq!(wrap_index(-1, 4, WrapMode::ClampToEdge), 0);
+    }
+
+    #[test]
+    fn test_mip_map() {
+        let mip_map = MipMap::new(ImageTexture {
+            width: 4,
+            height: 3,
+            texels: vec![
+                BLACK, WHITE, BLACK, WHITE, WHITE, BLACK, WHITE, BLACK, BLACK, WHITE, BLACK, WHITE,
+            ],
+            wrap: WrapMode::Repeat,
+        })
+        .unwrap();
+        let sizes: Vec<(usize, usize)> =
+            mip_map.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, vec![(4, 3), (2, 2), (1, 1)]);
+
+        // a tiny footprint reads the full resolution image, a large one its average:
+        let uv = Uv { u: 0.125, v: 0.5 };
+        assert_eq!(mip_map.sample(&uv, &POINT_FOOTPRINT), WHITE);
+        let large = UvFootprint {
+            duv_dx: Uv { u: 2.0, v: 0.0 },
+            duv_dy: Uv { u: 0.0, v: 2.0 },
+        };
+        let c = mip_map.sample(&uv, &large);
+        assert!(c.r > 100 && c.r < 155, "{:?}", c);
+        assert_eq!(
+            mip_map.sample(&uv, &UNBOUNDED_FOOTPRINT),
+            mip_map.levels[2].sample(&uv)
+        );
+
+        // ...and a footprint between two levels blends them:
+        let between = UvFootprint {
+            duv_dx: Uv {
+                u: 2.0_f64.powf(0.5) / 4.0,
+                v: 0.0,
+            },
+            duv_dy: POINT_FOOTPRINT.duv_dy,
+        };
+        let c = mip_map.sample(&uv, &between);
+        assert!(
+            c.r > mip_map.levels[1].sample(&uv).r && c.r < 255,
+            "{:?}",
+            c
+        );
+
+        let empty = |width, height| ImageTexture {
+            width,
+            height,
+            texels: vec![],
+            wrap: WrapMode::Repeat,
+        };
+        assert!(MipMap::new(empty(0, 4)).is_err());
+        assert!(MipMap::new(empty(4, 0)).is_err());
+        assert!(MipMap::new(empty(1, 1)).is_err());
+    }
+
+    #[test]
+    fn test_uv_footprint() {
+        let dpdu = Vector3 {
+            x: 2.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let dpdv = Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 1.0,
+        };
+        let dpdx = Vector3 {
+            x: 3.0,
+            y: 0.5,
+            z: 1.0,
+        };
+        let footprint = compute_uv_footprint(&dpdx, &dpdu, &dpdu, &dpdv);
+        // the part of dpdx that leaves the tangent plane is ignored:
+        assert!((footprint.duv_dx.u - 1.0).abs() < 0.000001);
+        assert!((footprint.duv_dx.v - 1.0).abs() < 0.000001);
+        assert!((footprint.duv_dy.u - 1.0).abs() < 0.000001);
+        assert!(footprint.duv_dy.v.abs() < 0.000001);
+    }
+
+    #[test]
+    fn test_composed_patterns() {
+        // a checkerboard of black and (white-black) stripes:
+        let texture = Texture::Pattern {
+            pattern: Pattern::Checker { size: 1.0 },
+            space: TextureSpace::Uv,
+            low: Box::new(Texture::Constant(BLACK)),
+            high: Box::new(Texture::Pattern {
+                pattern: Pattern::Stripes { width: 0.25 },
+                space: TextureSpace::Solid,
+                low: Box::new(Texture::Constant(WHITE)),
+                high: Box::new(Texture::Constant(BLACK)),
+            }),
+        };
+
+        let p = Vector3 {
+            x: 0.1,
+            y: 0.0,
+            z: 0.0,
+        };
+        assert_eq!(texture.sample(&Uv { u: 0.5, v: 0.5 }, &p), BLACK);
+        assert_eq!(texture.sample(&Uv { u: 1.5, v: 0.5 }, &p), WHITE);
+        let q = Vector3 { x: 0.3, ..p };
+        assert_eq!(texture.sample(&Uv { u: 1.5, v: 0.5 }, &q), BLACK);
+    }
+
+    #[test]
+    fn test_uv_mappings() {
+        let uv = spherical_uv(&Vector3 {
+            x: 0.0,
+            y: 2.0,
+            z: 0.0,
+        });
+        assert_eq!(uv.v, 0.0);
+        let uv = spherical_uv(&Vector3 {
+            x: -1.0,
+            y: 0.0,
+            z: 0.0,
+        });
+        assert_eq!(uv, Uv { u: 1.0, v: 0.5 });
+
+        // the derivatives of the spherical mapping span the sphere's tangent plane (facing outwards):
+        let d = Vector3 {
+            x: 3.0,
+            y: 4.0,
+            z: 0.0,
+        };
+        let (dpdu, dpdv) = spherical_uv_derivatives(&d);
+        assert_eq!(dot_product(&dpdu, &d), 0.0);
+        assert!(dot_product(&dpdv, &d).abs() < 0.000001);
+        assert!(dot_product(&cross_product(&dpdu, &dpdv), &d) > 0.0);
+
+        let p = Vector3 {
+            x: 3.0,
+            y: 60.0,
+            z: 4.0,
+        };
+        let n = Vector3 {
+            x: 0.0,
+            y: -1.0,
+            z: 0.0,
+        };
+        let uv = planar_uv(&p, &n);
+        assert_eq!(uv.u.abs() + uv.v.abs(), 7.0);
+        assert_eq!(box_uv(&p, &n), Uv { u: 3.0, v: 4.0 });
+    }
+}
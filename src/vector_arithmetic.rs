@@ -8,6 +8,12 @@ pub struct Vector3 {
     pub z: f64,
 }
 
+pub const ZERO_VECTOR3: Vector3 = Vector3 {
+    x: 0.0,
+    y: 0.0,
+    z: 0.0,
+};
+
 /// Scale a vector v by a scalar a.
 pub fn scale_vector(a: f64, v: &Vector3) -> Vector3 {
     return Vector3 {
@@ -59,6 +65,25 @@ pub fn normalize(v: &Vector3) -> Vector3 {
     };
 }
 
+/// Normalizes a given vector, returns None if the vector has no finite, non-zero length.
+pub fn try_normalize(v: &Vector3) -> Option<Vector3> {
+    let l = length(v);
+    return if l > 0.0 && l.is_finite() {
+        Some(Vector3 {
+            x: v.x / l,
+            y: v.y / l,
+            z: v.z / l,
+        })
+    } else {
+        None
+    };
+}
+
+/// Checks whether all components of a given vector are finite (neither NaN nor infinite).
+pub fn is_finite(v: &Vector3) -> bool {
+    return v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+}
+
 /// Computes the length of a given vector.
 pub fn length(v: &Vector3) -> f64 {
     let s = v.x * v.x + v.y * v.y + v.z * v.z;
@@ -190,6 +215,61 @@ mod tests {
         assert_eq!(w, e);
     }
 
+    #[test]
+    fn test_try_normalizing() {
+        let v = Vector3 {
+            x: 4.0,
+            y: 5.0,
+            z: 6.0,
+        };
+        assert_eq!(try_normalize(&v), Some(normalize(&v)));
+
+        let zero = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        assert_eq!(try_normalize(&zero), None);
+
+        let nan = Vector3 {
+            x: f64::NAN,
+            y: 1.0,
+            z: 0.0,
+        };
+        assert_eq!(try_normalize(&nan), None);
+
+        let inf = Vector3 {
+            x: f64::INFINITY,
+            y: 1.0,
+            z: 0.0,
+        };
+        assert_eq!(try_normalize(&inf), None);
+    }
+
+    #[test]
+    fn test_is_finite() {
+        let v = Vector3 {
+            x: 4.0,
+            y: 5.0,
+            z: 6.0,
+        };
+        assert!(is_finite(&v));
+
+        let w = Vector3 {
+            x: 4.0,
+            y: f64::NEG_INFINITY,
+            z: 6.0,
+        };
+        assert!(!is_finite(&w));
+
+        let u = Vector3 {
+            x: 4.0,
+            y: 5.0,
+            z: f64::NAN,
+        };
+        assert!(!is_finite(&u));
+    }
+
     #[test]
     fn test_length() {
         let v = Vector3 {
@@ -0,0 +1,547 @@
+//! An interactive viewer for the web frontend: the mouse orbits, pans and zooms the camera, while it moves
+//! a coarse preview is rendered, which the frontend refines once the mouse rests.
+
+use crate::camera::*;
+use crate::material::*;
+use crate::ray_tracer::*;
+use crate::scene_edit::*;
+use crate::vector_arithmetic::*;
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::HtmlCanvasElement;
+
+/// The camera stops this far (in radians) short of looking straight up or down.
+const MAX_PITCH: f64 = 0.5 * PI - 0.01;
+
+/// The preview renders one pixel for each block of this many by this many canvas pixels.
+const PREVIEW_SCALE: u32 = 6;
+
+/// How much the distance to the target changes per pixel that the mouse wheel scrolls.
+const ZOOM_PER_PIXEL: f64 = 0.001;
+
+/// Places a camera on a sphere around a target point that it looks at: it orbits the target around the
+/// camera's original up axis, pans in its image plane and zooms towards the target.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OrbitControls {
+    pub target: Vector3,
+    pub distance: f64,
+    /// The angle (in radians) around the up axis, starting from the forward axis.
+    pub yaw: f64,
+    /// The angle (in radians) above the target (negative: below it).
+    pub pitch: f64,
+    /// The axis that the camera orbits.
+    pub up: Vector3,
+    /// The horizontal direction the camera looks in at yaw 0.0 (perpendicular to up).
+    pub forward: Vector3,
+}
+
+impl OrbitControls {
+    /// Orbits a given target, starting where a given camera is.
+    pub fn new(camera: &Camera, target: &Vector3) -> OrbitControls {
+        let up = try_normalize(&camera.up).unwrap_or(camera.up);
+        let offset = difference(&target, &camera.pos);
+        let horizontal = |v: &Vector3| difference(&v, &scale_vector(dot_product(&v, &up), &up));
+        let forward = try_normalize(&horizontal(&offset))
+            .or_else(|| try_normalize(&horizontal(&camera.direction)))
+            .unwrap_or(camera.direction);
+        let distance = length(&offset);
+        let pitch = match try_normalize(&offset) {
+            Some(d) => -dot_product(&d, &up).clamp(-1.0, 1.0).asin(),
+            None => 0.0,
+        };
+
+        return OrbitControls {
+            target: *target,
+            distance,
+            yaw: 0.0,
+            pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH),
+            up,
+            forward,
+        };
+    }
+
+    /// The (unit) direction the camera looks in.
+    pub fn direction(&self) -> Vector3 {
+        let right = cross_product(&self.forward, &self.up);
+        let horizontal = sum(
+            &scale_vector(self.yaw.cos(), &self.forward),
+            &scale_vector(self.yaw.sin(), &right),
+        );
+        return sum(
+            &scale_vector(self.pitch.cos(), &horizontal),
+            &scale_vector(-self.pitch.sin(), &self.up),
+        );
+    }
+
+    /// Moves and turns a given camera to look at the target.
+    pub fn apply(&self, camera: &mut Camera) {
+        let direction = self.direction();
+        camera.pos = difference(&self.target, &scale_vector(self.distance, &direction));
+        camera.direction = direction;
+        camera.up = normalize(&difference(
+            &self.up,
+            &scale_vector(dot_product(&self.up, &direction), &direction),
+        ));
+    }
+
+    /// Orbits the target as if its scene was dragged along by a given distance (in image widths, with y
+    /// up): dragging across the whole image turns it half around.
+    pub fn orbit(&mut self, dx: f64, dy: f64) {
+        self.yaw += PI * dx;
+        self.pitch = (self.pitch - PI * dy).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    /// Moves the camera and the target as if the scene was dragged along by a given distance (in image
+    /// widths, with y up), so that the target follows the mouse.
+    pub fn pan(&mut self, dx: f64, dy: f64, camera: &Camera) {
+        let view_width = match camera.projection {
+            Projection::Perspective { fov } => 2.0 * self.distance * (0.5 * fov).tan(),
+            Projection::Orthographic { width } => width,
+            _ => self.distance,
+        };
+        let direction = self.direction();
+        let right = cross_product(&direction, &self.up);
+        let camera_up = cross_product(&right, &direction);
+        let shift = sum(&scale_vector(dx, &right), &scale_vector(dy, &camera_up));
+        self.target = difference(&self.target, &scale_vector(view_width, &shift));
+    }
+
+    /// Moves the camera towards (negative amounts) or away from the target, by a factor of e^amount.
+    pub fn zoom(&mut self, amount: f64) {
+        self.distance *= amount.exp();
+    }
+}
+
+/// What moving the mouse with a pressed button does.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Drag {
+    Orbit,
+    Pan,
+}
+
+/// Shows the test scene on a canvas, moves its camera with the mouse and edits its objects.
+#[wasm_bindgen]
+pub struct Viewer {
+    renderer: IncrementalRenderer,
+    canvas: HtmlCanvasElement,
+    controls: OrbitControls,
+    drag: Option<Drag>,
+    /// The mouse position (in CSS pixels) when it last moved.
+    mouse: (f64, f64),
+}
+
+#[wasm_bindgen]
+impl Viewer {
+    /// Creates a viewer on the canvas with a given ID, which orbits the middle of the test scene.
+    #[wasm_bindgen(constructor)]
+    pub fn new(canvas_id: &str) -> Result<Viewer, JsValue> {
+        let canvas = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(canvas_id))
+            .ok_or_else(|| JsValue::from_str(&format!("There is no element {}!", canvas_id)))?
+            .dyn_into::<HtmlCanvasElement>()
+            .map_err(|_| JsValue::from_str(&format!("{} is not a canvas!", canvas_id)))?;
+        let scene = crate::create_test_scene();
+        let target = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 250.0,
+        };
+        let controls = OrbitControls::new(&scene.camera, &target);
+        let renderer = IncrementalRenderer::new(scene, canvas.width(), canvas.height());
+
+        return Ok(Viewer {
+            renderer,
+            canvas,
+            controls,
+            drag: None,
+            mouse: (0.0, 0.0),
+        });
+    }
+
+    /// Starts dragging: the left mouse button orbits, the middle or right button (or the left one with shift
+    /// held down) pans.
+    pub fn mouse_down(&mut self, x: f64, y: f64, button: i16, shift_key: bool) {
+        self.drag = if button == 0 && !shift_key {
+            Some(Drag::Orbit)
+        } else {
+            Some(Drag::Pan)
+        };
+        self.mouse = (x, y);
+    }
+
+    /// Moves the camera while dragging, and renders a preview. Returns whether the camera moved.
+    pub fn mouse_move(&mut self, x: f64, y: f64) -> Result<bool, JsValue> {
+        let drag = match self.drag {
+            Some(drag) => drag,
+            None => return Ok(false),
+        };
+        // (the mouse's y axis points down, and the canvas may be scaled on the page:)
+        let client_width = match self.canvas.client_width() {
+            w if w > 0 => w as f64,
+            _ => self.canvas.width() as f64,
+        };
+        let dx = (x - self.mouse.0) / client_width;
+        let dy = (self.mouse.1 - y) / client_width;
+        self.mouse = (x, y);
+
+        match drag {
+            Drag::Orbit => self.controls.orbit(dx, dy),
+            Drag::Pan => self.controls.pan(dx, dy, &self.renderer.scene().camera),
+        }
+        self.render_preview()?;
+        return Ok(true);
+    }
+
+    /// Stops dragging.
+    pub fn mouse_up(&mut self) {
+        self.drag = None;
+    }
+
+    /// Zooms by the distance (in pixels) that the mouse wheel scrolled, and renders a preview.
+    pub fn wheel(&mut self, delta_y: f64) -> Result<(), JsValue> {
+        self.controls.zoom(ZOOM_PER_PIXEL * delta_y);
+        return self.render_preview();
+    }
+
+    /// Quickly renders a coarse preview: fewer pixels, reflections and samples.
+    pub fn render_preview(&mut self) -> Result<(), JsValue> {
+        self.update_camera()?;
+        let (width, height) = (self.canvas.width(), self.canvas.height());
+        let preview_width = (width as f64 / PREVIEW_SCALE as f64).ceil() as u32;
+        let preview_height = (height as f64 / PREVIEW_SCALE as f64).ceil() as u32;
+
+        let scene = self.renderer.scene();
+        let (settings, camera) = (
+            preview_settings(&scene.settings),
+            preview_camera(&scene.camera),
+        );
+        let (rgba, _) =
+            self.renderer
+                .render_with(&settings, &camera, preview_width, preview_height);
+
+        let rgba = upscale(&rgba, preview_width, PREVIEW_SCALE, width, height);
+        return draw_rgba(&self.canvas, rgba, width, height);
+    }
+
+    /// Finds the object at a given position (in CSS pixels) on the canvas. Returns an object with its
+    /// type (e.g. "sphere") and ID (its index among the scene's objects of that type), the position and
+    /// normal where its surface is hit, and the distance to the camera, or null if there is no object.
+    pub fn pick(&mut self, x: f64, y: f64) -> Result<JsValue, JsValue> {
+        self.update_camera()?;
+        let (width, height) = (self.canvas.width(), self.canvas.height());
+        let scale = match (self.canvas.client_width(), self.canvas.client_height()) {
+            (w, h) if w > 0 && h > 0 => (width as f64 / w as f64, height as f64 / h as f64),
+            _ => (1.0, 1.0),
+        };
+        let (px, py) = ((x * scale.0).floor(), (y * scale.1).floor());
+        if px < 0.0 || py < 0.0 || px >= width as f64 || py >= height as f64 {
+            return Ok(JsValue::NULL);
+        }
+        let picked = match pick(self.renderer.scene(), px as u32, py as u32, width, height) {
+            Some(picked) => picked,
+            None => return Ok(JsValue::NULL),
+        };
+
+        let vector = |v: &Vector3| {
+            return js_sys::Array::of3(&v.x.into(), &v.y.into(), &v.z.into());
+        };
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"type".into(), &picked.object.kind.name().into())?;
+        js_sys::Reflect::set(&result, &"id".into(), &(picked.object.index as u32).into())?;
+        js_sys::Reflect::set(&result, &"position".into(), &vector(&picked.point))?;
+        js_sys::Reflect::set(&result, &"normal".into(), &vector(&picked.normal))?;
+        js_sys::Reflect::set(&result, &"distance".into(), &picked.distance.into())?;
+        return Ok(result.into());
+    }
+
+    /// Renders the scene in full quality, redoing only what the camera moves and edits since the last
+    /// render changed.
+    pub fn render(&mut self) -> Result<(), JsValue> {
+        self.update_camera()?;
+        let (width, height) = (self.canvas.width(), self.canvas.height());
+        self.renderer.resize(width, height);
+        let (rgba, _) = self.renderer.render();
+        return draw_rgba(&self.canvas, rgba, width, height);
+    }
+
+    /// Sets the color of the object with a given type (e.g. "sphere") and ID.
+    pub fn set_color(
+        &mut self,
+        object_type: &str,
+        id: u32,
+        r: u8,
+        g: u8,
+        b: u8,
+    ) -> Result<(), JsValue> {
+        let object = object_id(object_type, id)?;
+        let mut material = object_material(self.renderer.scene(), &object)
+            .ok_or_else(|| JsValue::from_str(&format!("The scene has no {} {}!", object_type, id)))?
+            .clone();
+        material.color = Color { r, g, b };
+        return self.edit(SceneEdit::SetMaterial(object, material));
+    }
+
+    /// Moves the (center of the) sphere or cuboid with a given type and ID to a given position.
+    pub fn move_object(
+        &mut self,
+        object_type: &str,
+        id: u32,
+        x: f64,
+        y: f64,
+        z: f64,
+    ) -> Result<(), JsValue> {
+        let object = object_id(object_type, id)?;
+        let pos = Vector3 { x, y, z };
+        let scene = self.renderer.scene();
+        let moved = match object.kind {
+            ObjectKind::Sphere => scene.spheres.get(object.index).map(|sphere| {
+                return SceneObject::Sphere(Sphere {
+                    pos,
+                    ..sphere.clone()
+                });
+            }),
+            ObjectKind::Cuboid => scene.cuboids.get(object.index).map(|cuboid| {
+                let center = scale_vector(0.5, &sum(&cuboid.min, &cuboid.max));
+                let offset = difference(&pos, &center);
+                return SceneObject::Cuboid(Cuboid {
+                    min: sum(&cuboid.min, &offset),
+                    max: sum(&cuboid.max, &offset),
+                    ..cuboid.clone()
+                });
+            }),
+            _ => {
+                return Err(JsValue::from_str(&format!(
+                    "Cannot move a {}!",
+                    object_type
+                )))
+            }
+        }
+        .ok_or_else(|| JsValue::from_str(&format!("The scene has no {} {}!", object_type, id)))?;
+        return self.edit(SceneEdit::UpdateObject(object, moved));
+    }
+
+    /// Removes the object with a given type and ID, the following objects of that type move up by one ID.
+    pub fn remove_object(&mut self, object_type: &str, id: u32) -> Result<(), JsValue> {
+        return self.edit(SceneEdit::RemoveObject(object_id(object_type, id)?));
+    }
+
+    /// Adds a white, matte sphere, returns its ID.
+    pub fn add_sphere(&mut self, x: f64, y: f64, z: f64, radius: f64) -> Result<u32, JsValue> {
+        let sphere = Sphere {
+            pos: Vector3 { x, y, z },
+            r: radius,
+            velocity: ZERO_VECTOR3,
+            material: Material::new(
+                Color {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+                false,
+            ),
+        };
+        self.edit(SceneEdit::AddObject(SceneObject::Sphere(sphere)))?;
+        return Ok(object_count(self.renderer.scene(), ObjectKind::Sphere) as u32 - 1);
+    }
+
+    /// Moves the light to a given position.
+    pub fn set_light(&mut self, x: f64, y: f64, z: f64) -> Result<(), JsValue> {
+        let light = Light {
+            pos: Vector3 { x, y, z },
+        };
+        return self.edit(SceneEdit::SetLight(light));
+    }
+}
+
+impl Viewer {
+    /// Moves the scene's camera to where the orbit controls place it.
+    fn update_camera(&mut self) -> Result<(), JsValue> {
+        let mut camera = self.renderer.scene().camera;
+        self.controls.apply(&mut camera);
+        return self.edit(SceneEdit::SetCamera(camera));
+    }
+
+    fn edit(&mut self, edit: SceneEdit) -> Result<(), JsValue> {
+        return self
+            .renderer
+            .edit(edit)
+            .map(|_| ())
+            .map_err(|e| JsValue::from_str(&e));
+    }
+}
+
+/// The ID of an object with a given type (e.g. "sphere") and index.
+fn object_id(object_type: &str, id: u32) -> Result<ObjectId, JsValue> {
+    let kind = ObjectKind::from_name(object_type)
+        .ok_or_else(|| JsValue::from_str(&format!("Unknown object type: {}", object_type)))?;
+    return Ok(ObjectId {
+        kind,
+        index: id as usize,
+    });
+}
+
+/// Render settings for a preview: a single reflection, and at most one ambient occlusion sample.
+fn preview_settings(settings: &RenderSettings) -> RenderSettings {
+    return RenderSettings {
+        max_depth: settings.max_depth.min(1),
+        max_reflection_depth: settings.max_reflection_depth.min(1),
+        ambient_occlusion_samples: settings.ambient_occlusion_samples.min(1),
+        ..*settings
+    };
+}
+
+/// A camera for a preview, which takes a single sample per pixel.
+fn preview_camera(camera: &Camera) -> Camera {
+    let mut camera = *camera;
+    if let Some(lens) = &mut camera.lens {
+        lens.samples = 1;
+    }
+    if let Some(shutter) = &mut camera.shutter {
+        shutter.samples = 1;
+    }
+    return camera;
+}
+
+/// Enlarges a given RGBA image by repeating each pixel scale times in both directions, and crops it
+/// to a given size.
+fn upscale(rgba: &[u8], width: u32, scale: u32, target_width: u32, target_height: u32) -> Vec<u8> {
+    let mut enlarged = Vec::with_capacity((target_width * target_height * 4) as usize);
+    for y in 0..target_height {
+        for x in 0..target_width {
+            let i = (((y / scale) * width + x / scale) * 4) as usize;
+            enlarged.extend_from_slice(&rgba[i..i + 4]);
+        }
+    }
+    return enlarged;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(u: &Vector3, v: &Vector3) {
+        assert!(distance(&u, &v) < 0.000001, "{:?} != {:?}", u, v);
+    }
+
+    #[test]
+    fn test_orbit_controls() {
+        let mut camera = Camera::default();
+        let target = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 100.0,
+        };
+        let mut controls = OrbitControls::new(&camera, &target);
+        assert_eq!(
+            (controls.distance, controls.yaw, controls.pitch),
+            (100.0, 0.0, 0.0)
+        );
+        controls.apply(&mut camera);
+        assert_eq!(camera, Camera::default());
+
+        // dragging the scene to the right by half the image moves the camera to its left side:
+        controls.orbit(0.5, 0.0);
+        controls.apply(&mut camera);
+        // (the camera's right is -x:)
+        let left = Vector3 {
+            x: 100.0,
+            y: 0.0,
+            z: 100.0,
+        };
+        assert_close(&camera.pos, &left);
+        assert_close(
+            &camera.direction,
+            &Vector3 {
+                x: -1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        );
+        assert_close(
+            &camera.up,
+            &Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        );
+
+        // ...dragging it down moves the camera above the target, but never right above it:
+        controls.orbit(0.0, -2.0);
+        controls.apply(&mut camera);
+        assert_eq!(controls.pitch, MAX_PITCH);
+        assert!(camera.pos.y > 99.0);
+        assert!(dot_product(&camera.direction, &camera.up).abs() < 0.000001);
+        assert_close(
+            &camera.pos,
+            &difference(&target, &scale_vector(100.0, &camera.direction)),
+        );
+
+        // zooming keeps the direction:
+        let direction = camera.direction;
+        controls.zoom(-(2.0f64.ln()));
+        controls.apply(&mut camera);
+        assert!((controls.distance - 50.0).abs() < 0.000001);
+        assert_close(&camera.direction, &direction);
+    }
+
+    #[test]
+    fn test_pan() {
+        let mut camera = Camera::default();
+        let target = Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 100.0,
+        };
+        let mut controls = OrbitControls::new(&camera, &target);
+        // (the default camera sees 100 units wide at a distance of 100:)
+        controls.pan(0.25, -0.5, &camera);
+        controls.apply(&mut camera);
+        let shifted = Vector3 {
+            x: 25.0,
+            y: 50.0,
+            z: 0.0,
+        };
+        assert_close(&camera.pos, &shifted);
+        assert_close(
+            &camera.direction,
+            &Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+        );
+        // ...so the point that was at the image center is now where the mouse is:
+        let r = camera.primary_ray(&Point { x: 0.25, y: -0.5 }).unwrap();
+        let k = (target.z - r.origin.z) / r.direction.z;
+        assert_close(&sum(&r.origin, &scale_vector(k, &r.direction)), &target);
+    }
+
+    #[test]
+    fn test_preview() {
+        let camera = Camera {
+            lens: Some(ThinLens {
+                aperture_radius: 1.0,
+                focal_distance: 10.0,
+                aperture: Aperture::Circle,
+                samples: 16,
+            }),
+            ..Camera::default()
+        };
+        assert_eq!(preview_camera(&camera).samples(), 1);
+        let settings = preview_settings(&RenderSettings::default());
+        assert_eq!(settings.max_depth, 1);
+        assert_eq!(settings.ambient_occlusion_samples, 1);
+
+        // a 2x2 image, enlarged three times and cropped to 5x4:
+        let rgba: Vec<u8> = (0..4).flat_map(|i| vec![i, i, i, 255]).collect();
+        let enlarged = upscale(&rgba, 2, 3, 5, 4);
+        let grey: Vec<u8> = enlarged.chunks(4).map(|p| p[0]).collect();
+        assert_eq!(
+            grey,
+            vec![0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 2, 2, 2, 3, 3]
+        );
+    }
+}
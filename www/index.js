@@ -1,9 +1,91 @@
 import * as wasm from "lazor";
 
+let frame = 0;
+
 function render() {
     console.debug("Calling lazor.render_scene() ...");
     // TODO: provide the canvas' ID to render_scene(): 'result_canvas'
     wasm.render_scene();
 }
 
+function renderNextFrame() {
+    console.debug("Calling lazor.render_frame(" + frame + ") ...");
+    wasm.render_frame(frame);
+    frame += 1;
+}
+
+function downloadAnimation(format, mimeType, fileName) {
+    console.debug("Calling lazor.render_animation('" + format + "') ...");
+    const bytes = wasm.render_animation(format, 300, 300);
+    const url = URL.createObjectURL(new Blob([bytes], { type: mimeType }));
+    const link = document.createElement('a');
+    link.href = url;
+    link.download = fileName;
+    link.click();
+    URL.revokeObjectURL(url);
+}
+
+// the viewer (on its own canvas, which the buttons above don't draw on) renders a coarse preview while
+// the camera moves, and refines it once the mouse rests:
+const canvas = document.getElementById('viewer_canvas');
+const viewer = new wasm.Viewer('viewer_canvas');
+const refineDelay = 300;
+let refineTimeout = null;
+
+function refineLater() {
+    clearTimeout(refineTimeout);
+    refineTimeout = setTimeout(() => viewer.render(), refineDelay);
+}
+
+viewer.render_preview();
+refineLater();
+
+canvas.addEventListener("mousedown", event => {
+    viewer.mouse_down(event.offsetX, event.offsetY, event.button, event.shiftKey);
+    clearTimeout(refineTimeout);
+});
+canvas.addEventListener("mousemove", event => {
+    if (viewer.mouse_move(event.offsetX, event.offsetY)) {
+        refineLater();
+    }
+});
+window.addEventListener("mouseup", () => viewer.mouse_up());
+canvas.addEventListener("wheel", event => {
+    event.preventDefault();
+    viewer.wheel(event.deltaY);
+    refineLater();
+});
+canvas.addEventListener("contextmenu", event => event.preventDefault());
+// the picked object can be recolored or removed, which only re-renders what the edit changed:
+const colorInput = document.getElementById('color_input');
+const removeButton = document.getElementById('remove_button');
+let picked = null;
+
+canvas.addEventListener("dblclick", event => {
+    picked = viewer.pick(event.offsetX, event.offsetY);
+    document.getElementById('pick_info').textContent = picked
+        ? `${picked.type} #${picked.id} at ${picked.position.map(c => c.toFixed(1)).join(", ")}, `
+          + `${picked.distance.toFixed(1)} units away`
+        : "Nothing there.";
+    colorInput.disabled = removeButton.disabled = !picked;
+});
+colorInput.addEventListener("change", () => {
+    const hex = colorInput.value;
+    const [r, g, b] = [1, 3, 5].map(i => parseInt(hex.substr(i, 2), 16));
+    viewer.set_color(picked.type, picked.id, r, g, b);
+    viewer.render();
+});
+removeButton.addEventListener("click", () => {
+    viewer.remove_object(picked.type, picked.id);
+    picked = null;
+    colorInput.disabled = removeButton.disabled = true;
+    document.getElementById('pick_info').textContent = "Double-click an object to pick it.";
+    viewer.render();
+});
+
 document.getElementById('render_button').addEventListener("click", render);
+document.getElementById('next_frame_button').addEventListener("click", renderNextFrame);
+document.getElementById('gif_button').addEventListener("click",
+    () => downloadAnimation("gif", "image/gif", "animation.gif"));
+document.getElementById('apng_button').addEventListener("click",
+    () => downloadAnimation("apng", "image/apng", "animation.png"));